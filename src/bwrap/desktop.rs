@@ -0,0 +1,329 @@
+// Copyright (C) 2025 Pierre Le Gall
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use super::{BindMode, BuildWarning, Mount};
+use crate::config::{Dbus, Entry};
+
+/// X11 sockets directory
+const X11_SOCKETS_DIR: &str = "/tmp/.X11-unix";
+
+/// System bus socket
+const DBUS_SYSTEM_SOCKET: &str = "/run/dbus/system_bus_socket";
+
+/// Audio sockets, relative to `$XDG_RUNTIME_DIR`
+const AUDIO_SOCKETS: &[&str] = &["pulse", "pipewire-0"];
+
+/// Mounts and environment variables implied by the desktop fields of an entry
+#[derive(Debug, Default, PartialEq)]
+pub struct DesktopAccess {
+    /// Sockets mounted read-only, skipped by bwrap when missing
+    pub mounts: Vec<Mount>,
+    /// Host variables passed to the sandbox
+    pub env: Vec<(String, String)>,
+    /// Host variables hidden from the sandbox
+    pub unset_env: Vec<String>,
+    pub warnings: Vec<BuildWarning>,
+}
+
+impl DesktopAccess {
+    /// Expand the desktop fields of an entry, reading host variables with `var`
+    pub fn from_entry(config: &Entry, var: impl Fn(&str) -> Option<String>) -> Self {
+        let mut access = DesktopAccess::default();
+
+        if config.gui {
+            access.add_gui(&var);
+        }
+        if config.audio {
+            access.add_audio(&var);
+        }
+        match config.dbus {
+            None => {}
+            Some(Dbus::None) => access.hide_dbus_session(config, &var),
+            Some(Dbus::Session) => access.add_dbus_session(&var),
+            Some(Dbus::System) => access.add_dbus_system(&var),
+        }
+
+        access
+    }
+
+    /// Display server sockets: Wayland and/or X11, depending on which is set
+    fn add_gui(&mut self, var: &impl Fn(&str) -> Option<String>) {
+        if let Some(display) = var("WAYLAND_DISPLAY") {
+            let socket = match display.starts_with('/') {
+                true => Some(display.clone()),
+                false => var("XDG_RUNTIME_DIR").map(|dir| format!("{}/{}", dir, display)),
+            };
+            if let Some(socket) = socket {
+                self.add_mount(&socket);
+                self.pass_env("WAYLAND_DISPLAY", var);
+                self.pass_env("XDG_RUNTIME_DIR", var);
+            }
+        }
+
+        if var("DISPLAY").is_some() {
+            self.add_mount(X11_SOCKETS_DIR);
+            self.pass_env("DISPLAY", var);
+            if let Some(xauthority) = var("XAUTHORITY") {
+                self.add_mount(&xauthority);
+                self.pass_env("XAUTHORITY", var);
+            }
+        }
+    }
+
+    /// PulseAudio and PipeWire sockets, skipped by bwrap when missing
+    fn add_audio(&mut self, var: &impl Fn(&str) -> Option<String>) {
+        if let Some(dir) = var("XDG_RUNTIME_DIR") {
+            for socket in AUDIO_SOCKETS {
+                self.add_mount(&format!("{}/{}", dir, socket));
+            }
+            self.pass_env("XDG_RUNTIME_DIR", var);
+        }
+        self.pass_env("PULSE_SERVER", var);
+    }
+
+    /// Session bus socket, from a `unix:path=` address
+    /// Abstract sockets can't be bound, so they are left out
+    fn add_dbus_session(&mut self, var: &impl Fn(&str) -> Option<String>) {
+        let Some(address) = var("DBUS_SESSION_BUS_ADDRESS") else {
+            return;
+        };
+        let socket = address.strip_prefix("unix:").and_then(|params| {
+            params
+                .split(',')
+                .find_map(|param| param.strip_prefix("path="))
+        });
+        if let Some(socket) = socket {
+            self.add_mount(socket);
+            self.pass_env("DBUS_SESSION_BUS_ADDRESS", var);
+        }
+    }
+
+    /// Keep the session bus address out, the bus stays reachable through an
+    /// abstract socket when the network namespace is shared
+    fn hide_dbus_session(&mut self, config: &Entry, var: &impl Fn(&str) -> Option<String>) {
+        self.unset_env.push("DBUS_SESSION_BUS_ADDRESS".to_string());
+
+        let (shared, _) = config.effective_namespaces();
+        if shared.contains(&"network") && var("DBUS_SESSION_BUS_ADDRESS").is_some() {
+            self.warnings.push(BuildWarning::SharedNetworkDbus);
+        }
+    }
+
+    /// System bus socket, at its well-known location
+    fn add_dbus_system(&mut self, var: &impl Fn(&str) -> Option<String>) {
+        self.add_mount(DBUS_SYSTEM_SOCKET);
+        self.pass_env("DBUS_SYSTEM_BUS_ADDRESS", var);
+    }
+
+    fn add_mount(&mut self, path: &str) {
+        self.mounts
+            .push(Mount::same_path(BindMode::ReadOnly, true, path));
+    }
+
+    fn pass_env(&mut self, key: &str, var: &impl Fn(&str) -> Option<String>) {
+        if let Some(value) = var(key)
+            && !self.env.iter().any(|(existing, _)| existing == key)
+        {
+            self.env.push((key.to_string(), value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    fn socket(path: &str) -> Mount {
+        Mount::same_path(BindMode::ReadOnly, true, path)
+    }
+
+    fn gui_entry() -> Entry {
+        Entry {
+            gui: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_gui_x11() {
+        let access = DesktopAccess::from_entry(
+            &gui_entry(),
+            lookup(&[("DISPLAY", ":0"), ("XAUTHORITY", "/home/me/.Xauthority")]),
+        );
+
+        assert_eq!(
+            access.mounts,
+            vec![socket(X11_SOCKETS_DIR), socket("/home/me/.Xauthority"),]
+        );
+        assert_eq!(
+            access.env,
+            vec![
+                ("DISPLAY".to_string(), ":0".to_string()),
+                ("XAUTHORITY".to_string(), "/home/me/.Xauthority".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gui_wayland() {
+        let access = DesktopAccess::from_entry(
+            &gui_entry(),
+            lookup(&[
+                ("WAYLAND_DISPLAY", "wayland-0"),
+                ("XDG_RUNTIME_DIR", "/run/user/1000"),
+            ]),
+        );
+
+        assert_eq!(access.mounts, vec![socket("/run/user/1000/wayland-0")]);
+        assert_eq!(
+            access.env,
+            vec![
+                ("WAYLAND_DISPLAY".to_string(), "wayland-0".to_string()),
+                ("XDG_RUNTIME_DIR".to_string(), "/run/user/1000".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_audio() {
+        let entry = Entry {
+            audio: true,
+            ..Default::default()
+        };
+        let access =
+            DesktopAccess::from_entry(&entry, lookup(&[("XDG_RUNTIME_DIR", "/run/user/1000")]));
+
+        assert_eq!(
+            access.mounts,
+            vec![
+                socket("/run/user/1000/pulse"),
+                socket("/run/user/1000/pipewire-0"),
+            ]
+        );
+        assert_eq!(
+            access.env,
+            vec![("XDG_RUNTIME_DIR".to_string(), "/run/user/1000".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_gui_and_audio_pass_env_once() {
+        let entry = Entry {
+            gui: true,
+            audio: true,
+            ..Default::default()
+        };
+        let access = DesktopAccess::from_entry(
+            &entry,
+            lookup(&[
+                ("WAYLAND_DISPLAY", "wayland-0"),
+                ("XDG_RUNTIME_DIR", "/run/user/1000"),
+            ]),
+        );
+
+        assert_eq!(access.mounts.len(), 3);
+        assert_eq!(
+            access.env,
+            vec![
+                ("WAYLAND_DISPLAY".to_string(), "wayland-0".to_string()),
+                ("XDG_RUNTIME_DIR".to_string(), "/run/user/1000".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dbus_session() {
+        let entry = Entry {
+            dbus: Some(Dbus::Session),
+            ..Default::default()
+        };
+        let address = "unix:path=/run/user/1000/bus,guid=abc";
+        let access =
+            DesktopAccess::from_entry(&entry, lookup(&[("DBUS_SESSION_BUS_ADDRESS", address)]));
+
+        assert_eq!(access.mounts, vec![socket("/run/user/1000/bus")]);
+        assert_eq!(
+            access.env,
+            vec![("DBUS_SESSION_BUS_ADDRESS".to_string(), address.to_string())]
+        );
+
+        // Abstract sockets can't be bound
+        let access = DesktopAccess::from_entry(
+            &entry,
+            lookup(&[("DBUS_SESSION_BUS_ADDRESS", "unix:abstract=/tmp/dbus-x")]),
+        );
+        assert_eq!(access, DesktopAccess::default());
+    }
+
+    #[test]
+    fn test_dbus_system() {
+        let entry = Entry {
+            dbus: Some(Dbus::System),
+            ..Default::default()
+        };
+        let access = DesktopAccess::from_entry(&entry, lookup(&[]));
+
+        assert_eq!(access.mounts, vec![socket(DBUS_SYSTEM_SOCKET)]);
+        assert!(access.env.is_empty());
+    }
+
+    #[test]
+    fn test_dbus_unset() {
+        let address = "unix:path=/run/user/1000/bus";
+        let entry = Entry {
+            share: vec!["network".to_string()],
+            ..Default::default()
+        };
+        let access =
+            DesktopAccess::from_entry(&entry, lookup(&[("DBUS_SESSION_BUS_ADDRESS", address)]));
+        assert_eq!(access, DesktopAccess::default());
+    }
+
+    #[test]
+    fn test_dbus_none() {
+        let address = "unix:path=/run/user/1000/bus";
+        let entry = Entry {
+            dbus: Some(Dbus::None),
+            ..Default::default()
+        };
+        let access =
+            DesktopAccess::from_entry(&entry, lookup(&[("DBUS_SESSION_BUS_ADDRESS", address)]));
+        assert!(access.mounts.is_empty() && access.env.is_empty());
+        assert_eq!(access.unset_env, vec!["DBUS_SESSION_BUS_ADDRESS"]);
+        assert!(access.warnings.is_empty());
+
+        // A shared network reaches the abstract socket of the bus
+        let entry = Entry {
+            dbus: Some(Dbus::None),
+            share: vec!["network".to_string()],
+            ..Default::default()
+        };
+        let access = DesktopAccess::from_entry(
+            &entry,
+            lookup(&[("DBUS_SESSION_BUS_ADDRESS", "unix:abstract=/tmp/dbus-x")]),
+        );
+        assert_eq!(access.warnings, vec![BuildWarning::SharedNetworkDbus]);
+
+        let access = DesktopAccess::from_entry(&entry, lookup(&[]));
+        assert!(access.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_gui_disabled_or_without_display() {
+        let vars = [("DISPLAY", ":0")];
+        let access = DesktopAccess::from_entry(&Entry::default(), lookup(&vars));
+        assert_eq!(access, DesktopAccess::default());
+
+        let access = DesktopAccess::from_entry(&gui_entry(), lookup(&[]));
+        assert_eq!(access, DesktopAccess::default());
+    }
+}
@@ -1,131 +1,1254 @@
-use anyhow::Result;
-use std::process::Command;
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::{Mutex, OnceLock};
+use tracing::{trace, trace_span};
+
+use crate::config::loader::ConfigLoader;
+use crate::config::{Config, Entry, Network, Overlay};
+
+mod desktop;
+mod network;
+mod plan;
+// Running bwrap, stubbed out on other systems
+#[cfg(target_os = "linux")]
+mod run;
+
+pub use desktop::DesktopAccess;
+pub use network::{NETWORK_HELPER, RestrictedNetwork, resolve_host};
+use plan::path_depth;
+pub use plan::{BindMode, Mount, SandboxPlan};
+
+pub const NAMESPACES: [&str; 6] = ["user", "pid", "network", "ipc", "uts", "cgroup"];
+
+/// Exit code returned when a command is killed on timeout (same as `timeout(1)`)
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Size (in bytes) of the bwrap options above which they are passed with --args
+/// Well below the usual ARG_MAX (2 MiB), which also accounts for the environment
+const ARGS_FD_THRESHOLD: usize = 128 * 1024;
+
+/// Error returned instead of running bwrap on other systems than Linux
+pub const UNSUPPORTED_PLATFORM: &str = "shwrap requires Linux and bubblewrap";
+
+/// Destination of a configured mount, with its mode
+#[derive(Debug, Clone, PartialEq)]
+pub struct MountPoint {
+    /// Mount kind: read-write, read-only, device, overlay or tmpfs
+    pub mode: &'static str,
+    pub dst: String,
+}
+
+impl fmt::Display for MountPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' ({})", self.dst, self.mode)
+    }
+}
+
+/// Mount nested under (or on) another one, the layering is worth a check
+/// The inner mount is applied after the outer one
+#[derive(Debug, Clone, PartialEq)]
+pub struct NestedMount {
+    pub outer: MountPoint,
+    pub inner: MountPoint,
+}
+
+impl fmt::Display for NestedMount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is mounted over {}", self.inner, self.outer)
+    }
+}
+
+/// Non-fatal problem found while building the bwrap arguments
+/// The offending config value is skipped
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildWarning {
+    /// Bind not in the `src:dest` format
+    InvalidBind(String),
+    /// Overlay missing its upper/work directories
+    InvalidOverlay(String),
+    /// Excluded subpath outside of its read-only bind
+    InvalidExclude(String, String),
+    /// Root tmpfs without any bind, nothing of the host is visible
+    EmptyRootTmpfs,
+    /// Glob pattern of a bind source matching no path
+    NoGlobMatch(String),
+    /// `dbus: none` with a shared network, the session bus abstract socket is reachable
+    SharedNetworkDbus,
+}
+
+impl fmt::Display for BuildWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildWarning::InvalidBind(bind) => write!(f, "invalid bind format '{}'", bind),
+            BuildWarning::InvalidOverlay(message) => write!(f, "{}", message),
+            BuildWarning::InvalidExclude(subpath, path) => {
+                write!(f, "excluded path '{}' is not under '{}'", subpath, path)
+            }
+            BuildWarning::EmptyRootTmpfs => {
+                write!(
+                    f,
+                    "root_tmpfs is set without any bind, the sandbox will be empty"
+                )
+            }
+            BuildWarning::NoGlobMatch(pattern) => write!(f, "no path matches '{}'", pattern),
+            BuildWarning::SharedNetworkDbus => {
+                write!(
+                    f,
+                    "dbus is none but the network is shared, the session bus may still be reachable through its abstract socket"
+                )
+            }
+        }
+    }
+}
+
+/// Warning for an unshared pid namespace without a procfs (see missing_proc)
+pub const MISSING_PROC: &str = "the pid namespace is unshared without a proc mount, \
+    programs reading /proc/self will fail (add 'proc: /proc' or 'auto_proc: true')";
+
+/// Version of bubblewrap, as reported by `bwrap --version`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BwrapVersion(pub u32, pub u32, pub u32);
+
+impl BwrapVersion {
+    /// Parse the output of `bwrap --version` (e.g. `bubblewrap 0.8.0`)
+    pub fn parse(output: &str) -> Option<Self> {
+        let version = output.split_whitespace().last()?;
+        let mut parts = version.split('.').map(|part| part.parse::<u32>());
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+        let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+
+        Some(BwrapVersion(major, minor, patch))
+    }
+}
+
+impl fmt::Display for BwrapVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+/// Flags missing from older bwrap versions, with the version introducing them
+const FLAG_MIN_VERSIONS: &[(&str, BwrapVersion)] = &[
+    ("--cap-drop", BwrapVersion(0, 3, 0)),
+    ("--json-status-fd", BwrapVersion(0, 4, 0)),
+    ("--clearenv", BwrapVersion(0, 5, 0)),
+    ("--overlay-src", BwrapVersion(0, 9, 0)),
+    ("--overlay", BwrapVersion(0, 9, 0)),
+    ("--tmp-overlay", BwrapVersion(0, 9, 0)),
+    ("--ro-overlay", BwrapVersion(0, 9, 0)),
+];
+
+/// Sysctls disabling unprivileged user namespaces when set to 0
+const USERNS_SYSCTLS: &[&str] = &[
+    "/proc/sys/kernel/unprivileged_userns_clone",
+    "/proc/sys/user/max_user_namespaces",
+];
+
+/// Get the sysctl disabling unprivileged user namespaces, reading them with `read`
+/// None if they are enabled (missing sysctls don't restrict them)
+pub fn userns_disabled_by(read: impl Fn(&str) -> Option<String>) -> Option<&'static str> {
+    USERNS_SYSCTLS
+        .iter()
+        .find(|sysctl| read(sysctl).is_some_and(|value| value.trim() == "0"))
+        .copied()
+}
+
+/// Environment variable overriding the bwrap executable
+pub const BWRAP_PATH_VAR: &str = "SHWRAP_BWRAP_PATH";
+
+/// Get the bwrap executable to run: the configured one, else `$SHWRAP_BWRAP_PATH`,
+/// else `bwrap` from PATH
+pub fn bwrap_program(configured: Option<&str>) -> String {
+    resolve_bwrap_program(configured, |key| std::env::var(key).ok())
+}
 
-use crate::config::Entry;
+fn resolve_bwrap_program(configured: Option<&str>, var: impl Fn(&str) -> Option<String>) -> String {
+    match configured {
+        Some(path) => expand(path),
+        None => var(BWRAP_PATH_VAR)
+            .filter(|path| !path.is_empty())
+            .unwrap_or("bwrap".to_string()),
+    }
+}
+
+/// Get the version of a bwrap executable, probed once per process
+/// None if bwrap can't be run or its version can't be parsed
+pub fn bwrap_version(program: &str) -> Option<BwrapVersion> {
+    static VERSIONS: OnceLock<Mutex<HashMap<String, Option<BwrapVersion>>>> = OnceLock::new();
+
+    let mut versions = VERSIONS.get_or_init(Default::default).lock().ok()?;
+    *versions
+        .entry(program.to_string())
+        .or_insert_with(|| probe_version(Command::new(program)))
+}
+
+/// Run bwrap (or a stand-in) with --version and parse its output
+pub fn probe_version(mut cmd: Command) -> Option<BwrapVersion> {
+    let output = cmd.arg("--version").output().ok()?;
+
+    BwrapVersion::parse(&String::from_utf8_lossy(&output.stdout))
+}
 
-const NAMESPACES: [&str; 6] = ["user", "pid", "network", "ipc", "uts", "cgroup"];
+/// Fail on the first flag too recent for the given bwrap version
+fn check_flags<'a>(args: impl IntoIterator<Item = &'a str>, version: &BwrapVersion) -> Result<()> {
+    for arg in args {
+        if let Some((flag, min_version)) = FLAG_MIN_VERSIONS.iter().find(|(flag, _)| *flag == arg)
+            && version < min_version
+        {
+            bail!(
+                "bwrap {} does not support {} (requires bwrap {} or newer)",
+                version,
+                flag,
+                min_version
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Status of a sandboxed process, as reported by bwrap
+#[derive(Debug, Clone, PartialEq)]
+pub struct SandboxStatus {
+    /// PID of the sandboxed process (outside the sandbox), unknown if it never started
+    pub child_pid: Option<u32>,
+    pub exit_code: i32,
+}
 
 pub struct WrappedCommandBuilder {
     config: Entry,
+    quiet: bool,
+    /// Host variables passed with `--keep-env`, on top of the entry env
+    kept_env: Vec<(String, String)>,
+    /// Host files wired to the stdin and stdout of bwrap, inherited if unset
+    stdin_file: Option<PathBuf>,
+    stdout_file: Option<PathBuf>,
+    /// Directory relative bind sources are resolved against, the current one if unset
+    base_dir: Option<PathBuf>,
+    /// Start from an empty environment, without host variables passed by prefix
+    unset_all_env: bool,
+    /// Directory of the overlays kept with overlay_cache (see from_config)
+    overlay_cache_dir: Option<PathBuf>,
 }
 
 impl WrappedCommandBuilder {
     pub fn new(config: Entry) -> Self {
-        Self { config }
+        Self {
+            config,
+            quiet: false,
+            kept_env: Vec::new(),
+            stdin_file: None,
+            stdout_file: None,
+            base_dir: None,
+            unset_all_env: false,
+            overlay_cache_dir: None,
+        }
+    }
+
+    /// Resolve a command entry (with its templates) into a ready builder
+    /// With overlay_cache, overlays are kept in a cache directory keyed by the
+    /// config path and the command, so they persist across runs
+    /// Fails if the command has no entry (nor wildcard), is disabled or has broken extends
+    pub fn from_config(config: &Config, command: &str) -> Result<Self> {
+        Self::from_config_with_profile(config, command, None)
+    }
+
+    /// Same as from_config, merging the given profile over the command entry first
+    pub fn from_config_with_profile(
+        config: &Config,
+        command: &str,
+        profile: Option<&str>,
+    ) -> Result<Self> {
+        let mut cmd_config = config
+            .get_command(command)
+            .context(format!("No configuration found for command '{}'", command))?;
+
+        if !cmd_config.is_enabled() {
+            bail!("Command '{}' is disabled in configuration", command);
+        }
+
+        if let Some(profile) = profile {
+            cmd_config = cmd_config
+                .with_profile(profile)
+                .context(format!("Invalid profile for command '{}'", command))?;
+        }
+        config.get_extends_chain(command)?;
+
+        Ok(Self::from_entry(config, command, cmd_config))
+    }
+
+    /// Merge an entry of a config with its templates, without any check,
+    /// relative binds and cached overlays resolved as with from_config
+    pub fn from_entry(config: &Config, command: &str, cmd_config: Entry) -> Self {
+        let mut builder = Self::new(config.merge_with_template(cmd_config));
+        builder.base_dir = config.entry_base_dir(command);
+        builder.overlay_cache_dir = overlay_cache_dir(config, command);
+        builder
+    }
+
+    /// Get the resolved entry used to build the command
+    pub fn config(&self) -> &Entry {
+        &self.config
+    }
+
+    /// Apply the strict preset on top of the configuration
+    pub fn hardened(mut self) -> Self {
+        self.config.harden = true;
+        self
+    }
+
+    /// Run as uid/gid 0 in the sandbox (as with `run_as_root: true`)
+    pub fn as_root(mut self) -> Self {
+        self.config.run_as_root = true;
+        self
+    }
+
+    /// Start from an empty environment: only the variables set by the entry
+    /// (`env`, `env_file` and desktop access) are defined, `env_prefix_passthrough`
+    /// is ignored
+    pub fn unset_all_env(mut self) -> Self {
+        self.unset_all_env = true;
+        self
+    }
+
+    /// Pass host variables to the sandbox, reading them with `var`
+    /// Unset variables are skipped
+    pub fn keep_env(mut self, keys: &[String], var: impl Fn(&str) -> Option<String>) -> Self {
+        for key in keys {
+            if let Some(value) = var(key) {
+                self.kept_env.push((key.clone(), value));
+            }
+        }
+        self
+    }
+
+    /// Set variables in the sandbox as given (not expanded), over the entry ones
+    pub fn with_env(mut self, vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.kept_env.extend(vars);
+        self
+    }
+
+    /// Resolve relative bind sources against this directory (usually the one
+    /// of the config file) instead of the current one
+    pub fn with_base_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.base_dir = Some(dir.into());
+        self
+    }
+
+    /// Keep the overlays of overlay_cache under this directory
+    pub fn with_overlay_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.overlay_cache_dir = Some(dir.into());
+        self
+    }
+
+    /// Read stdin from a host file (opened outside the sandbox)
+    pub fn with_stdin_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stdin_file = Some(path.into());
+        self
+    }
+
+    /// Write stdout to a host file, created or truncated (outside the sandbox)
+    pub fn with_stdout_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stdout_file = Some(path.into());
+        self
+    }
+
+    /// Override the configured timeout (in seconds)
+    pub fn with_timeout(mut self, timeout: u64) -> Self {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    /// Don't print build warnings to stderr (errors are still returned)
+    pub fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+
+    /// Share all namespaces with the host (binds and env are still applied)
+    pub fn without_isolation(mut self) -> Self {
+        self.config.share = NAMESPACES.iter().map(|ns| ns.to_string()).collect();
+        self
+    }
+
+    /// Run the pre_exec steps on the host with `sh -c`, in order
+    /// Stops at the first step exiting with a nonzero code
+    pub fn run_pre_exec(&self) -> Result<()> {
+        for step in &self.config.pre_exec {
+            let status = Command::new("sh")
+                .args(["-c", step])
+                .status()
+                .context(format!("Failed to run pre_exec step '{}'", step))?;
+            if !status.success() {
+                bail!(
+                    "pre_exec step '{}' failed with exit code {}",
+                    step,
+                    exit_code(status)
+                );
+            }
+        }
+
+        Ok(())
     }
 
     /// Build the bwrap command arguments
-    pub fn build_args(&self) -> Vec<String> {
-        let mut args = Vec::new();
+    /// Warnings are printed to stderr unless quiet, see build_args_with_warnings to collect them
+    pub fn build_args(&self) -> Result<Vec<String>> {
+        Ok(self.plan()?.to_args())
+    }
+
+    /// Build the bwrap command arguments, collecting warnings instead of printing them
+    /// Fails if an env file can't be read
+    pub fn build_args_with_warnings(&self) -> Result<(Vec<String>, Vec<BuildWarning>)> {
+        let (plan, warnings) = self.plan_with_warnings()?;
+        let args = plan.to_args();
 
-        // Determine which namespaces to unshare (all by default, except those in share)
-        let shared_namespaces: std::collections::HashSet<&str> =
-            self.config.share.iter().map(|s| s.as_str()).collect();
+        trace!(?args, warnings = warnings.len(), "built bwrap arguments");
+        Ok((args, warnings))
+    }
+
+    /// Describe the sandbox (namespaces, mounts, environment) without building arguments
+    /// Warnings are printed to stderr unless quiet, see plan_with_warnings to collect them
+    pub fn plan(&self) -> Result<SandboxPlan> {
+        let (plan, warnings) = self.plan_with_warnings()?;
+
+        if !self.quiet {
+            for warning in warnings {
+                eprintln!("Warning: {}", warning);
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// Describe the sandbox, collecting warnings instead of printing them
+    /// Fails if an env file can't be read
+    pub fn plan_with_warnings(&self) -> Result<(SandboxPlan, Vec<BuildWarning>)> {
+        let _span = trace_span!("plan").entered();
+        let mut plan = SandboxPlan::default();
+        let mut warnings = Vec::new();
 
         // Unshare all namespaces except those explicitly shared
-        for namespace in &NAMESPACES {
-            if !shared_namespaces.contains(namespace) {
-                match *namespace {
-                    "network" => args.push("--unshare-net".to_string()),
-                    "pid" => args.push("--unshare-pid".to_string()),
-                    "ipc" => args.push("--unshare-ipc".to_string()),
-                    "uts" => args.push("--unshare-uts".to_string()),
-                    "user" => args.push("--unshare-user".to_string()),
-                    "cgroup" => args.push("--unshare-cgroup".to_string()),
-                    _ => {}
-                }
+        // A restricted network is the namespace of the network helper, not bwrap's
+        let (shared, unshared) = self.config.effective_namespaces();
+        let restricted_network = self.config.network == Some(Network::Restricted);
+        plan.shared = shared.iter().map(|ns| ns.to_string()).collect();
+        plan.unshared = unshared
+            .iter()
+            .filter(|ns| !(restricted_network && **ns == "network"))
+            .map(|ns| ns.to_string())
+            .collect();
+
+        // Map the user and group inside the sandbox (needs an unshared user namespace)
+        // Running as root maps both to 0
+        if !shared.contains(&"user") {
+            let root = self.config.run_as_root.then_some(0);
+            plan.uid = root.or(self.config.uid);
+            plan.gid = root.or(self.config.gid);
+        }
+
+        // Make sure the sandbox does not outlive us when killed on timeout
+        plan.die_with_parent = self.config.timeout.is_some() || self.config.harden;
+
+        // Detach from the controlling terminal (blocks TIOCSTI input injection)
+        // unless the command needs the terminal
+        plan.new_session =
+            !self.config.allow_tty && self.config.new_session.unwrap_or(self.config.harden);
+
+        // Strict preset: no capabilities, empty environment
+        plan.drop_caps = self.config.harden;
+        plan.clear_env = self.config.harden || self.unset_all_env;
+
+        let mounts = &mut plan.mounts;
+
+        // Strict root: an empty tmpfs on `/` before any mount
+        if self.config.root_tmpfs {
+            mounts.push(Mount::Tmpfs("/".to_string()));
+
+            let has_binds = !(self.config.bind.is_empty()
+                && self.config.bind_try.is_empty()
+                && self.config.ro_bind.is_empty()
+                && self.config.ro_bind_try.is_empty()
+                && self.config.dev_bind.is_empty()
+                && self.config.overlay.is_empty());
+            if !has_binds {
+                warnings.push(BuildWarning::EmptyRootTmpfs);
             }
         }
 
-        // Handle custom bind mounts
-        for bind in &self.config.bind {
-            let parts: Vec<&str> = bind.split(':').collect();
-            if parts.len() == 2 {
-                let src = shellexpand::full(parts[0]).unwrap_or_else(|_| parts[0].into());
-                let dst = shellexpand::full(parts[1]).unwrap_or_else(|_| parts[1].into());
-                args.push("--bind".to_string());
-                args.push(src.to_string());
-                args.push(dst.to_string());
-            } else {
-                eprintln!("Warning: invalid bind format '{}'", bind);
+        // Handle directories to create (before binds that may need them)
+        for dir in &self.config.dir {
+            mounts.push(Mount::Dir(expand(dir)));
+        }
+
+        // Handle desktop access (gui, audio, dbus), unless the destination is mounted explicitly
+        let desktop = DesktopAccess::from_entry(&self.config, |key| std::env::var(key).ok());
+        let explicit_destinations = self.mount_destinations();
+        for mount in desktop.mounts {
+            if let Mount::Bind { dst, .. } = &mount
+                && explicit_destinations.contains(dst)
+            {
+                continue;
             }
+            mounts.push(mount);
         }
 
-        // Handle read-only binds
+        // Handle custom bind mounts
+        let base_dir = self.base_dir.as_deref();
+        push_binds(mounts, &mut warnings, base_dir, false, &self.config.bind);
+        push_binds(mounts, &mut warnings, base_dir, true, &self.config.bind_try);
+
+        // Handle read-only binds, glob patterns bind each match
+        // Excluded subpaths are hidden by a tmpfs mounted over them afterward
         for ro_bind in &self.config.ro_bind {
-            let expanded = shellexpand::full(ro_bind).unwrap_or_else(|_| ro_bind.into());
-            args.push("--ro-bind".to_string());
-            args.push(expanded.to_string());
-            args.push(expanded.to_string());
+            let path = resolve(ro_bind.path(), base_dir);
+            let paths = match expand_glob(&path) {
+                Some(matches) => matches.into_iter().map(|(path, _)| path).collect(),
+                None => vec![path.clone()],
+            };
+            if paths.is_empty() {
+                warnings.push(BuildWarning::NoGlobMatch(path.clone()));
+            }
+            for bound in &paths {
+                mounts.push(Mount::same_path(BindMode::ReadOnly, false, bound));
+            }
+            for subpath in ro_bind.exclude() {
+                let subpath = resolve(subpath, base_dir);
+                if paths
+                    .iter()
+                    .any(|bound| Path::new(&subpath).starts_with(bound))
+                {
+                    mounts.push(Mount::Tmpfs(subpath));
+                } else {
+                    warnings.push(BuildWarning::InvalidExclude(subpath, path.clone()));
+                }
+            }
         }
+        push_paths(
+            mounts,
+            base_dir,
+            BindMode::ReadOnly,
+            true,
+            &self.config.ro_bind_try,
+        );
 
         // Handle device binds
-        for dev_bind in &self.config.dev_bind {
-            let expanded = shellexpand::full(dev_bind).unwrap_or_else(|_| dev_bind.into());
-            args.push("--dev-bind".to_string());
-            args.push(expanded.to_string());
-            args.push(expanded.to_string());
+        push_paths(
+            mounts,
+            base_dir,
+            BindMode::Device,
+            false,
+            &self.config.dev_bind,
+        );
+        if self.config.allow_tty {
+            mounts.push(Mount::same_path(BindMode::Device, false, "/dev/tty"));
+            mounts.push(Mount::same_path(BindMode::Device, true, "/dev/console"));
+        }
+
+        // Handle overlays
+        for overlay in &self.config.overlay {
+            let cached = self.overlay_cache(overlay);
+            if cached.is_none()
+                && let Err(err) = overlay.validate()
+            {
+                warnings.push(BuildWarning::InvalidOverlay(err.to_string()));
+                continue;
+            }
+
+            let upper = match (&overlay.upper, &overlay.work) {
+                (Some(upper), Some(work)) if !overlay.tmp => Some((expand(upper), expand(work))),
+                _ => cached
+                    .map(|(upper, work)| (upper.display().to_string(), work.display().to_string())),
+            };
+            mounts.push(Mount::Overlay {
+                src: expand(&overlay.src),
+                dst: expand(&overlay.dst),
+                upper,
+            });
+        }
+
+        // Handle the procfs, mounted on /proc with auto_proc if none is
+        if let Some(path) = self.proc_mount() {
+            mounts.push(Mount::Proc(path));
         }
 
         // Handle tmpfs
         for tmpfs in &self.config.tmpfs {
-            args.push("--tmpfs".to_string());
-            args.push(tmpfs.clone());
+            mounts.push(Mount::Tmpfs(tmpfs.clone()));
+        }
+
+        // Mount parents before their children, whatever the config order
+        // (the sort is stable, so mounts on the same path keep their order)
+        mounts.sort_by_key(|mount| path_depth(mount.dst()));
+
+        // Handle lock files (held while the sandbox runs)
+        for path in &self.config.lock_file {
+            mounts.push(Mount::LockFile(expand(path)));
+        }
+
+        // Handle read-only remounts (after the mounts they apply to)
+        for path in &self.config.remount_ro {
+            mounts.push(Mount::RemountRo(expand(path)));
         }
 
-        // Handle environment variables
+        // Handle environment variables, host variables passed for desktop access
+        // and by prefix then env files first so inline values win (and kept
+        // variables win over all)
+        // Inline values are expanded (not file values nor keys), sorted by key
+        // so the output is deterministic
+        plan.env.extend(desktop.env);
+        if !self.unset_all_env && !self.config.env_prefix_passthrough.is_empty() {
+            plan.env.extend(std::env::vars().filter(|(key, _)| {
+                self.config
+                    .env_prefix_passthrough
+                    .iter()
+                    .any(|prefix| key.starts_with(prefix.as_str()))
+            }));
+        }
+        for path in &self.config.env_file {
+            plan.env.extend(read_env_file(&expand(path))?);
+        }
         for (key, value) in &self.config.env {
-            args.push("--setenv".to_string());
-            args.push(key.clone());
-            args.push(value.clone());
+            plan.env.insert(key.clone(), expand(value));
+        }
+        plan.env.extend(self.kept_env.iter().cloned());
+
+        // Handle unset environment variables, patterns ending with `*` unset
+        // the matching host variables
+        let host_keys = std::env::vars_os().filter_map(|(key, _)| key.into_string().ok());
+        plan.unset_env = unset_env_keys(&self.config.unset_env, host_keys);
+
+        // Hide the desktop variables not given access to, unless set explicitly
+        // or the environment is cleared anyway
+        for key in desktop.unset_env {
+            if !plan.clear_env && !plan.env.contains_key(&key) && !plan.unset_env.contains(&key) {
+                plan.unset_env.push(key);
+            }
+        }
+        warnings.extend(desktop.warnings);
+
+        Ok((plan, warnings))
+    }
+
+    /// Get the upper and work directories of an overlay kept in the cache,
+    /// None if it has its own, is temporary, or overlay_cache is off
+    fn overlay_cache(&self, overlay: &Overlay) -> Option<(PathBuf, PathBuf)> {
+        let dir = self.overlay_cache_dir.as_ref()?;
+        let cached = self.config.overlay_cache
+            && !overlay.tmp
+            && overlay.upper.is_none()
+            && overlay.work.is_none();
+        if !cached {
+            return None;
+        }
+
+        let dir = dir.join(cache_key(Path::new(&expand(&overlay.dst)), ""));
+        Some((dir.join("upper"), dir.join("work")))
+    }
+
+    /// Get where the procfs is mounted, the configured path or `/proc` with
+    /// auto_proc if needed
+    fn proc_mount(&self) -> Option<String> {
+        match &self.config.proc {
+            Some(path) => Some(expand(path)),
+            None => (self.config.auto_proc && self.lacks_proc()).then(|| "/proc".to_string()),
         }
+    }
 
-        // Handle unset environment variables
-        for key in &self.config.unset_env {
-            args.push("--unsetenv".to_string());
-            args.push(key.clone());
+    /// Tell if the pid namespace is unshared without a procfs (see MISSING_PROC)
+    /// auto_proc mounts one, a bind covering `/proc` is the host one
+    pub fn missing_proc(&self) -> bool {
+        !self.config.auto_proc && self.lacks_proc()
+    }
+
+    fn lacks_proc(&self) -> bool {
+        let pid_unshared = self.config.effective_namespaces().1.contains(&"pid");
+        let proc_bound = self
+            .mount_destinations()
+            .iter()
+            .any(|dst| Path::new("/proc").starts_with(dst));
+        pid_unshared && self.config.proc.is_none() && !proc_bound
+    }
+
+    /// Get the destinations of the explicitly configured mounts (expanded)
+    fn mount_destinations(&self) -> Vec<String> {
+        self.mounts().into_iter().map(|mount| mount.dst).collect()
+    }
+
+    /// Get the explicitly configured mounts, in the order bwrap applies them
+    /// (excluded subpaths of read-only binds are left out)
+    fn mounts(&self) -> Vec<MountPoint> {
+        let mount = |mode, dst: &str| MountPoint {
+            mode,
+            dst: expand(dst),
+        };
+        // Paths bound at the same location are resolved like bind sources
+        let same_path = |mode, path: &str| MountPoint {
+            mode,
+            dst: resolve(path, self.base_dir.as_deref()),
+        };
+        let bind_dst = |bind: &String| bind.split(':').nth(1).map(String::from);
+
+        let binds = self.config.bind.iter().chain(&self.config.bind_try);
+        let mut mounts: Vec<_> = binds
+            .filter_map(bind_dst)
+            .map(|dst| mount("read-write", &dst))
+            .chain(
+                self.config
+                    .ro_bind
+                    .iter()
+                    .map(|ro_bind| same_path("read-only", ro_bind.path())),
+            )
+            .chain(
+                self.config
+                    .ro_bind_try
+                    .iter()
+                    .map(|path| same_path("read-only", path)),
+            )
+            .chain(
+                self.config
+                    .dev_bind
+                    .iter()
+                    .map(|path| same_path("device", path)),
+            )
+            .chain(
+                self.config
+                    .overlay
+                    .iter()
+                    .map(|overlay| mount("overlay", &overlay.dst)),
+            )
+            .chain(self.config.tmpfs.iter().map(|path| mount("tmpfs", path)))
+            .collect();
+        mounts.sort_by_key(|mount| path_depth(&mount.dst));
+        mounts
+    }
+
+    /// Find the mounts nested under (or on) another one
+    pub fn nested_mounts(&self) -> Vec<NestedMount> {
+        let mounts = self.mounts();
+        let mut nested = Vec::new();
+
+        for (i, outer) in mounts.iter().enumerate() {
+            for (j, inner) in mounts.iter().enumerate() {
+                let is_nested = Path::new(&inner.dst).starts_with(&outer.dst);
+                // Mounts are sorted by depth, so an inner mount comes after
+                // its outer one (and mounts on the same path are reported once)
+                if i < j && is_nested {
+                    nested.push(NestedMount {
+                        outer: outer.clone(),
+                        inner: inner.clone(),
+                    });
+                }
+            }
         }
 
-        args
+        nested
     }
 
-    /// Execute a command with bwrap
-    pub fn exec(&self, command: &str, command_args: &[String]) -> Result<i32> {
-        let bwrap_args = self.build_args();
+    /// Whether shwrap must stay around while bwrap runs (to enforce a timeout,
+    /// stream inline files, long argument lists or the restricted network
+    /// files), so exec_replace can't be used
+    pub fn needs_supervision(&self) -> bool {
+        let long_args = match self.build_args_with_warnings() {
+            Ok((args, _)) => args_size(&args) > ARGS_FD_THRESHOLD,
+            Err(_) => true,
+        };
+
+        self.config.timeout.is_some()
+            || !self.config.files.is_empty()
+            || self.config.network.is_some()
+            || long_args
+    }
 
-        let mut cmd = Command::new("bwrap");
-        cmd.args(&bwrap_args);
-        cmd.arg(command);
-        cmd.args(command_args);
+    /// Check that the given bwrap version supports the flags used by the config
+    pub fn check_version(&self, version: &BwrapVersion) -> Result<()> {
+        let mut bwrap_args = self.build_args()?;
+        if !self.config.files.is_empty() {
+            bwrap_args.push("--file".to_string());
+        }
 
-        let status = cmd.status()?;
-        Ok(status.code().unwrap_or(1))
+        check_flags(bwrap_args.iter().map(String::as_str), version)
     }
 
     /// Show the bwrap command that would be executed (dry-run)
-    pub fn show(&self, command: &str, command_args: &[String]) -> String {
-        let bwrap_args = self.build_args();
+    pub fn show(&self, command: &str, command_args: &[String]) -> Result<String> {
+        Ok(self.show_groups(command, command_args)?.join(" "))
+    }
+
+    /// Show the bwrap command running the given, already built, arguments
+    pub fn show_args(
+        &self,
+        bwrap_args: &[String],
+        command: &str,
+        command_args: &[String],
+    ) -> String {
+        self.command_groups(bwrap_args.to_vec(), command, command_args)
+            .join(" ")
+    }
+
+    /// Get the bwrap argv, command included (inline files are left out,
+    /// they are streamed through pipes only available while running)
+    pub fn show_argv(&self, command: &str, command_args: &[String]) -> Result<Vec<String>> {
+        let mut argv = self.helper_argv();
+        argv.push(self.bwrap_program());
+        argv.extend(self.build_args()?);
+        argv.push(command.to_string());
+        argv.extend(self.config.command_args(command_args));
 
-        let mut parts = vec!["bwrap".to_string()];
-        parts.extend(bwrap_args);
-        parts.push(command.to_string());
-        parts.extend(command_args.iter().cloned());
+        Ok(argv)
+    }
+
+    /// Show the bwrap command on several lines, one flag with its values per line
+    pub fn show_pretty(&self, command: &str, command_args: &[String]) -> Result<String> {
+        Ok(self.show_groups(command, command_args)?.join(" \\\n  "))
+    }
+
+    /// Show the bwrap command as a table, each flag with its values and a description
+    pub fn show_explained(&self, command: &str, command_args: &[String]) -> Result<String> {
+        let groups = self.show_groups(command, command_args)?;
+        let width = groups.iter().map(|group| group.len()).max().unwrap_or(0);
+        let last = groups.len() - 1;
+
+        let lines: Vec<_> = groups
+            .iter()
+            .enumerate()
+            .map(|(index, group)| {
+                let description = match index {
+                    0 => "run the command in a bubblewrap sandbox",
+                    _ if index == last => "command run in the sandbox",
+                    _ => describe_flag(group.split(' ').next().unwrap_or_default()),
+                };
+                format!("{:<width$}  # {}", group, description)
+            })
+            .collect();
+
+        Ok(lines.join("\n"))
+    }
+
+    /// Get the bwrap executable run for this entry (see bwrap_program)
+    pub fn bwrap_program(&self) -> String {
+        bwrap_program(self.config.bwrap_path.as_deref())
+    }
+
+    /// Get the network helper command line running bwrap, if any
+    fn helper_argv(&self) -> Vec<String> {
+        match self.config.network {
+            Some(Network::Restricted) => network::helper_argv(&self.config.allow_hosts),
+            None => vec![],
+        }
+    }
+
+    /// Get the shown command as groups: bwrap, each flag with its values, then the command
+    fn show_groups(&self, command: &str, command_args: &[String]) -> Result<Vec<String>> {
+        Ok(self.command_groups(self.build_args()?, command, command_args))
+    }
+
+    /// Group the given bwrap arguments as shown, see show_groups
+    fn command_groups(
+        &self,
+        bwrap_args: Vec<String>,
+        command: &str,
+        command_args: &[String],
+    ) -> Vec<String> {
+        let mut launcher = self.helper_argv();
+        launcher.push(self.bwrap_program());
+
+        let mut groups = vec![launcher.join(" ")];
+        let mut bwrap_args = bwrap_args.into_iter();
+        while let Some(flag) = bwrap_args.next() {
+            let mut group = vec![flag.clone()];
+            group.extend(bwrap_args.by_ref().take(flag_arity(&flag)));
+            groups.push(group.join(" "));
+        }
+        for file in &self.config.files {
+            groups.push(format!("--file {} (inline)", expand(&file.dest)));
+        }
+        if self.config.network.is_some() {
+            groups.push("--ro-bind-data /etc/hosts (allowed hosts)".to_string());
+            groups.push("--ro-bind-data /etc/resolv.conf (empty)".to_string());
+        }
+        let mut command_line = vec![command.to_string()];
+        command_line.extend(self.config.command_args(command_args));
+        groups.push(command_line.join(" "));
 
-        parts.join(" ")
+        groups
     }
 }
 
+/// Find the executable a command name resolves to, like a shell would
+/// Names containing a slash are paths and are not searched in PATH,
+/// relative PATH entries are ignored
+pub fn find_executable(command: &str) -> Option<PathBuf> {
+    let is_executable = |path: &Path| path.metadata().is_ok_and(|meta| is_executable(&meta));
+
+    if command.contains('/') {
+        let path = std::path::absolute(command).ok()?;
+        return is_executable(&path).then_some(path);
+    }
+
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(command))
+        .find(|path| path.is_absolute() && is_executable(path))
+}
+
+/// Get a short human description of a bwrap flag
+fn describe_flag(flag: &str) -> &'static str {
+    match flag {
+        "--unshare-user" => "isolate users: separate user and group IDs",
+        "--unshare-pid" => "isolate processes: host processes are invisible",
+        "--unshare-net" => "isolate network: no internet access",
+        "--unshare-ipc" => "isolate IPC: no shared memory with the host",
+        "--unshare-uts" => "isolate hostname",
+        "--unshare-cgroup" => "isolate control groups",
+        "--uid" => "run as this user ID in the sandbox",
+        "--gid" => "run as this group ID in the sandbox",
+        "--dir" => "create a directory",
+        "--die-with-parent" => "kill the sandbox when shwrap exits",
+        "--new-session" => "detach from the terminal session (blocks input injection)",
+        "--cap-drop" => "drop capabilities",
+        "--clearenv" => "start with an empty environment",
+        "--bind" => "mount read-write",
+        "--bind-try" => "mount read-write, if the source exists",
+        "--ro-bind" => "mount read-only",
+        "--ro-bind-try" => "mount read-only, if the source exists",
+        "--dev-bind" => "mount with device access",
+        "--dev-bind-try" => "mount with device access, if the source exists",
+        "--overlay-src" => "overlay lower layer",
+        "--overlay" => "overlay, writes kept in the upper directory",
+        "--tmp-overlay" => "overlay, writes discarded on exit",
+        "--ro-overlay" => "read-only overlay",
+        "--tmpfs" => "mount an empty temporary filesystem",
+        "--proc" => "mount a new procfs",
+        "--lock-file" => "hold a lock on this file while running",
+        "--remount-ro" => "remount read-only",
+        "--setenv" => "set an environment variable",
+        "--unsetenv" => "unset an environment variable",
+        "--file" => "create a file from inline content",
+        _ => "",
+    }
+}
+
+/// Get the number of values following a bwrap flag
+fn flag_arity(flag: &str) -> usize {
+    match flag {
+        "--overlay" => 3,
+        "--bind" | "--bind-try" | "--ro-bind" | "--ro-bind-try" | "--dev-bind"
+        | "--dev-bind-try" | "--setenv" | "--file" => 2,
+        "--dir" | "--tmpfs" | "--proc" | "--remount-ro" | "--lock-file" | "--overlay-src"
+        | "--tmp-overlay" | "--ro-overlay" | "--unsetenv" | "--uid" | "--gid" | "--cap-drop"
+        | "--json-status-fd" | "--args" => 1,
+        _ => 0,
+    }
+}
+
+/// Expand `~` and environment variables, keeping the value as is on failure
+fn expand(value: &str) -> String {
+    shellexpand::full(value)
+        .map(|expanded| expanded.to_string())
+        .unwrap_or_else(|_| value.to_string())
+}
+
+/// Get the variables to unset: literal names as is, and the host variables
+/// (sorted) matching the names ending with `*` as prefixes
+fn unset_env_keys(names: &[String], host_keys: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut host_keys: Vec<String> = host_keys.into_iter().collect();
+    host_keys.sort();
+
+    let mut keys: Vec<String> = Vec::new();
+    for name in names {
+        let matches: Vec<&String> = match name.strip_suffix('*') {
+            Some(prefix) => host_keys
+                .iter()
+                .filter(|key| key.starts_with(prefix))
+                .collect(),
+            None => vec![name],
+        };
+        for key in matches {
+            if !keys.contains(key) {
+                keys.push(key.clone());
+            }
+        }
+    }
+    keys
+}
+
+/// Split the leading `KEY=VALUE` arguments (as a shell prefix) from the others
+pub fn split_env_prefix(args: &[String]) -> (Vec<(String, String)>, &[String]) {
+    let is_name = |key: &str| {
+        key.chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+
+    let mut vars = Vec::new();
+    for (index, arg) in args.iter().enumerate() {
+        match arg.split_once('=') {
+            Some((key, value)) if is_name(key) => vars.push((key.to_string(), value.to_string())),
+            _ => return (vars, &args[index..]),
+        }
+    }
+    (vars, &[])
+}
+
+/// Get the directory of the overlays kept for a command with overlay_cache,
+/// under the cache directory, None if the config has no file
+pub fn overlay_cache_dir(config: &Config, command: &str) -> Option<PathBuf> {
+    let path = config.entry_path(command)?;
+    Some(ConfigLoader::get_cache_dir().join(cache_key(path, command)))
+}
+
+/// Get a stable key for a path and a name: the FNV-1a hash of both, in hex
+/// (the std hasher may change across Rust releases)
+fn cache_key(path: &Path, name: &str) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let bytes = path.as_os_str().as_encoded_bytes();
+    for byte in bytes.iter().chain(b"\0").chain(name.as_bytes()) {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    format!("{:016x}", hash)
+}
+
+/// Fail on other systems than Linux, where bubblewrap can't run
+pub fn check_platform() -> Result<()> {
+    if cfg!(target_os = "linux") {
+        return Ok(());
+    }
+
+    bail!(UNSUPPORTED_PLATFORM)
+}
+
+/// Running bwrap, other systems than Linux only fail (see check_platform)
+#[cfg(not(target_os = "linux"))]
+impl WrappedCommandBuilder {
+    pub fn exec(&self, _command: &str, _command_args: &[String]) -> Result<i32> {
+        bail!(UNSUPPORTED_PLATFORM)
+    }
+
+    pub fn exec_args(
+        &self,
+        _bwrap_args: Vec<String>,
+        _command: &str,
+        _command_args: &[String],
+    ) -> Result<i32> {
+        bail!(UNSUPPORTED_PLATFORM)
+    }
+
+    pub fn exec_with_status(
+        &self,
+        _command: &str,
+        _command_args: &[String],
+    ) -> Result<SandboxStatus> {
+        bail!(UNSUPPORTED_PLATFORM)
+    }
+
+    pub fn exec_replace(
+        &self,
+        _command: &str,
+        _command_args: &[String],
+    ) -> Result<std::convert::Infallible> {
+        bail!(UNSUPPORTED_PLATFORM)
+    }
+
+    pub fn exec_replace_args(
+        &self,
+        _bwrap_args: Vec<String>,
+        _command: &str,
+        _command_args: &[String],
+    ) -> Result<std::convert::Infallible> {
+        bail!(UNSUPPORTED_PLATFORM)
+    }
+}
+
+/// Expand a host path, resolving it against the base directory if relative
+fn resolve(path: &str, base_dir: Option<&Path>) -> String {
+    let expanded = expand(path);
+    match base_dir {
+        Some(dir) if Path::new(&expanded).is_relative() => {
+            let relative = expanded.strip_prefix("./").unwrap_or(&expanded);
+            dir.join(relative).display().to_string()
+        }
+        _ => expanded,
+    }
+}
+
+/// Read the `KEY=VALUE` pairs of an env file
+fn read_env_file(path: &str) -> Result<Vec<(String, String)>> {
+    let content =
+        fs::read_to_string(path).context(format!("Failed to read env file '{}'", path))?;
+
+    parse_env_file(&content).context(format!("Failed to parse env file '{}'", path))
+}
+
+/// Parse `KEY=VALUE` lines, ignoring blank lines and `#` comments
+/// Values are kept as is, except for surrounding quotes
+fn parse_env_file(content: &str) -> Result<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("line {}: expected KEY=VALUE, got '{}'", index + 1, line);
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            bail!("line {}: missing key", index + 1);
+        }
+
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+            .unwrap_or(value);
+
+        pairs.push((key.to_string(), value.to_string()));
+    }
+
+    Ok(pairs)
+}
+
+/// Push `src:dest` read-write binds, skipping (and reporting) invalid ones
+fn push_binds(
+    mounts: &mut Vec<Mount>,
+    warnings: &mut Vec<BuildWarning>,
+    base_dir: Option<&Path>,
+    optional: bool,
+    binds: &[String],
+) {
+    let bind = |src, dst| Mount::Bind {
+        mode: BindMode::ReadWrite,
+        optional,
+        src,
+        dst,
+    };
+
+    for spec in binds {
+        let parts: Vec<&str> = spec.split(':').collect();
+        if parts.len() != 2 {
+            warnings.push(BuildWarning::InvalidBind(spec.clone()));
+            continue;
+        }
+
+        let (src, dst) = (resolve(parts[0], base_dir), expand(parts[1]));
+        let Some(matches) = expand_glob(&src) else {
+            mounts.push(bind(src, dst));
+            continue;
+        };
+
+        // Each match keeps its path relative to the pattern base under dst
+        if matches.is_empty() && !optional {
+            warnings.push(BuildWarning::NoGlobMatch(src));
+        }
+        for (path, relative) in matches {
+            let dst = Path::new(&dst).join(relative);
+            mounts.push(bind(path, dst.display().to_string()));
+        }
+    }
+}
+
+/// Expand a glob pattern into the matching paths (sorted), each with its path
+/// relative to the pattern base (the leading components without wildcards)
+/// None if the path has no wildcard
+fn expand_glob(pattern: &str) -> Option<Vec<(String, PathBuf)>> {
+    let is_pattern = |part: &str| part.contains(['*', '?', '[']);
+    if !is_pattern(pattern) {
+        return None;
+    }
+
+    let base: PathBuf = Path::new(pattern)
+        .components()
+        .take_while(|component| !is_pattern(&component.as_os_str().to_string_lossy()))
+        .collect();
+    let matches = match glob::glob(pattern) {
+        Ok(paths) => paths.filter_map(Result::ok).collect(),
+        Err(_) => vec![],
+    };
+
+    Some(
+        matches
+            .into_iter()
+            .map(|path: PathBuf| {
+                let relative = path.strip_prefix(&base).unwrap_or(&path).to_path_buf();
+                (path.display().to_string(), relative)
+            })
+            .collect(),
+    )
+}
+
+/// Push paths mounted at the same location
+fn push_paths(
+    mounts: &mut Vec<Mount>,
+    base_dir: Option<&Path>,
+    mode: BindMode,
+    optional: bool,
+    paths: &[String],
+) {
+    for path in paths {
+        mounts.push(Mount::same_path(mode, optional, &resolve(path, base_dir)));
+    }
+}
+
+/// Size of the arguments once NUL terminated, as counted against ARG_MAX
+fn args_size(args: &[String]) -> usize {
+    args.iter().map(|arg| arg.len() + 1).sum()
+}
+
+/// Check that a file has an execute permission bit
+#[cfg(unix)]
+fn is_executable(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    meta.is_file() && meta.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(meta: &fs::Metadata) -> bool {
+    meta.is_file()
+}
+
+/// Get the exit code of a process like a shell would: 128 + signal when killed by a signal
+#[cfg(unix)]
+fn exit_code(status: ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    }
+}
+
+#[cfg(not(unix))]
+fn exit_code(status: ExitStatus) -> i32 {
+    status.code().unwrap_or(1)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::config::EntryType;
+    use crate::config::{Dbus, Enabled, EntryType, InlineFile, Overlay, RoBind};
 
     use super::*;
+    use indoc::indoc;
     use std::collections::HashMap;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
 
-    fn create_test_config() -> Entry {
+    pub(super) fn create_test_config() -> Entry {
         Entry {
             entry_type: EntryType::Command,
-            enabled: true,
+            enabled: Enabled::Always(true),
             extends: None,
             share: vec![],
             bind: vec![],
@@ -134,6 +1257,7 @@ mod tests {
             tmpfs: vec![],
             env: HashMap::new(),
             unset_env: vec![],
+            ..Default::default()
         }
     }
 
@@ -143,7 +1267,7 @@ mod tests {
         // Empty config = all namespaces unshared by default
 
         let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
+        let args = builder.build_args().unwrap();
 
         assert!(args.contains(&"--unshare-net".to_string()));
         assert!(args.contains(&"--unshare-pid".to_string()));
@@ -160,7 +1284,7 @@ mod tests {
         config.share = vec!["network".to_string(), "user".to_string()];
 
         let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
+        let args = builder.build_args().unwrap();
 
         // Network and user should NOT be unshared
         assert!(!args.contains(&"--unshare-net".to_string()));
@@ -174,94 +1298,853 @@ mod tests {
     }
 
     #[test]
-    fn test_build_args_bind() {
+    fn test_build_args_without_isolation() {
+        let mut config = create_test_config();
+        config.share = vec!["network".to_string()];
+        config.ro_bind = vec!["/usr".into()];
+        config.env.insert("TEST".to_string(), "value".to_string());
+
+        let builder = WrappedCommandBuilder::new(config).without_isolation();
+        let args = builder.build_args().unwrap();
+
+        assert!(!args.iter().any(|arg| arg.starts_with("--unshare-")));
+        assert!(args.contains(&"--ro-bind".to_string()));
+        assert!(args.contains(&"--setenv".to_string()));
+    }
+
+    #[test]
+    fn test_build_args_timeout_dies_with_parent() {
+        let config = create_test_config();
+        let builder = WrappedCommandBuilder::new(config);
+        assert!(
+            !builder
+                .build_args()
+                .unwrap()
+                .contains(&"--die-with-parent".to_string())
+        );
+
+        let mut config = create_test_config();
+        config.timeout = Some(10);
+        let builder = WrappedCommandBuilder::new(config);
+        assert!(
+            builder
+                .build_args()
+                .unwrap()
+                .contains(&"--die-with-parent".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_exit_code() {
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        // SAFETY: the pid belongs to our own child, not yet reaped
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+        }
+        assert_eq!(exit_code(child.wait().unwrap()), 143);
+
+        let mut child = Command::new("sh").args(["-c", "exit 1"]).spawn().unwrap();
+        assert_eq!(exit_code(child.wait().unwrap()), 1);
+    }
+
+    #[test]
+    fn test_show_inline_files() {
+        let mut config = create_test_config();
+        config.files = vec![InlineFile {
+            dest: "/app/config.json".to_string(),
+            content: "{}".to_string(),
+        }];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let cmd = builder.show("node", &[]).unwrap();
+
+        assert!(cmd.ends_with(" --file /app/config.json (inline) node"));
+        assert!(builder.needs_supervision());
+        assert!(builder.exec_replace("node", &[]).is_err());
+    }
+
+    #[test]
+    fn test_build_args_bind() {
+        let mut config = create_test_config();
+        config.bind = vec!["/src:/dest".to_string()];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        let bind_idx = args.iter().position(|x| x == "--bind").unwrap();
+        assert_eq!(args[bind_idx + 1], "/src");
+        assert_eq!(args[bind_idx + 2], "/dest");
+    }
+
+    #[test]
+    fn test_build_args_uid_gid() {
+        let mut config = create_test_config();
+        config.uid = Some(0);
+        config.gid = Some(100);
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        let uid_idx = args.iter().position(|x| x == "--uid").unwrap();
+        assert_eq!(args[uid_idx + 1], "0");
+        let gid_idx = args.iter().position(|x| x == "--gid").unwrap();
+        assert_eq!(args[gid_idx + 1], "100");
+    }
+
+    #[test]
+    fn test_build_args_uid_gid_shared_user() {
+        let mut config = create_test_config();
+        config.share = vec!["user".to_string()];
+        config.uid = Some(0);
+        config.gid = Some(0);
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        assert!(!args.contains(&"--uid".to_string()));
+        assert!(!args.contains(&"--gid".to_string()));
+    }
+
+    #[test]
+    fn test_build_args_as_root() {
+        let mut config = create_test_config();
+        config.uid = Some(1000);
+
+        let builder = WrappedCommandBuilder::new(config).as_root();
+        assert!(builder.config().check_id_mapping().is_ok());
+        let args = builder.build_args().unwrap();
+
+        let uid_idx = args.iter().position(|x| x == "--uid").unwrap();
+        assert_eq!(args[uid_idx + 1], "0");
+        let gid_idx = args.iter().position(|x| x == "--gid").unwrap();
+        assert_eq!(args[gid_idx + 1], "0");
+
+        let mut config = create_test_config();
+        config.share = vec!["user".to_string()];
+        let builder = WrappedCommandBuilder::new(config).as_root();
+        assert!(builder.config().check_id_mapping().is_err());
+    }
+
+    #[test]
+    fn test_build_args_harden() {
+        let mut config = create_test_config();
+        config.harden = true;
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        assert_eq!(
+            args,
+            [
+                "--unshare-user",
+                "--unshare-pid",
+                "--unshare-net",
+                "--unshare-ipc",
+                "--unshare-uts",
+                "--unshare-cgroup",
+                "--die-with-parent",
+                "--new-session",
+                "--cap-drop",
+                "ALL",
+                "--clearenv",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_args_harden_overrides() {
+        let mut config = create_test_config();
+        config.harden = true;
+        config.timeout = Some(10);
+        config.share = vec!["network".to_string()];
+        config.env.insert("LANG".to_string(), "C.UTF-8".to_string());
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        // Shared namespaces and env values still apply, flags are not repeated
+        assert!(!args.contains(&"--unshare-net".to_string()));
+        let clearenv_idx = args.iter().position(|x| x == "--clearenv").unwrap();
+        let setenv_idx = args.iter().position(|x| x == "--setenv").unwrap();
+        assert!(clearenv_idx < setenv_idx);
+        assert_eq!(args.iter().filter(|x| *x == "--die-with-parent").count(), 1);
+    }
+
+    #[test]
+    fn test_build_args_new_session() {
+        let mut config = create_test_config();
+        assert!(
+            !WrappedCommandBuilder::new(config.clone())
+                .build_args()
+                .unwrap()
+                .contains(&"--new-session".to_string())
+        );
+
+        config.new_session = Some(true);
+        let args = WrappedCommandBuilder::new(config.clone())
+            .build_args()
+            .unwrap();
+        assert!(args.contains(&"--new-session".to_string()));
+
+        // An explicit value wins over the harden preset
+        config.new_session = Some(false);
+        config.harden = true;
+        let args = WrappedCommandBuilder::new(config).build_args().unwrap();
+        assert!(!args.contains(&"--new-session".to_string()));
+    }
+
+    #[test]
+    fn test_build_args_lock_file() {
+        let mut config = create_test_config();
+        config.lock_file = vec!["/tmp/build.lock".to_string(), "~/cache.lock".to_string()];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        let home = std::env::var("HOME").unwrap();
+        let locks: Vec<_> = args
+            .iter()
+            .enumerate()
+            .filter(|(_, arg)| *arg == "--lock-file")
+            .map(|(idx, _)| args[idx + 1].clone())
+            .collect();
+        assert_eq!(
+            locks,
+            [
+                "/tmp/build.lock".to_string(),
+                format!("{}/cache.lock", home)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_args_dir() {
+        let mut config = create_test_config();
+        config.dir = vec!["/app/cache".to_string(), "~/.cache".to_string()];
+        config.bind = vec!["/src:/app/cache/src".to_string()];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        let dir_idx = args.iter().position(|x| x == "--dir").unwrap();
+        assert_eq!(args[dir_idx + 1], "/app/cache");
+        assert_eq!(args[dir_idx + 2], "--dir");
+        assert!(!args[dir_idx + 3].contains('~'));
+
+        // Directories are created before binds
+        let bind_idx = args.iter().position(|x| x == "--bind").unwrap();
+        assert!(dir_idx < bind_idx);
+    }
+
+    #[test]
+    fn test_build_args_ro_bind() {
+        let mut config = create_test_config();
+        config.ro_bind = vec!["/usr".into()];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        assert!(args.contains(&"--ro-bind".to_string()));
+        assert!(args.contains(&"/usr".to_string()));
+    }
+
+    #[test]
+    fn test_build_args_bind_try() {
+        let mut config = create_test_config();
+        config.bind_try = vec!["~/.cache/tool:/cache".to_string(), "invalid".to_string()];
+        config.ro_bind_try = vec!["/etc/optional".to_string()];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let (args, warnings) = builder.build_args_with_warnings().unwrap();
+
+        let bind_idx = args.iter().position(|x| x == "--bind-try").unwrap();
+        assert!(!args[bind_idx + 1].contains('~'));
+        assert_eq!(args[bind_idx + 2], "/cache");
+        assert!(!args.contains(&"--bind".to_string()));
+
+        let ro_bind_idx = args.iter().position(|x| x == "--ro-bind-try").unwrap();
+        assert_eq!(args[ro_bind_idx + 1], "/etc/optional");
+        assert_eq!(args[ro_bind_idx + 2], "/etc/optional");
+        assert!(!args.contains(&"--ro-bind".to_string()));
+
+        assert_eq!(
+            warnings,
+            vec![BuildWarning::InvalidBind("invalid".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_build_args_dev_bind() {
+        let mut config = create_test_config();
+        config.dev_bind = vec!["/dev/null".to_string()];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        assert!(args.contains(&"--dev-bind".to_string()));
+        assert!(args.contains(&"/dev/null".to_string()));
+    }
+
+    #[test]
+    fn test_build_args_tmpfs() {
+        let mut config = create_test_config();
+        config.tmpfs = vec!["/tmp".to_string(), "/var/tmp".to_string()];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        assert!(args.contains(&"--tmpfs".to_string()));
+        assert!(args.contains(&"/tmp".to_string()));
+        assert!(args.contains(&"/var/tmp".to_string()));
+    }
+
+    #[test]
+    fn test_build_args_tmp_overlay() {
+        let mut config = create_test_config();
+        config.overlay = vec![Overlay {
+            src: "/srv/app".to_string(),
+            dst: "/app".to_string(),
+            upper: None,
+            work: None,
+            tmp: true,
+        }];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        let src_idx = args.iter().position(|x| x == "--overlay-src").unwrap();
+        assert_eq!(args[src_idx + 1], "/srv/app");
+        assert_eq!(args[src_idx + 2], "--tmp-overlay");
+        assert_eq!(args[src_idx + 3], "/app");
+        assert!(!args.contains(&"--overlay".to_string()));
+    }
+
+    #[test]
+    fn test_build_args_persistent_overlay() {
+        let mut config = create_test_config();
+        config.overlay = vec![Overlay {
+            src: "/srv/app".to_string(),
+            dst: "/app".to_string(),
+            upper: Some("/var/upper".to_string()),
+            work: Some("/var/work".to_string()),
+            tmp: false,
+        }];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        let src_idx = args.iter().position(|x| x == "--overlay-src").unwrap();
+        assert_eq!(args[src_idx + 1], "/srv/app");
+        assert_eq!(args[src_idx + 2], "--overlay");
+        assert_eq!(args[src_idx + 3], "/var/upper");
+        assert_eq!(args[src_idx + 4], "/var/work");
+        assert_eq!(args[src_idx + 5], "/app");
+    }
+
+    #[test]
+    fn test_build_args_overlay_missing_work_skipped() {
+        let mut config = create_test_config();
+        config.overlay = vec![Overlay {
+            src: "/srv/app".to_string(),
+            dst: "/app".to_string(),
+            upper: Some("/var/upper".to_string()),
+            work: None,
+            tmp: false,
+        }];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        assert!(!args.contains(&"--overlay-src".to_string()));
+        assert!(!args.contains(&"--overlay".to_string()));
+    }
+
+    #[test]
+    fn test_build_args_remount_ro() {
+        let mut config = create_test_config();
+        config.remount_ro = vec!["/app/vendor".to_string(), "~/.cache".to_string()];
+        config.bind = vec!["/srv/app:/app".to_string()];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        let remount_idx = args.iter().position(|x| x == "--remount-ro").unwrap();
+        assert_eq!(args[remount_idx + 1], "/app/vendor");
+        assert_eq!(args[remount_idx + 2], "--remount-ro");
+        assert!(!args[remount_idx + 3].contains('~'));
+
+        // Remounts come after the binds
+        let bind_idx = args.iter().position(|x| x == "--bind").unwrap();
+        assert!(bind_idx < remount_idx);
+    }
+
+    #[test]
+    fn test_build_args_env() {
+        let mut config = create_test_config();
+        config
+            .env
+            .insert("NODE_ENV".to_string(), "production".to_string());
+        config.env.insert("DEBUG".to_string(), "true".to_string());
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        let setenv_count = args.iter().filter(|x| *x == "--setenv").count();
+        assert_eq!(setenv_count, 2);
+        assert!(args.contains(&"NODE_ENV".to_string()));
+        assert!(args.contains(&"production".to_string()));
+    }
+
+    #[test]
+    fn test_build_args_env_expansion() {
+        unsafe {
+            std::env::set_var("SHWRAP_TEST_ENV_EXPANSION", "expanded");
+        }
+
+        let mut config = create_test_config();
+        config
+            .env
+            .insert("HOME_BIN".to_string(), "~/bin".to_string());
+        config.env.insert(
+            "FROM_VAR".to_string(),
+            "$SHWRAP_TEST_ENV_EXPANSION/bin:/usr/bin".to_string(),
+        );
+        config
+            .env
+            .insert("PLAIN".to_string(), "no tokens here".to_string());
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        let value_of = |key: &str| {
+            let idx = args.iter().position(|x| x == key).unwrap();
+            args[idx + 1].clone()
+        };
+        assert!(!value_of("HOME_BIN").contains('~'));
+        assert!(value_of("HOME_BIN").ends_with("/bin"));
+        assert_eq!(value_of("FROM_VAR"), "expanded/bin:/usr/bin");
+        assert_eq!(value_of("PLAIN"), "no tokens here");
+    }
+
+    #[test]
+    fn test_build_args_env_expansion_keeps_literal_dollar() {
+        let mut config = create_test_config();
+        config
+            .env
+            .insert("PRICE".to_string(), "costs 5$".to_string());
+        config.env.insert(
+            "UNDEFINED".to_string(),
+            "$SHWRAP_TEST_UNDEFINED_VARIABLE".to_string(),
+        );
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        // Bare `$` and undefined variables fall back to the literal value
+        assert!(args.contains(&"costs 5$".to_string()));
+        assert!(args.contains(&"$SHWRAP_TEST_UNDEFINED_VARIABLE".to_string()));
+    }
+
+    #[test]
+    fn test_build_args_env_deterministic_order() {
+        let mut config = create_test_config();
+        for key in ["ZETA", "ALPHA", "MIKE", "BRAVO", "YANKEE", "DELTA"] {
+            config.env.insert(key.to_string(), key.to_lowercase());
+        }
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        for _ in 0..10 {
+            assert_eq!(builder.build_args().unwrap(), args);
+        }
+
+        let keys: Vec<_> = args
+            .iter()
+            .enumerate()
+            .filter(|(_, arg)| *arg == "--setenv")
+            .map(|(idx, _)| args[idx + 1].as_str())
+            .collect();
+        assert_eq!(keys, ["ALPHA", "BRAVO", "DELTA", "MIKE", "YANKEE", "ZETA"]);
+    }
+
+    #[test]
+    fn test_parse_env_file() {
+        let pairs = parse_env_file(indoc! {r#"
+            # Database settings
+            DB_HOST=localhost
+
+            DB_PASSWORD = "s3cr=t"
+            GREETING='hello world'
+            EMPTY=
+        "#})
+        .unwrap();
+
+        assert_eq!(
+            pairs,
+            [
+                ("DB_HOST".to_string(), "localhost".to_string()),
+                ("DB_PASSWORD".to_string(), "s3cr=t".to_string()),
+                ("GREETING".to_string(), "hello world".to_string()),
+                ("EMPTY".to_string(), "".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_env_file_invalid_line() {
+        let err = parse_env_file("VALID=1\nINVALID\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+
+        assert!(parse_env_file("=value").is_err());
+    }
+
+    #[test]
+    fn test_build_args_env_file_precedence() {
+        let mut first = NamedTempFile::new().unwrap();
+        writeln!(first, "SHARED=first\nFIRST_ONLY=1\nINLINE=file").unwrap();
+        let mut second = NamedTempFile::new().unwrap();
+        writeln!(second, "SHARED=second").unwrap();
+
+        let mut config = create_test_config();
+        config.env_file = vec![
+            first.path().to_string_lossy().to_string(),
+            second.path().to_string_lossy().to_string(),
+        ];
+        config
+            .env
+            .insert("INLINE".to_string(), "inline".to_string());
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+
+        let setenv: Vec<_> = args
+            .iter()
+            .enumerate()
+            .filter(|(_, arg)| *arg == "--setenv")
+            .map(|(idx, _)| (args[idx + 1].as_str(), args[idx + 2].as_str()))
+            .collect();
+        assert_eq!(
+            setenv,
+            [
+                ("FIRST_ONLY", "1"),
+                ("INLINE", "inline"),
+                ("SHARED", "second")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_args_env_file_unreadable() {
         let mut config = create_test_config();
-        config.bind = vec!["/src:/dest".to_string()];
+        config.env_file = vec!["/nonexistent/.env".to_string()];
 
         let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
+        let err = builder.build_args().unwrap_err();
 
-        let bind_idx = args.iter().position(|x| x == "--bind").unwrap();
-        assert_eq!(args[bind_idx + 1], "/src");
-        assert_eq!(args[bind_idx + 2], "/dest");
+        assert!(err.to_string().contains("/nonexistent/.env"));
     }
 
     #[test]
-    fn test_build_args_ro_bind() {
+    fn test_build_args_unset_env() {
         let mut config = create_test_config();
-        config.ro_bind = vec!["/usr".to_string()];
+        config.unset_env = vec!["DEBUG".to_string(), "VERBOSE".to_string()];
 
         let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
+        let args = builder.build_args().unwrap();
 
-        assert!(args.contains(&"--ro-bind".to_string()));
-        assert!(args.contains(&"/usr".to_string()));
+        assert!(args.contains(&"--unsetenv".to_string()));
+        assert!(args.contains(&"DEBUG".to_string()));
+        assert!(args.contains(&"VERBOSE".to_string()));
     }
 
     #[test]
-    fn test_build_args_dev_bind() {
+    fn test_build_args_dbus_none() {
+        let unsets_dbus = |config: Entry| {
+            let args = WrappedCommandBuilder::new(config).build_args().unwrap();
+            args.windows(2)
+                .any(|window| window == ["--unsetenv", "DBUS_SESSION_BUS_ADDRESS"])
+        };
+
+        // Unset by default, the args are left unchanged
+        let default_args = WrappedCommandBuilder::new(create_test_config())
+            .build_args()
+            .unwrap();
+        assert!(!default_args.contains(&"--unsetenv".to_string()));
+
+        let dbus_none = || Entry {
+            dbus: Some(Dbus::None),
+            ..create_test_config()
+        };
+        assert!(unsets_dbus(dbus_none()));
+
+        // Set explicitly or cleared with the whole environment
+        let mut config = dbus_none();
+        config.env = HashMap::from([(
+            "DBUS_SESSION_BUS_ADDRESS".to_string(),
+            "unix:path=/tmp/bus".to_string(),
+        )]);
+        assert!(!unsets_dbus(config));
+        let mut config = dbus_none();
+        config.harden = true;
+        assert!(!unsets_dbus(config));
+        let mut config = dbus_none();
+        config.dbus = Some(Dbus::Session);
+        assert!(!unsets_dbus(config));
+    }
+
+    #[test]
+    fn test_unset_env_keys() {
+        let names = ["AWS_*".to_string(), "DEBUG".to_string()];
+        let host_keys = [
+            "AWS_SECRET_ACCESS_KEY",
+            "HOME",
+            "AWS_ACCESS_KEY_ID",
+            "MY_AWS_X",
+        ]
+        .map(String::from);
+
+        assert_eq!(
+            unset_env_keys(&names, host_keys.clone()),
+            vec!["AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY", "DEBUG"]
+        );
+        // A pattern matching nothing unsets nothing, a lone `*` unsets everything
+        assert_eq!(
+            unset_env_keys(&["GCP_*".to_string()], host_keys.clone()),
+            Vec::<String>::new()
+        );
+        assert_eq!(unset_env_keys(&["*".to_string()], host_keys).len(), 4);
+    }
+
+    #[test]
+    fn test_plan() {
         let mut config = create_test_config();
+        config.share = vec!["network".to_string(), "user".to_string()];
+        config.bind = vec!["/srv/app:/app".to_string()];
+        config.ro_bind = vec!["/usr".into()];
         config.dev_bind = vec!["/dev/null".to_string()];
+        config.tmpfs = vec!["/tmp".to_string()];
+        config.env.insert("LANG".to_string(), "C".to_string());
+
+        let plan = WrappedCommandBuilder::new(config).plan().unwrap();
+
+        assert_eq!(plan.unshared, vec!["pid", "ipc", "uts", "cgroup"]);
+        assert_eq!(plan.shared, vec!["user", "network"]);
+        assert_eq!(
+            plan.mounts,
+            vec![
+                Mount::Bind {
+                    mode: BindMode::ReadWrite,
+                    optional: false,
+                    src: "/srv/app".to_string(),
+                    dst: "/app".to_string(),
+                },
+                Mount::same_path(BindMode::ReadOnly, false, "/usr"),
+                Mount::Tmpfs("/tmp".to_string()),
+                Mount::same_path(BindMode::Device, false, "/dev/null"),
+            ]
+        );
+        assert_eq!(plan.env.get("LANG").unwrap(), "C");
+    }
 
+    #[test]
+    fn test_plan_matches_args() {
+        let mut config = create_test_config();
+        config.harden = true;
+        config.ro_bind = vec![RoBind::Tree {
+            path: "/home".to_string(),
+            exclude: vec!["/home/.ssh".to_string()],
+        }];
         let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
 
-        assert!(args.contains(&"--dev-bind".to_string()));
-        assert!(args.contains(&"/dev/null".to_string()));
+        let plan = builder.plan().unwrap();
+        assert!(plan.drop_caps && plan.clear_env && plan.die_with_parent);
+        assert_eq!(plan.mounts[1], Mount::Tmpfs("/home/.ssh".to_string()));
+        assert_eq!(plan.to_args(), builder.build_args().unwrap());
     }
 
     #[test]
-    fn test_build_args_tmpfs() {
+    fn test_build_args_relative_binds() {
         let mut config = create_test_config();
-        config.tmpfs = vec!["/tmp".to_string(), "/var/tmp".to_string()];
-
-        let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
-
-        assert!(args.contains(&"--tmpfs".to_string()));
-        assert!(args.contains(&"/tmp".to_string()));
-        assert!(args.contains(&"/var/tmp".to_string()));
+        config.ro_bind = vec!["./scripts".into(), "/usr".into()];
+        config.bind = vec!["cache:/cache".to_string()];
+
+        let args = WrappedCommandBuilder::new(config)
+            .with_base_dir("/srv/project")
+            .build_args()
+            .unwrap();
+
+        assert!(
+            args.windows(3)
+                .any(|window| window
+                    == ["--ro-bind", "/srv/project/scripts", "/srv/project/scripts"])
+        );
+        assert!(
+            args.windows(3)
+                .any(|window| window == ["--ro-bind", "/usr", "/usr"])
+        );
+        assert!(
+            args.windows(3)
+                .any(|window| window == ["--bind", "/srv/project/cache", "/cache"])
+        );
     }
 
     #[test]
-    fn test_build_args_env() {
+    fn test_build_args_unset_all_env() {
         let mut config = create_test_config();
         config
             .env
             .insert("NODE_ENV".to_string(), "production".to_string());
-        config.env.insert("DEBUG".to_string(), "true".to_string());
+        config.env_prefix_passthrough = vec!["PATH".to_string()];
+
+        let args = WrappedCommandBuilder::new(config)
+            .unset_all_env()
+            .build_args()
+            .unwrap();
+
+        let clearenv_idx = args.iter().position(|arg| arg == "--clearenv").unwrap();
+        let setenv: Vec<_> = args
+            .windows(3)
+            .enumerate()
+            .filter(|(_, window)| window[0] == "--setenv")
+            .map(|(index, window)| (index, window[1].as_str(), window[2].as_str()))
+            .collect();
+        assert_eq!(setenv.len(), 1);
+        assert_eq!((setenv[0].1, setenv[0].2), ("NODE_ENV", "production"));
+        assert!(clearenv_idx < setenv[0].0);
+    }
+
+    #[test]
+    #[cfg(not(target_os = "linux"))]
+    fn test_exec_unsupported_platform() {
+        let builder = WrappedCommandBuilder::new(create_test_config());
+        let err = builder.exec("true", &[]).unwrap_err();
+        assert_eq!(err.to_string(), UNSUPPORTED_PLATFORM);
+    }
 
+    #[test]
+    fn test_show_injected_args() {
+        let mut config = create_test_config();
+        config.share = NAMESPACES.iter().map(|ns| ns.to_string()).collect();
+        config.prepend_args = vec!["--disable-proto=delete".to_string()];
+        config.append_args = vec!["--trace-warnings".to_string()];
         let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
 
-        let setenv_count = args.iter().filter(|x| *x == "--setenv").count();
-        assert_eq!(setenv_count, 2);
-        assert!(args.contains(&"NODE_ENV".to_string()));
-        assert!(args.contains(&"production".to_string()));
+        let args = ["app.js".to_string()];
+        assert_eq!(
+            builder.show("node", &args).unwrap(),
+            "bwrap node --disable-proto=delete app.js --trace-warnings"
+        );
+        assert!(builder.show_argv("node", &args).unwrap().ends_with(&[
+            "node".to_string(),
+            "--disable-proto=delete".to_string(),
+            "app.js".to_string(),
+            "--trace-warnings".to_string(),
+        ]));
     }
 
     #[test]
-    fn test_build_args_unset_env() {
-        let mut config = create_test_config();
-        config.unset_env = vec!["DEBUG".to_string(), "VERBOSE".to_string()];
+    fn test_overlay_cache() {
+        let mut config = Config::from_yaml(indoc! {"
+            npm:
+              overlay_cache: true
+              overlay:
+                - src: /srv/app/node_modules
+                  dst: /app/node_modules
+                - src: /srv/app/dist
+                  dst: /app/dist
+                  tmp: true
+            yarn:
+              overlay_cache: true
+              overlay:
+                - src: /srv/app/node_modules
+                  dst: /app/node_modules
+        "})
+        .unwrap();
+        config.path = Some(PathBuf::from("/srv/app/.shwrap.yaml"));
+
+        let upper = |command: &str| {
+            let plan = WrappedCommandBuilder::from_config(&config, command)
+                .unwrap()
+                .plan()
+                .unwrap();
+            match &plan.mounts[0] {
+                Mount::Overlay {
+                    upper: Some((upper, work)),
+                    ..
+                } => {
+                    assert_eq!(Path::new(upper).parent(), Path::new(work).parent());
+                    upper.clone()
+                }
+                mount => panic!("unexpected mount {:?}", mount),
+            }
+        };
+
+        // Stable across builders, distinct per command
+        let cached = upper("npm");
+        assert_eq!(upper("npm"), cached);
+        assert_ne!(upper("yarn"), cached);
+        assert!(cached.starts_with(&ConfigLoader::get_cache_dir().display().to_string()));
+        assert!(cached.ends_with("/upper"));
+
+        // Temporary overlays are still discarded
+        let plan = WrappedCommandBuilder::from_config(&config, "npm")
+            .unwrap()
+            .plan()
+            .unwrap();
+        assert!(matches!(
+            &plan.mounts[1],
+            Mount::Overlay { upper: None, .. }
+        ));
+    }
 
-        let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
+    #[test]
+    fn test_build_args_keep_env() {
+        let mut config = create_test_config();
+        config.env.insert("FOO".to_string(), "config".to_string());
+
+        let keys = ["FOO".to_string(), "UNSET".to_string()];
+        let builder = WrappedCommandBuilder::new(config).keep_env(&keys, |key| {
+            (key == "FOO").then(|| "host $value".to_string())
+        });
+        let args = builder.build_args().unwrap();
+
+        let setenv: Vec<_> = args
+            .windows(3)
+            .filter(|window| window[0] == "--setenv")
+            .map(|window| (window[1].as_str(), window[2].as_str()))
+            .collect();
+        assert_eq!(setenv, vec![("FOO", "host $value")]);
+    }
 
-        assert!(args.contains(&"--unsetenv".to_string()));
-        assert!(args.contains(&"DEBUG".to_string()));
-        assert!(args.contains(&"VERBOSE".to_string()));
+    #[test]
+    fn test_split_env_prefix() {
+        let args: Vec<String> = ["FOO=1", "BAR=2", "xyz", "BAZ=3"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let (vars, args) = split_env_prefix(&args);
+        assert_eq!(args, ["xyz", "BAZ=3"]);
+
+        let builder = WrappedCommandBuilder::new(create_test_config()).with_env(vars);
+        let show = builder.show("cmd", args).unwrap();
+        assert!(show.contains("--setenv BAR 2 --setenv FOO 1"));
+        assert!(show.ends_with("cmd xyz BAZ=3"));
+
+        let args = ["1A=b".to_string(), "=c".to_string()];
+        assert_eq!(split_env_prefix(&args), (vec![], &args[..]));
     }
 
     #[test]
     fn test_build_args_combined() {
         let mut config = create_test_config();
         config.share = vec!["user".to_string()]; // Share only user namespace
-        config.ro_bind = vec!["/usr".to_string()];
+        config.ro_bind = vec!["/usr".into()];
         config.env.insert("TEST".to_string(), "value".to_string());
 
         let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
+        let args = builder.build_args().unwrap();
 
         // Check all types are present
         assert!(args.contains(&"--unshare-net".to_string()));
@@ -276,7 +2159,7 @@ mod tests {
         config.share = vec!["user".to_string()]; // Share user, unshare rest
 
         let builder = WrappedCommandBuilder::new(config);
-        let cmd = builder.show("node", &["script.js".to_string()]);
+        let cmd = builder.show("node", &["script.js".to_string()]).unwrap();
 
         assert!(cmd.starts_with("bwrap"));
         assert!(cmd.contains("--unshare-net"));
@@ -284,18 +2167,79 @@ mod tests {
         assert!(cmd.contains("script.js"));
     }
 
+    #[test]
+    fn test_from_config() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              ro_bind:
+                - /usr
+            node:
+              extends: base
+              share:
+                - network
+            off:
+              enabled: false
+        "})
+        .unwrap();
+
+        let builder = WrappedCommandBuilder::from_config(&config, "node").unwrap();
+        let cmd = builder.show("node", &["app.js".to_string()]).unwrap();
+        assert!(cmd.starts_with("bwrap "));
+        assert!(cmd.contains("--ro-bind /usr /usr"));
+        assert!(!cmd.contains("--unshare-net"));
+        assert!(cmd.ends_with(" node app.js"));
+
+        let err = WrappedCommandBuilder::from_config(&config, "missing")
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("No configuration found for command 'missing'")
+        );
+
+        let err = WrappedCommandBuilder::from_config(&config, "off")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("is disabled"));
+    }
+
+    #[test]
+    fn test_from_entry() {
+        let mut config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              ro_bind:
+                - ./vendor
+            off:
+              extends: base
+              enabled: false
+        "})
+        .unwrap();
+        config.base_dir = Some(PathBuf::from("/srv/project"));
+
+        // Disabled entries are still resolved, as from_config would
+        let entry = config.get_command("off").unwrap();
+        let builder = WrappedCommandBuilder::from_entry(&config, "off", entry);
+        let args = builder.build_args().unwrap();
+        assert!(args.windows(3).any(|window| window
+            == ["--ro-bind", "/srv/project/vendor", "/srv/project/vendor"]));
+    }
+
     #[test]
     fn test_show_command_with_multiple_args() {
         let config = create_test_config();
         let builder = WrappedCommandBuilder::new(config);
-        let cmd = builder.show(
-            "git",
-            &[
-                "commit".to_string(),
-                "-m".to_string(),
-                "message".to_string(),
-            ],
-        );
+        let cmd = builder
+            .show(
+                "git",
+                &[
+                    "commit".to_string(),
+                    "-m".to_string(),
+                    "message".to_string(),
+                ],
+            )
+            .unwrap();
 
         assert!(cmd.contains("git"));
         assert!(cmd.contains("commit"));
@@ -307,7 +2251,7 @@ mod tests {
     fn test_empty_config() {
         let config = create_test_config();
         let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
+        let args = builder.build_args().unwrap();
 
         // Empty config should unshare all namespaces by default
         assert!(args.contains(&"--unshare-net".to_string()));
@@ -324,7 +2268,7 @@ mod tests {
         config.bind = vec!["~/.config:~/.config".to_string()];
 
         let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
+        let args = builder.build_args().unwrap();
 
         // shellexpand should expand ~ to home directory
         let bind_idx = args.iter().position(|x| x == "--bind").unwrap();
@@ -339,7 +2283,7 @@ mod tests {
         config.bind = vec!["invalid".to_string()];
 
         let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
+        let args = builder.build_args().unwrap();
 
         // Should not add invalid bind to args (only warning printed)
         // Count --bind flags, should be 0 for invalid format
@@ -347,11 +2291,56 @@ mod tests {
         assert_eq!(bind_count, 0);
     }
 
+    #[test]
+    fn test_invalid_bind_format_warning() {
+        let mut config = create_test_config();
+        config.bind = vec!["invalid".to_string(), "/src:/dest".to_string()];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let (args, warnings) = builder.build_args_with_warnings().unwrap();
+
+        assert_eq!(
+            warnings,
+            vec![BuildWarning::InvalidBind("invalid".to_string())]
+        );
+        assert_eq!(warnings[0].to_string(), "invalid bind format 'invalid'");
+        assert_eq!(args.iter().filter(|x| *x == "--bind").count(), 1);
+    }
+
+    #[test]
+    fn test_invalid_overlay_warning() {
+        let mut config = create_test_config();
+        config.overlay = vec![Overlay {
+            src: "/srv/app".to_string(),
+            dst: "/app".to_string(),
+            upper: None,
+            work: None,
+            tmp: false,
+        }];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let (_, warnings) = builder.build_args_with_warnings().unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(warnings[0], BuildWarning::InvalidOverlay(_)));
+    }
+
+    #[test]
+    fn test_valid_config_has_no_warnings() {
+        let mut config = create_test_config();
+        config.bind = vec!["/src:/dest".to_string()];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let (_, warnings) = builder.build_args_with_warnings().unwrap();
+
+        assert!(warnings.is_empty());
+    }
+
     #[test]
     fn test_unshare_all_by_default() {
         let config = create_test_config();
         let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
+        let args = builder.build_args().unwrap();
 
         // All namespaces should be unshared by default
         assert!(args.contains(&"--unshare-net".to_string()));
@@ -368,7 +2357,7 @@ mod tests {
         config.share = vec!["user".to_string(), "network".to_string()];
 
         let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
+        let args = builder.build_args().unwrap();
 
         // User and network should NOT be unshared (they are shared)
         assert!(!args.contains(&"--unshare-user".to_string()));
@@ -394,7 +2383,7 @@ mod tests {
         ];
 
         let builder = WrappedCommandBuilder::new(config);
-        let args = builder.build_args();
+        let args = builder.build_args().unwrap();
 
         // No namespaces should be unshared
         assert!(!args.contains(&"--unshare-user".to_string()));
@@ -404,4 +2393,532 @@ mod tests {
         assert!(!args.contains(&"--unshare-uts".to_string()));
         assert!(!args.contains(&"--unshare-cgroup".to_string()));
     }
+
+    #[test]
+    fn test_run_pre_exec() {
+        let out_dir = tempfile::TempDir::new().unwrap();
+        let marker = out_dir.path().join("marker");
+        let mut config = create_test_config();
+        config.pre_exec = vec![
+            format!("echo first > {}", marker.display()),
+            format!("echo second >> {}", marker.display()),
+        ];
+
+        WrappedCommandBuilder::new(config).run_pre_exec().unwrap();
+
+        assert_eq!(fs::read_to_string(&marker).unwrap(), "first\nsecond\n");
+    }
+
+    #[test]
+    fn test_run_pre_exec_stops_on_failure() {
+        let out_dir = tempfile::TempDir::new().unwrap();
+        let marker = out_dir.path().join("marker");
+        let mut config = create_test_config();
+        config.pre_exec = vec!["exit 3".to_string(), format!("touch {}", marker.display())];
+
+        let err = WrappedCommandBuilder::new(config)
+            .run_pre_exec()
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "pre_exec step 'exit 3' failed with exit code 3"
+        );
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn test_show_pretty() {
+        let mut config = create_test_config();
+        config.share = vec!["user".to_string()];
+        config.bind = vec!["/tmp:/tmp".to_string(), "/srv:/data".to_string()];
+        config.env = HashMap::from([("NODE_ENV".to_string(), "production".to_string())]);
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = vec!["app.js".to_string()];
+        let pretty = builder.show_pretty("node", &args).unwrap();
+
+        let lines: Vec<_> = pretty.lines().collect();
+        assert_eq!(lines[0], "bwrap \\");
+        assert!(lines.contains(&"  --bind /tmp /tmp \\"));
+        assert!(lines.contains(&"  --bind /srv /data \\"));
+        assert!(lines.contains(&"  --setenv NODE_ENV production \\"));
+        assert_eq!(lines.last(), Some(&"  node app.js"));
+
+        // Joining the lines gives back the single-line form
+        assert_eq!(
+            pretty.replace(" \\\n  ", " "),
+            builder.show("node", &args).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_build_args_ro_bind_exclude() {
+        let mut config = create_test_config();
+        config.ro_bind = vec![
+            RoBind::Tree {
+                path: "/usr".to_string(),
+                exclude: vec!["/usr/local".to_string(), "/opt".to_string()],
+            },
+            "/lib".into(),
+        ];
+
+        let (args, warnings) = WrappedCommandBuilder::new(config)
+            .build_args_with_warnings()
+            .unwrap();
+
+        let bind_idx = args.iter().position(|arg| arg == "--ro-bind").unwrap();
+        assert_eq!(
+            args[bind_idx..bind_idx + 8],
+            [
+                "--ro-bind",
+                "/usr",
+                "/usr",
+                "--ro-bind",
+                "/lib",
+                "/lib",
+                "--tmpfs",
+                "/usr/local"
+            ]
+        );
+        assert_eq!(
+            warnings,
+            vec![BuildWarning::InvalidExclude(
+                "/opt".to_string(),
+                "/usr".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_bwrap_version_parse() {
+        assert_eq!(
+            BwrapVersion::parse("bubblewrap 0.8.0\n"),
+            Some(BwrapVersion(0, 8, 0))
+        );
+        assert_eq!(
+            BwrapVersion::parse("bubblewrap 0.11"),
+            Some(BwrapVersion(0, 11, 0))
+        );
+        assert_eq!(BwrapVersion::parse(""), None);
+        assert_eq!(BwrapVersion::parse("bubblewrap unknown"), None);
+    }
+
+    #[test]
+    fn test_check_version_gates_recent_flags() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo bubblewrap 0.8.0", "bwrap"]);
+        let version = probe_version(cmd).unwrap();
+        assert_eq!(version, BwrapVersion(0, 8, 0));
+
+        let mut config = create_test_config();
+        config.overlay = vec![Overlay {
+            src: "/srv".to_string(),
+            dst: "/data".to_string(),
+            upper: None,
+            work: None,
+            tmp: true,
+        }];
+        let builder = WrappedCommandBuilder::new(config);
+
+        let err = builder.check_version(&version).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "bwrap 0.8.0 does not support --overlay-src (requires bwrap 0.9.0 or newer)"
+        );
+        builder.check_version(&BwrapVersion(0, 9, 0)).unwrap();
+        WrappedCommandBuilder::new(create_test_config())
+            .check_version(&version)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_build_args_env_prefix_passthrough() {
+        // SAFETY: the variables are only read by this test
+        unsafe {
+            std::env::set_var("SHWRAP_PREFIX_TEST_ONE", "one");
+            std::env::set_var("SHWRAP_PREFIX_TEST_TWO", "two");
+            std::env::set_var("SHWRAP_OTHER_PREFIX_TEST", "other");
+        }
+
+        let mut config = create_test_config();
+        config.env_prefix_passthrough = vec!["SHWRAP_PREFIX_TEST_".to_string()];
+        config.env = HashMap::from([("SHWRAP_PREFIX_TEST_TWO".to_string(), "inline".to_string())]);
+
+        let args = WrappedCommandBuilder::new(config).build_args().unwrap();
+
+        unsafe {
+            std::env::remove_var("SHWRAP_PREFIX_TEST_ONE");
+            std::env::remove_var("SHWRAP_PREFIX_TEST_TWO");
+            std::env::remove_var("SHWRAP_OTHER_PREFIX_TEST");
+        }
+
+        let setenv: Vec<_> = args
+            .windows(3)
+            .filter(|window| window[0] == "--setenv")
+            .map(|window| (window[1].as_str(), window[2].as_str()))
+            .collect();
+        assert_eq!(
+            setenv,
+            vec![
+                ("SHWRAP_PREFIX_TEST_ONE", "one"),
+                ("SHWRAP_PREFIX_TEST_TWO", "inline")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_show_args() {
+        let builder = WrappedCommandBuilder::new(create_test_config());
+        let args = ["app.js".to_string()];
+        let bwrap_args = builder.build_args().unwrap();
+
+        assert_eq!(
+            builder.show_args(&bwrap_args, "node", &args),
+            builder.show("node", &args).unwrap()
+        );
+        assert_eq!(
+            builder.show_args(&["--unshare-all".to_string()], "node", &args),
+            "bwrap --unshare-all node app.js"
+        );
+    }
+
+    #[test]
+    fn test_build_args_root_tmpfs() {
+        let mut config = create_test_config();
+        config.root_tmpfs = true;
+        config.dir = vec!["/work".to_string()];
+        config.bind = vec!["/tmp:/tmp".to_string()];
+        config.ro_bind = vec!["/usr".into()];
+
+        let (args, warnings) = WrappedCommandBuilder::new(config)
+            .build_args_with_warnings()
+            .unwrap();
+
+        let root_idx = args.iter().position(|arg| arg == "--tmpfs").unwrap();
+        assert_eq!(args[root_idx + 1], "/");
+        let bind_idx = args.iter().position(|arg| arg == "--bind").unwrap();
+        let ro_bind_idx = args.iter().position(|arg| arg == "--ro-bind").unwrap();
+        let dir_idx = args.iter().position(|arg| arg == "--dir").unwrap();
+        assert!(root_idx < dir_idx);
+        assert!(root_idx < bind_idx);
+        assert!(root_idx < ro_bind_idx);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_build_args_root_tmpfs_without_binds() {
+        let mut config = create_test_config();
+        config.root_tmpfs = true;
+
+        let (args, warnings) = WrappedCommandBuilder::new(config)
+            .build_args_with_warnings()
+            .unwrap();
+
+        assert!(args.windows(2).any(|window| window == ["--tmpfs", "/"]));
+        assert_eq!(warnings, vec![BuildWarning::EmptyRootTmpfs]);
+    }
+
+    #[test]
+    fn test_missing_proc() {
+        let builder = WrappedCommandBuilder::new(create_test_config());
+        assert!(builder.missing_proc());
+        assert!(
+            !builder
+                .build_args()
+                .unwrap()
+                .contains(&"--proc".to_string())
+        );
+
+        let mut config = create_test_config();
+        config.share = vec!["pid".to_string()];
+        assert!(!WrappedCommandBuilder::new(config).missing_proc());
+
+        let mut config = create_test_config();
+        config.ro_bind = vec!["/".into()];
+        assert!(!WrappedCommandBuilder::new(config).missing_proc());
+
+        let mut config = create_test_config();
+        config.proc = Some("/proc".to_string());
+        let builder = WrappedCommandBuilder::new(config);
+        assert!(!builder.missing_proc());
+        let args = builder.build_args().unwrap();
+        assert!(args.windows(2).any(|window| window == ["--proc", "/proc"]));
+    }
+
+    #[test]
+    fn test_build_args_auto_proc() {
+        let mut config = create_test_config();
+        config.auto_proc = true;
+        let builder = WrappedCommandBuilder::new(config);
+        assert!(!builder.missing_proc());
+        let args = builder.build_args().unwrap();
+        assert!(args.windows(2).any(|window| window == ["--proc", "/proc"]));
+
+        // Nothing to inject when the pid namespace is shared
+        let mut config = create_test_config();
+        config.auto_proc = true;
+        config.share = vec!["pid".to_string()];
+        let args = WrappedCommandBuilder::new(config).build_args().unwrap();
+        assert!(!args.contains(&"--proc".to_string()));
+    }
+
+    #[test]
+    fn test_build_args_allow_tty() {
+        let mut config = create_test_config();
+        config.allow_tty = true;
+        config.harden = true;
+
+        let args = WrappedCommandBuilder::new(config).build_args().unwrap();
+
+        assert!(
+            args.windows(3)
+                .any(|window| window == ["--dev-bind", "/dev/tty", "/dev/tty"])
+        );
+        assert!(
+            args.windows(3)
+                .any(|window| window == ["--dev-bind-try", "/dev/console", "/dev/console"])
+        );
+        assert!(!args.contains(&"--new-session".to_string()));
+
+        let args = WrappedCommandBuilder::new(create_test_config())
+            .build_args()
+            .unwrap();
+        assert!(!args.contains(&"/dev/tty".to_string()));
+    }
+
+    #[test]
+    fn test_show_explained() {
+        let mut config = create_test_config();
+        config.ro_bind = vec!["/usr".into()];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let explained = builder.show_explained("node", &[]).unwrap();
+        let lines: Vec<_> = explained.lines().collect();
+
+        assert!(lines.iter().any(|line| line.starts_with("--unshare-net ")
+            && line.ends_with("# isolate network: no internet access")));
+        assert!(
+            lines
+                .iter()
+                .any(|line| line.starts_with("--ro-bind /usr /usr ")
+                    && line.ends_with("# mount read-only"))
+        );
+        assert!(lines.last().unwrap().starts_with("node "));
+
+        // Descriptions are aligned
+        let columns: std::collections::HashSet<_> =
+            lines.iter().map(|line| line.find(" # ")).collect();
+        assert_eq!(columns.len(), 1);
+    }
+
+    #[test]
+    fn test_mount_destinations() {
+        let mut config = create_test_config();
+        config.gui = true;
+        config.bind = vec!["/srv/x11:/tmp/.X11-unix".to_string()];
+        config.ro_bind = vec!["/usr".into()];
+        config.tmpfs = vec!["/tmp/cache".to_string()];
+
+        let destinations = WrappedCommandBuilder::new(config).mount_destinations();
+
+        // Explicit mounts take over the desktop access ones, parents first
+        assert_eq!(destinations, vec!["/usr", "/tmp/.X11-unix", "/tmp/cache"]);
+    }
+
+    #[test]
+    fn test_restricted_network() {
+        let mut config = create_test_config();
+        config.network = Some(Network::Restricted);
+        config.allow_hosts = vec!["registry.npmjs.org".to_string()];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let args = builder.build_args().unwrap();
+        // The network namespace is the one of the helper
+        assert!(!args.contains(&"--unshare-net".to_string()));
+        assert!(args.contains(&"--unshare-pid".to_string()));
+        assert!(builder.needs_supervision());
+
+        let shown = builder.show("npm", &[]).unwrap();
+        assert!(shown.starts_with("pasta --config-net"));
+        assert!(shown.contains(
+            "--no-map-gw -- sh -c nft \"$1\" && shift && exec \"$@\" shwrap-network \
+             '(nft filter allowing registry.npmjs.org)' bwrap "
+        ));
+        assert!(shown.contains("--ro-bind-data /etc/hosts (allowed hosts)"));
+    }
+
+    #[test]
+    fn test_build_args_glob_binds() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let libs = temp_dir.path().join("lib");
+        fs::create_dir(&libs).unwrap();
+        for name in ["libb.so", "liba.so", "notes.txt"] {
+            fs::write(libs.join(name), "").unwrap();
+        }
+        let lib = |name: &str| libs.join(name).display().to_string();
+        let pattern = format!("{}/lib*.so", libs.display());
+
+        let mut config = create_test_config();
+        config.ro_bind = vec![pattern.as_str().into()];
+        config.bind = vec![format!("{}/*.so:/opt/libs", libs.display())];
+        config.bind_try = vec![format!("{}/*.missing:/opt/none", libs.display())];
+
+        let (args, warnings) = WrappedCommandBuilder::new(config)
+            .build_args_with_warnings()
+            .unwrap();
+        assert!(warnings.is_empty());
+
+        // Sorted matches, relative structure kept under the destination
+        let binds: Vec<_> = args.windows(3).filter(|w| w[0] == "--bind").collect();
+        assert_eq!(binds.len(), 2);
+        assert_eq!(binds[0][1], lib("liba.so"));
+        assert_eq!(binds[0][2], "/opt/libs/liba.so");
+        assert_eq!(binds[1][2], "/opt/libs/libb.so");
+        let ro_binds: Vec<_> = args
+            .windows(3)
+            .filter(|w| w[0] == "--ro-bind")
+            .map(|w| w[1].clone())
+            .collect();
+        assert_eq!(ro_binds, vec![lib("liba.so"), lib("libb.so")]);
+        assert!(!args.iter().any(|arg| arg.contains("missing")));
+
+        // No match for a plain bind is reported
+        let mut config = create_test_config();
+        config.ro_bind = vec![format!("{}/*.missing", libs.display()).as_str().into()];
+        let (args, warnings) = WrappedCommandBuilder::new(config)
+            .build_args_with_warnings()
+            .unwrap();
+        assert!(!args.contains(&"--ro-bind".to_string()));
+        assert_eq!(
+            warnings,
+            vec![BuildWarning::NoGlobMatch(format!(
+                "{}/*.missing",
+                libs.display()
+            ))]
+        );
+    }
+
+    #[test]
+    fn test_build_args_mounts_parents_first() {
+        let mut config = create_test_config();
+        config.bind = vec!["/srv/cache:/usr/lib/cache".to_string()];
+        config.ro_bind = vec!["/usr/lib".into(), "/usr".into(), "/etc".into()];
+        config.dir = vec!["/usr/lib/cache/tmp".to_string()];
+        config.tmpfs = vec!["/".to_string()];
+
+        let args = WrappedCommandBuilder::new(config).build_args().unwrap();
+        let dsts: Vec<_> = args
+            .iter()
+            .enumerate()
+            .filter(|(_, arg)| ["--bind", "--ro-bind", "--dir", "--tmpfs"].contains(&arg.as_str()))
+            .map(|(index, arg)| args[index + flag_arity(arg)].as_str())
+            .collect();
+        assert_eq!(
+            dsts,
+            vec![
+                "/",
+                "/usr",
+                "/etc",
+                "/usr/lib",
+                "/usr/lib/cache",
+                "/usr/lib/cache/tmp"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_mounts() {
+        let mut config = create_test_config();
+        config.bind = vec!["/srv/app:/app".to_string(), "/srv/data:/data".to_string()];
+        config.ro_bind = vec!["/app/vendor".into(), "/data".into()];
+        config.tmpfs = vec!["/tmp".to_string()];
+
+        let nested: Vec<_> = WrappedCommandBuilder::new(config)
+            .nested_mounts()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            nested,
+            vec![
+                "'/app/vendor' (read-only) is mounted over '/app' (read-write)",
+                "'/data' (read-only) is mounted over '/data' (read-write)",
+            ]
+        );
+
+        // A read-write bind under a read-only one is applied after it
+        let mut config = create_test_config();
+        config.bind = vec!["/srv/cache:/usr/cache".to_string()];
+        config.ro_bind = vec!["/usr".into()];
+        let nested = WrappedCommandBuilder::new(config).nested_mounts();
+        assert_eq!(
+            nested[0].to_string(),
+            "'/usr/cache' (read-write) is mounted over '/usr' (read-only)"
+        );
+    }
+
+    #[test]
+    fn test_userns_disabled_by() {
+        let sysctls = |values: &'static [(&'static str, &'static str)]| {
+            move |path: &str| {
+                values
+                    .iter()
+                    .find(|(sysctl, _)| *sysctl == path)
+                    .map(|(_, value)| format!("{}\n", value))
+            }
+        };
+
+        assert_eq!(userns_disabled_by(sysctls(&[])), None);
+        assert_eq!(
+            userns_disabled_by(sysctls(&[
+                ("/proc/sys/kernel/unprivileged_userns_clone", "1"),
+                ("/proc/sys/user/max_user_namespaces", "63432"),
+            ])),
+            None
+        );
+        assert_eq!(
+            userns_disabled_by(sysctls(&[(
+                "/proc/sys/kernel/unprivileged_userns_clone",
+                "0"
+            )])),
+            Some("/proc/sys/kernel/unprivileged_userns_clone")
+        );
+        assert_eq!(
+            userns_disabled_by(sysctls(&[("/proc/sys/user/max_user_namespaces", "0")])),
+            Some("/proc/sys/user/max_user_namespaces")
+        );
+    }
+
+    #[test]
+    fn test_bwrap_program() {
+        let env = |key: &str| (key == BWRAP_PATH_VAR).then(|| "/opt/bwrap/bin/bwrap".to_string());
+        let no_env = |_: &str| None;
+
+        assert_eq!(resolve_bwrap_program(None, no_env), "bwrap");
+        assert_eq!(resolve_bwrap_program(None, env), "/opt/bwrap/bin/bwrap");
+        // The config wins over the environment
+        assert_eq!(
+            resolve_bwrap_program(Some("/usr/local/bin/bwrap-shim"), env),
+            "/usr/local/bin/bwrap-shim"
+        );
+    }
+
+    #[test]
+    fn test_show_bwrap_path() {
+        let mut config = create_test_config();
+        config.bwrap_path = Some("/usr/local/bin/bwrap-shim".to_string());
+        let builder = WrappedCommandBuilder::new(config);
+
+        assert!(
+            builder
+                .show("npm", &[])
+                .unwrap()
+                .starts_with("/usr/local/bin/bwrap-shim --unshare-user")
+        );
+        assert_eq!(
+            builder.show_argv("npm", &[]).unwrap()[0],
+            "/usr/local/bin/bwrap-shim"
+        );
+    }
 }
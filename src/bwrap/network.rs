@@ -0,0 +1,257 @@
+// Copyright (C) 2025 Pierre Le Gall
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{Context, Result};
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::config::{Entry, Network};
+
+/// User-mode network helper (from the passt project)
+pub const NETWORK_HELPER: &str = "pasta";
+
+/// Options of the helper: a new network namespace reaching the outside through
+/// the host, without forwarded ports nor access to the host services
+const HELPER_ARGS: &[&str] = &[
+    "--config-net",
+    "--quiet",
+    "--tcp-ports",
+    "none",
+    "--udp-ports",
+    "none",
+    "--tcp-ns",
+    "none",
+    "--udp-ns",
+    "none",
+    "--no-map-gw",
+];
+
+/// Firewall loading the filter in the namespace of the helper (from nftables)
+pub const FIREWALL: &str = "nft";
+
+/// Script run by the helper: load the filter, then run bwrap only if it loaded
+const FILTER_SCRIPT: &str = r#"nft "$1" && shift && exec "$@""#;
+
+/// Network of an entry with `network: restricted`
+/// bwrap runs in the network namespace of the helper, where a filter drops
+/// the packets sent to other addresses than the allowed hosts ones, with an
+/// `/etc/hosts` listing the allowed hosts only and no DNS server
+/// (`/etc/resolv.conf` is empty)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestrictedNetwork {
+    /// Path to the helper executable
+    pub helper: PathBuf,
+    /// Path to the firewall executable
+    pub firewall: PathBuf,
+    /// Path to the shell running FILTER_SCRIPT
+    pub shell: PathBuf,
+    /// Addresses of the allowed hosts
+    pub addrs: Vec<IpAddr>,
+    /// Content of `/etc/hosts` in the sandbox
+    pub hosts: String,
+}
+
+impl RestrictedNetwork {
+    /// Set up the restricted network of an entry, None if it has none
+    /// `find` looks up the helper executable, `resolve` the addresses of a host
+    pub fn from_entry(
+        config: &Entry,
+        find: impl Fn(&str) -> Option<PathBuf>,
+        resolve: impl Fn(&str) -> Result<Vec<IpAddr>>,
+    ) -> Result<Option<Self>> {
+        if config.network != Some(Network::Restricted) {
+            return Ok(None);
+        }
+
+        let helper = find(NETWORK_HELPER).context(format!(
+            "'network: restricted' needs {} (from the 'passt' package), which is not installed",
+            NETWORK_HELPER
+        ))?;
+
+        // Without the filter, any address could be reached: fail closed
+        let firewall = find(FIREWALL).context(format!(
+            "'network: restricted' needs {} (from the 'nftables' package) to filter the \
+             network, which is not installed",
+            FIREWALL
+        ))?;
+        let shell = find("sh").context("'network: restricted' needs sh, which is not found")?;
+
+        let mut entries = Vec::new();
+        for host in &config.allow_hosts {
+            for addr in resolve(host)? {
+                entries.push((addr, host.as_str()));
+            }
+        }
+
+        Ok(Some(RestrictedNetwork {
+            helper,
+            firewall,
+            shell,
+            addrs: entries.iter().map(|(addr, _)| *addr).collect(),
+            hosts: hosts_file(&entries),
+        }))
+    }
+
+    /// Helper process loading the filter then running bwrap with the
+    /// arguments added to it
+    /// bwrap does not run if the filter fails to load
+    pub fn command(&self, bwrap: &str) -> Command {
+        let script = FILTER_SCRIPT.replacen("nft", &self.firewall.to_string_lossy(), 1);
+
+        let mut cmd = Command::new(&self.helper);
+        cmd.args(HELPER_ARGS)
+            .arg("--")
+            .arg(&self.shell)
+            .args(["-c", &script, "shwrap-network"])
+            .arg(filter_rules(&self.addrs))
+            .arg(bwrap);
+        cmd
+    }
+}
+
+/// Get the helper command line prefixed to bwrap (for display)
+/// Addresses are resolved when the command runs, the filter shows the hosts
+pub fn helper_argv(allow_hosts: &[String]) -> Vec<String> {
+    let mut argv = vec![NETWORK_HELPER.to_string()];
+    argv.extend(HELPER_ARGS.iter().map(|arg| arg.to_string()));
+    argv.extend(["--", "sh", "-c", FILTER_SCRIPT, "shwrap-network"].map(String::from));
+    argv.push(format!(
+        "'(nft filter allowing {})'",
+        match allow_hosts.is_empty() {
+            true => "loopback only".to_string(),
+            false => allow_hosts.join(", "),
+        }
+    ));
+    argv
+}
+
+/// Get the nftables rules dropping the packets sent to other addresses than
+/// the given ones (and loopback)
+pub fn filter_rules(addrs: &[IpAddr]) -> String {
+    let mut rules = vec![
+        "add table inet shwrap".to_string(),
+        "add chain inet shwrap output { type filter hook output priority 0; policy drop; }"
+            .to_string(),
+        "add rule inet shwrap output oifname \"lo\" accept".to_string(),
+    ];
+    for addr in addrs {
+        let family = match addr {
+            IpAddr::V4(_) => "ip",
+            IpAddr::V6(_) => "ip6",
+        };
+        rules.push(format!(
+            "add rule inet shwrap output {} daddr {} accept",
+            family, addr
+        ));
+    }
+
+    rules.join("; ")
+}
+
+/// Resolve a host with the host resolver
+pub fn resolve_host(host: &str) -> Result<Vec<IpAddr>> {
+    let mut addrs: Vec<IpAddr> = (host, 0)
+        .to_socket_addrs()
+        .context(format!("Failed to resolve allowed host '{}'", host))?
+        .map(|addr| addr.ip())
+        .collect();
+    addrs.dedup();
+
+    Ok(addrs)
+}
+
+/// Format `/etc/hosts`, localhost included
+fn hosts_file(entries: &[(IpAddr, &str)]) -> String {
+    let mut content = String::from("127.0.0.1 localhost\n::1 localhost\n");
+    for (addr, host) in entries {
+        content.push_str(&format!("{} {}\n", addr, host));
+    }
+
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    fn restricted_entry() -> Entry {
+        Entry {
+            network: Some(Network::Restricted),
+            allow_hosts: vec!["registry.npmjs.org".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn resolve(host: &str) -> Result<Vec<IpAddr>> {
+        match host {
+            "registry.npmjs.org" => Ok(vec!["104.16.0.35".parse()?, "2606:4700::6810:23".parse()?]),
+            _ => bail!("unknown host {}", host),
+        }
+    }
+
+    #[test]
+    fn test_helper_command() {
+        let network = RestrictedNetwork::from_entry(
+            &restricted_entry(),
+            |name| Some(PathBuf::from("/usr/bin").join(name)),
+            resolve,
+        )
+        .unwrap()
+        .unwrap();
+
+        let cmd = network.command("bwrap");
+        assert_eq!(cmd.get_program(), "/usr/bin/pasta");
+        let args: Vec<_> = cmd.get_args().map(|arg| arg.to_str().unwrap()).collect();
+        assert_eq!(args[0], "--config-net");
+        let filter = args.iter().position(|arg| *arg == "--").unwrap();
+        assert_eq!(
+            args[filter..],
+            [
+                "--",
+                "/usr/bin/sh",
+                "-c",
+                r#"/usr/bin/nft "$1" && shift && exec "$@""#,
+                "shwrap-network",
+                "add table inet shwrap; \
+                 add chain inet shwrap output { type filter hook output priority 0; policy drop; }; \
+                 add rule inet shwrap output oifname \"lo\" accept; \
+                 add rule inet shwrap output ip daddr 104.16.0.35 accept; \
+                 add rule inet shwrap output ip6 daddr 2606:4700::6810:23 accept",
+                "bwrap",
+            ]
+        );
+        let shown = helper_argv(&restricted_entry().allow_hosts);
+        assert_eq!(&shown[1..filter + 2], &args[..filter + 1]);
+        assert_eq!(
+            shown.last().unwrap(),
+            "'(nft filter allowing registry.npmjs.org)'"
+        );
+
+        assert_eq!(
+            network.hosts,
+            "127.0.0.1 localhost\n::1 localhost\n\
+             104.16.0.35 registry.npmjs.org\n\
+             2606:4700::6810:23 registry.npmjs.org\n"
+        );
+    }
+
+    #[test]
+    fn test_missing_helper() {
+        let err =
+            RestrictedNetwork::from_entry(&restricted_entry(), |_| None, resolve).unwrap_err();
+        assert!(err.to_string().contains("needs pasta"));
+
+        // No filter, no network
+        let find = |name: &str| (name != FIREWALL).then(|| PathBuf::from("/usr/bin").join(name));
+        let err = RestrictedNetwork::from_entry(&restricted_entry(), find, resolve).unwrap_err();
+        assert!(err.to_string().contains("needs nft"));
+    }
+
+    #[test]
+    fn test_unrestricted() {
+        let network = RestrictedNetwork::from_entry(&Entry::default(), |_| None, resolve).unwrap();
+        assert_eq!(network, None);
+    }
+}
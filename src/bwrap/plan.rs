@@ -0,0 +1,244 @@
+// Copyright (C) 2025 Pierre Le Gall
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::BTreeMap;
+use std::path::{Component, Path};
+
+/// Access given by a bind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindMode {
+    ReadWrite,
+    ReadOnly,
+    /// Read-write, with device access
+    Device,
+}
+
+/// Mount of the sandbox
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mount {
+    /// Directory created in the sandbox
+    Dir(String),
+    /// Host path mounted in the sandbox, skipped if missing when `optional`
+    Bind {
+        mode: BindMode,
+        optional: bool,
+        src: String,
+        dst: String,
+    },
+    /// Overlay of `src`, writes are kept in `upper` (with its `work` directory)
+    /// or discarded on exit when None
+    Overlay {
+        src: String,
+        dst: String,
+        upper: Option<(String, String)>,
+    },
+    /// Empty temporary filesystem
+    Tmpfs(String),
+    /// New procfs
+    Proc(String),
+    /// File locked while the sandbox runs
+    LockFile(String),
+    /// Mount remounted read-only
+    RemountRo(String),
+}
+
+impl Mount {
+    /// Bind of a path at the same location
+    pub fn same_path(mode: BindMode, optional: bool, path: &str) -> Self {
+        Mount::Bind {
+            mode,
+            optional,
+            src: path.to_string(),
+            dst: path.to_string(),
+        }
+    }
+
+    /// Path the mount applies to
+    pub fn dst(&self) -> &str {
+        match self {
+            Mount::Bind { dst, .. } | Mount::Overlay { dst, .. } => dst,
+            Mount::Dir(path)
+            | Mount::Tmpfs(path)
+            | Mount::Proc(path)
+            | Mount::LockFile(path)
+            | Mount::RemountRo(path) => path,
+        }
+    }
+
+    fn push_args(&self, args: &mut Vec<String>) {
+        let mut push = |values: &[&str]| args.extend(values.iter().map(|value| value.to_string()));
+
+        match self {
+            Mount::Dir(path) => push(&["--dir", path]),
+            Mount::Bind {
+                mode,
+                optional,
+                src,
+                dst,
+            } => {
+                let flag = match (mode, optional) {
+                    (BindMode::ReadWrite, false) => "--bind",
+                    (BindMode::ReadWrite, true) => "--bind-try",
+                    (BindMode::ReadOnly, false) => "--ro-bind",
+                    (BindMode::ReadOnly, true) => "--ro-bind-try",
+                    (BindMode::Device, false) => "--dev-bind",
+                    (BindMode::Device, true) => "--dev-bind-try",
+                };
+                push(&[flag, src, dst]);
+            }
+            Mount::Overlay { src, dst, upper } => {
+                push(&["--overlay-src", src]);
+                match upper {
+                    Some((upper, work)) => push(&["--overlay", upper, work, dst]),
+                    None => push(&["--tmp-overlay", dst]),
+                }
+            }
+            Mount::Tmpfs(path) => push(&["--tmpfs", path]),
+            Mount::Proc(path) => push(&["--proc", path]),
+            Mount::LockFile(path) => push(&["--lock-file", path]),
+            Mount::RemountRo(path) => push(&["--remount-ro", path]),
+        }
+    }
+}
+
+/// Get the number of components of a path, `/` being 0
+pub fn path_depth(path: &str) -> usize {
+    Path::new(path)
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .count()
+}
+
+/// Sandbox described by an entry, as built by WrappedCommandBuilder::plan
+/// Values are expanded (`~` and variables), mounts are in the order bwrap applies them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SandboxPlan {
+    /// Namespaces unshared from the host, in NAMESPACES order
+    pub unshared: Vec<String>,
+    /// Namespaces shared with the host, in NAMESPACES order
+    pub shared: Vec<String>,
+    /// User and group inside the sandbox
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Kill the sandbox when shwrap dies
+    pub die_with_parent: bool,
+    /// Detach from the controlling terminal
+    pub new_session: bool,
+    /// Drop all capabilities
+    pub drop_caps: bool,
+    /// Start from an empty environment
+    pub clear_env: bool,
+    pub mounts: Vec<Mount>,
+    /// Variables set in the sandbox, sorted by name
+    pub env: BTreeMap<String, String>,
+    /// Variables unset in the sandbox
+    pub unset_env: Vec<String>,
+}
+
+impl SandboxPlan {
+    /// Get the bwrap arguments implementing the plan
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        for namespace in &self.unshared {
+            let flag = match namespace.as_str() {
+                "network" => "--unshare-net",
+                "pid" => "--unshare-pid",
+                "ipc" => "--unshare-ipc",
+                "uts" => "--unshare-uts",
+                "user" => "--unshare-user",
+                "cgroup" => "--unshare-cgroup",
+                _ => continue,
+            };
+            args.push(flag.to_string());
+        }
+
+        if let Some(uid) = self.uid {
+            args.push("--uid".to_string());
+            args.push(uid.to_string());
+        }
+        if let Some(gid) = self.gid {
+            args.push("--gid".to_string());
+            args.push(gid.to_string());
+        }
+
+        if self.die_with_parent {
+            args.push("--die-with-parent".to_string());
+        }
+        if self.new_session {
+            args.push("--new-session".to_string());
+        }
+        if self.drop_caps {
+            args.push("--cap-drop".to_string());
+            args.push("ALL".to_string());
+        }
+        // Cleared before the values below, which still apply
+        if self.clear_env {
+            args.push("--clearenv".to_string());
+        }
+
+        for mount in &self.mounts {
+            mount.push_args(&mut args);
+        }
+
+        for (key, value) in &self.env {
+            args.push("--setenv".to_string());
+            args.push(key.clone());
+            args.push(value.clone());
+        }
+        for key in &self.unset_env {
+            args.push("--unsetenv".to_string());
+            args.push(key.clone());
+        }
+
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_depth() {
+        assert_eq!(path_depth("/"), 0);
+        assert_eq!(path_depth("/usr"), 1);
+        assert_eq!(path_depth("/usr/lib/"), 2);
+    }
+
+    #[test]
+    fn test_to_args() {
+        let plan = SandboxPlan {
+            unshared: vec!["pid".to_string(), "network".to_string()],
+            shared: vec!["user".to_string()],
+            uid: Some(0),
+            clear_env: true,
+            mounts: vec![
+                Mount::same_path(BindMode::ReadOnly, false, "/usr"),
+                Mount::Bind {
+                    mode: BindMode::ReadWrite,
+                    optional: true,
+                    src: "/srv/app".to_string(),
+                    dst: "/app".to_string(),
+                },
+                Mount::Overlay {
+                    src: "/srv/data".to_string(),
+                    dst: "/data".to_string(),
+                    upper: None,
+                },
+                Mount::Tmpfs("/tmp".to_string()),
+            ],
+            env: BTreeMap::from([("LANG".to_string(), "C".to_string())]),
+            unset_env: vec!["DEBUG".to_string()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            plan.to_args().join(" "),
+            "--unshare-pid --unshare-net --uid 0 --clearenv \
+             --ro-bind /usr /usr --bind-try /srv/app /app \
+             --overlay-src /srv/data --tmp-overlay /data --tmpfs /tmp \
+             --setenv LANG C --unsetenv DEBUG"
+        );
+    }
+}
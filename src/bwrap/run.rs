@@ -0,0 +1,808 @@
+// Copyright (C) 2025 Pierre Le Gall
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::{Handle, Signals};
+use std::convert::Infallible;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::network::{RestrictedNetwork, resolve_host};
+use super::{
+    ARGS_FD_THRESHOLD, SandboxStatus, TIMEOUT_EXIT_CODE, WrappedCommandBuilder, args_size,
+    bwrap_version, check_flags, check_platform, exit_code, expand, find_executable,
+};
+
+/// Interval between two child status checks while waiting with a timeout
+const TIMEOUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+impl WrappedCommandBuilder {
+    /// Execute a command with bwrap
+    /// Returns 128 + signal if bwrap was killed by a signal, or TIMEOUT_EXIT_CODE if
+    /// the command was killed after the configured timeout
+    pub fn exec(&self, command: &str, command_args: &[String]) -> Result<i32> {
+        self.exec_args(self.build_args()?, command, command_args)
+    }
+
+    /// Execute a command with bwrap and the given arguments, built beforehand
+    /// with build_args (see exec)
+    pub fn exec_args(
+        &self,
+        bwrap_args: Vec<String>,
+        command: &str,
+        command_args: &[String],
+    ) -> Result<i32> {
+        let mut child = self.spawn(
+            Command::new(self.bwrap_program()),
+            bwrap_args,
+            vec![],
+            command,
+            command_args,
+        )?;
+
+        self.wait(&mut child)
+    }
+
+    /// Execute a command with bwrap, reporting the sandboxed process status
+    /// The exit code is TIMEOUT_EXIT_CODE if the command was killed after the configured timeout
+    pub fn exec_with_status(
+        &self,
+        command: &str,
+        command_args: &[String],
+    ) -> Result<SandboxStatus> {
+        self.run_with_status(Command::new(self.bwrap_program()), command, command_args)
+    }
+
+    /// Run bwrap (or a stand-in) with a pipe wired to --json-status-fd
+    pub(super) fn run_with_status(
+        &self,
+        mut cmd: Command,
+        command: &str,
+        command_args: &[String],
+    ) -> Result<SandboxStatus> {
+        let (mut reader, writer) = io::pipe()?;
+        let fd = writer.as_raw_fd();
+
+        cmd.arg("--json-status-fd").arg(fd.to_string());
+        let mut child = self.spawn(cmd, self.build_args()?, vec![fd], command, command_args)?;
+        // Only bwrap must hold the write end, so reading stops when it exits
+        drop(writer);
+
+        let exit_code = self.wait(&mut child)?;
+        let mut output = String::new();
+        reader.read_to_string(&mut output)?;
+
+        // bwrap reports no exit code when killed or failing before the launch
+        let report = parse_json_status(&output)?;
+        Ok(SandboxStatus {
+            child_pid: report.child_pid,
+            exit_code: report.exit_code.unwrap_or(exit_code),
+        })
+    }
+
+    /// Spawn bwrap (or a stand-in) with the given arguments running the command,
+    /// streaming the inline files
+    /// The given fds are inherited by the process
+    pub(super) fn spawn(
+        &self,
+        mut cmd: Command,
+        mut bwrap_args: Vec<String>,
+        mut inherited_fds: Vec<RawFd>,
+        command: &str,
+        command_args: &[String],
+    ) -> Result<Child> {
+        check_platform()?;
+        self.create_overlay_cache()?;
+        self.check_inherit_fds()?;
+        inherited_fds.extend(&self.config.inherit_fds);
+        let network = RestrictedNetwork::from_entry(&self.config, find_executable, resolve_host)?;
+
+        let mut data: Vec<(&str, String, Vec<u8>)> = self
+            .config
+            .files
+            .iter()
+            .map(|file| {
+                (
+                    "--file",
+                    expand(&file.dest),
+                    file.content.clone().into_bytes(),
+                )
+            })
+            .collect();
+        if let Some(network) = &network {
+            data.push((
+                "--ro-bind-data",
+                "/etc/hosts".to_string(),
+                network.hosts.clone().into_bytes(),
+            ));
+            data.push(("--ro-bind-data", "/etc/resolv.conf".to_string(), vec![]));
+        }
+
+        let mut streams = Vec::new();
+        for (flag, dest, content) in data {
+            let (reader, writer) = io::pipe()?;
+            inherited_fds.push(reader.as_raw_fd());
+            bwrap_args.push(flag.to_string());
+            bwrap_args.push(reader.as_raw_fd().to_string());
+            bwrap_args.push(dest);
+            streams.push((reader, writer, content));
+        }
+
+        let program = self.bwrap_program();
+        let runs_bwrap = cmd.get_program() == program.as_str();
+        if runs_bwrap && let Some(version) = bwrap_version(&program) {
+            let cmd_args = cmd.get_args().filter_map(|arg| arg.to_str());
+            check_flags(
+                cmd_args.chain(bwrap_args.iter().map(String::as_str)),
+                &version,
+            )?;
+        }
+
+        // Run bwrap in the network namespace of the helper
+        if let Some(network) = &network
+            && runs_bwrap
+        {
+            let mut wrapped = network.command(&program);
+            wrapped.args(cmd.get_args());
+            cmd = wrapped;
+        }
+
+        // Pass long argument lists through a pipe, they could exceed ARG_MAX (E2BIG)
+        if args_size(&bwrap_args) > ARGS_FD_THRESHOLD {
+            let (reader, writer) = io::pipe()?;
+            inherited_fds.push(reader.as_raw_fd());
+            cmd.arg("--args").arg(reader.as_raw_fd().to_string());
+            let content = bwrap_args
+                .iter()
+                .flat_map(|arg| [arg.as_bytes(), b"\0"])
+                .flatten()
+                .copied()
+                .collect();
+            streams.push((reader, writer, content));
+        } else {
+            cmd.args(bwrap_args);
+        }
+
+        cmd.arg(command);
+        cmd.args(self.config.command_args(command_args));
+
+        // Pipes are close-on-exec, clear the flag on the ones bwrap needs
+        inherit_fds(&mut cmd, inherited_fds);
+        self.redirect_stdio(&mut cmd)?;
+
+        // Killed as a whole on timeout, with the helper in front of bwrap
+        if self.config.timeout.is_some() {
+            cmd.process_group(0);
+            // Reading the terminal from a background group stops it (SIGTTIN)
+            if self.stdin_file.is_none() && owns_terminal() {
+                take_terminal(&mut cmd);
+            }
+        }
+
+        let child = cmd.spawn()?;
+
+        // bwrap reads the pipes one after the other, write them in the background
+        for (reader, mut writer, content) in streams {
+            drop(reader);
+            thread::spawn(move || {
+                let _ = writer.write_all(&content);
+            });
+        }
+
+        Ok(child)
+    }
+
+    /// Wait for the bwrap process, forwarding signals and enforcing the timeout
+    /// With a timeout, the process runs in its own group (see spawn)
+    fn wait(&self, child: &mut Child) -> Result<i32> {
+        let Some(timeout) = self.config.timeout else {
+            let _forwarder = SignalForwarder::new(child, false)?;
+            let status = child.wait()?;
+            return Ok(exit_code(status));
+        };
+
+        // Signals sent to shwrap go to the whole group, and the terminal
+        // taken by the group (see spawn) is given back once it is done
+        let _forwarder = SignalForwarder::new(child, true)?;
+        let _terminal = TerminalForeground::of(child);
+
+        // Kill the whole group: bwrap may run under the network helper, and
+        // --die-with-parent takes the sandbox down with bwrap
+        match wait_with_timeout(child, Duration::from_secs(timeout))? {
+            Some(status) => Ok(exit_code(status)),
+            None => {
+                // SAFETY: killpg has no memory safety requirements, the group
+                // leader is our own child, not yet reaped
+                if unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL) } == -1 {
+                    return Err(io::Error::last_os_error().into());
+                }
+                child.wait()?;
+                Ok(TIMEOUT_EXIT_CODE)
+            }
+        }
+    }
+
+    /// Replace the current process with bwrap (no process layer in between)
+    /// Only returns if the exec call itself failed
+    pub fn exec_replace(&self, command: &str, command_args: &[String]) -> Result<Infallible> {
+        self.exec_replace_args(self.build_args()?, command, command_args)
+    }
+
+    /// Replace the current process with bwrap and the given arguments, built
+    /// beforehand with build_args (see exec_replace)
+    pub fn exec_replace_args(
+        &self,
+        bwrap_args: Vec<String>,
+        command: &str,
+        command_args: &[String],
+    ) -> Result<Infallible> {
+        if self.needs_supervision() {
+            bail!(
+                "A timeout, inline files or a restricted network need shwrap to keep running, use exec instead"
+            );
+        }
+
+        let err = self
+            .build_command(bwrap_args, command, command_args)?
+            .exec();
+
+        Err(err.into())
+    }
+
+    /// Assemble the bwrap process with the given arguments running the command
+    /// (without inline files)
+    pub(super) fn build_command(
+        &self,
+        bwrap_args: Vec<String>,
+        command: &str,
+        command_args: &[String],
+    ) -> Result<Command> {
+        check_platform()?;
+        self.create_overlay_cache()?;
+        let program = self.bwrap_program();
+        if let Some(version) = bwrap_version(&program) {
+            check_flags(bwrap_args.iter().map(String::as_str), &version)?;
+        }
+
+        self.check_inherit_fds()?;
+
+        let mut cmd = Command::new(program);
+        cmd.args(bwrap_args);
+        cmd.arg(command);
+        cmd.args(self.config.command_args(command_args));
+        inherit_fds(&mut cmd, self.config.inherit_fds.clone());
+        self.redirect_stdio(&mut cmd)?;
+
+        Ok(cmd)
+    }
+
+    /// Wire the stdin and stdout files, if any, to the bwrap process
+    fn redirect_stdio(&self, cmd: &mut Command) -> Result<()> {
+        if let Some(path) = &self.stdin_file {
+            let file =
+                fs::File::open(path).context(format!("Failed to open stdin file {:?}", path))?;
+            cmd.stdin(file);
+        }
+        if let Some(path) = &self.stdout_file {
+            let file = fs::File::create(path)
+                .context(format!("Failed to create stdout file {:?}", path))?;
+            cmd.stdout(file);
+        }
+
+        Ok(())
+    }
+
+    /// Check that the file descriptors to inherit are open
+    fn check_inherit_fds(&self) -> Result<()> {
+        for fd in &self.config.inherit_fds {
+            // SAFETY: F_GETFD only reads the descriptor flags
+            if unsafe { libc::fcntl(*fd, libc::F_GETFD) } == -1 {
+                bail!("File descriptor {} to inherit is not open", fd);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create the cached overlay directories missing
+    fn create_overlay_cache(&self) -> Result<()> {
+        for overlay in &self.config.overlay {
+            if let Some((upper, work)) = self.overlay_cache(overlay) {
+                for dir in [upper, work] {
+                    fs::create_dir_all(&dir)
+                        .context(format!("Failed to create overlay cache {:?}", dir))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Keep the given file descriptors open in the process run by the command
+/// (clearing their close-on-exec flag in the child only)
+fn inherit_fds(cmd: &mut Command, fds: Vec<RawFd>) {
+    // SAFETY: fcntl is async-signal-safe
+    unsafe {
+        cmd.pre_exec(move || {
+            for fd in &fds {
+                if libc::fcntl(*fd, libc::F_SETFD, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Whether stdin is a terminal shwrap is in the foreground of
+fn owns_terminal() -> bool {
+    // SAFETY: isatty, tcgetpgrp and getpgrp have no memory safety requirements
+    unsafe {
+        libc::isatty(libc::STDIN_FILENO) == 1
+            && libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp()
+    }
+}
+
+/// Put the process group of the process run by the command in the foreground
+/// of the terminal on stdin
+fn take_terminal(cmd: &mut Command) {
+    // SAFETY: sigprocmask, tcsetpgrp and getpgrp are async-signal-safe
+    unsafe {
+        cmd.pre_exec(|| {
+            if without_sigttou(|| libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp())) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Call a terminal function with SIGTTOU blocked, changing the foreground
+/// group from the background raises it otherwise
+fn without_sigttou(call: impl FnOnce() -> libc::c_int) -> libc::c_int {
+    // SAFETY: the sets are initialized by sigemptyset and pthread_sigmask
+    unsafe {
+        let mut blocked = std::mem::zeroed();
+        let mut previous = std::mem::zeroed();
+        libc::sigemptyset(&mut blocked);
+        libc::sigaddset(&mut blocked, libc::SIGTTOU);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &blocked, &mut previous);
+        let result = call();
+        libc::pthread_sigmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+        result
+    }
+}
+
+/// Terminal taken by the process group of a child, given back to shwrap
+/// when dropped
+struct TerminalForeground;
+
+impl TerminalForeground {
+    fn of(child: &Child) -> Option<Self> {
+        // spawn returns once the child is executed, the terminal taken before
+        // SAFETY: isatty and tcgetpgrp have no memory safety requirements
+        let taken = unsafe {
+            libc::isatty(libc::STDIN_FILENO) == 1
+                && libc::tcgetpgrp(libc::STDIN_FILENO) == child.id() as libc::pid_t
+        };
+        taken.then_some(Self)
+    }
+}
+
+impl Drop for TerminalForeground {
+    fn drop(&mut self) {
+        // SAFETY: tcsetpgrp and getpgrp have no memory safety requirements
+        without_sigttou(|| unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp()) });
+    }
+}
+
+/// Forward SIGINT and SIGTERM received by shwrap to a child process, or
+/// to its process group
+/// The signal handlers are torn down when dropped
+pub(super) struct SignalForwarder {
+    handle: Handle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SignalForwarder {
+    pub(super) fn new(child: &Child, group: bool) -> Result<Self> {
+        // A negative pid is the process group of that leader
+        let pid = match group {
+            true => -(child.id() as libc::pid_t),
+            false => child.id() as libc::pid_t,
+        };
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        let handle = signals.handle();
+
+        let thread = thread::spawn(move || {
+            for signal in signals.forever() {
+                // SAFETY: kill has no memory safety requirements
+                unsafe {
+                    libc::kill(pid, signal);
+                }
+            }
+        });
+
+        Ok(Self {
+            handle,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for SignalForwarder {
+    fn drop(&mut self) {
+        // Closing ends the forwarding loop, the handlers are unregistered with it
+        self.handle.close();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Wait for a child process to exit, giving up after the timeout
+/// Returns None if the child is still running when the timeout expires
+pub(super) fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Duration,
+) -> Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(TIMEOUT_POLL_INTERVAL);
+    }
+}
+
+/// Event written by bwrap to --json-status-fd
+#[derive(Debug, Default, Deserialize)]
+struct JsonStatus {
+    #[serde(rename = "child-pid")]
+    child_pid: Option<u32>,
+    #[serde(rename = "exit-code")]
+    exit_code: Option<i32>,
+}
+
+/// Merge the stream of JSON events written by bwrap to --json-status-fd
+fn parse_json_status(output: &str) -> Result<JsonStatus> {
+    let mut status = JsonStatus::default();
+
+    for event in serde_json::Deserializer::from_str(output).into_iter::<JsonStatus>() {
+        let event = event.context("Failed to parse bwrap JSON status")?;
+        status.child_pid = event.child_pid.or(status.child_pid);
+        status.exit_code = event.exit_code.or(status.exit_code);
+    }
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bwrap::tests::create_test_config;
+    use crate::config::{InlineFile, Overlay};
+    use indoc::indoc;
+
+    #[test]
+    fn test_wait_with_timeout_expires() {
+        let mut child = Command::new("sleep").arg("5").spawn().unwrap();
+
+        let start = Instant::now();
+        let status = wait_with_timeout(&mut child, Duration::from_millis(100)).unwrap();
+        assert!(status.is_none());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_wait_with_timeout_completes() {
+        let mut child = Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap();
+
+        let status = wait_with_timeout(&mut child, Duration::from_secs(5)).unwrap();
+        assert_eq!(status.unwrap().code(), Some(3));
+    }
+
+    #[test]
+    fn test_run_with_status_stub_bwrap() {
+        // Stand-in for bwrap writing its status to the fd given with --json-status-fd
+        let stub = indoc! {r#"
+            while [ $# -gt 0 ]; do
+              [ "$1" = --json-status-fd ] && fd=$2
+              shift
+            done
+            echo '{ "child-pid": 4242 }' >&"$fd"
+            echo '{ "exit-code": 3 }' >&"$fd"
+            exit 3
+        "#};
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", stub, "bwrap"]);
+
+        let builder = WrappedCommandBuilder::new(create_test_config());
+        let status = builder.run_with_status(cmd, "true", &[]).unwrap();
+
+        assert_eq!(
+            status,
+            SandboxStatus {
+                child_pid: Some(4242),
+                exit_code: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_run_with_status_no_report() {
+        // bwrap failing before the launch reports nothing
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "exit 1", "bwrap"]);
+
+        let builder = WrappedCommandBuilder::new(create_test_config());
+        let status = builder.run_with_status(cmd, "true", &[]).unwrap();
+
+        assert_eq!(status.child_pid, None);
+        assert_eq!(status.exit_code, 1);
+    }
+
+    #[test]
+    fn test_spawn_streams_inline_files() {
+        let out_dir = tempfile::TempDir::new().unwrap();
+
+        // Stand-in for bwrap copying each --file fd to the output directory
+        let stub = indoc! {r#"
+            while [ $# -gt 0 ]; do
+              if [ "$1" = --file ]; then
+                cat <&"$2" > "$OUT_DIR/$(basename "$3")"
+                shift 2
+              fi
+              shift
+            done
+        "#};
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", stub, "bwrap"])
+            .env("OUT_DIR", out_dir.path());
+
+        let mut config = create_test_config();
+        config.files = vec![
+            InlineFile {
+                dest: "/app/config.json".to_string(),
+                content: "{ \"debug\": true }\n".to_string(),
+            },
+            InlineFile {
+                dest: "/etc/token".to_string(),
+                content: "secret".to_string(),
+            },
+        ];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let bwrap_args = builder.build_args().unwrap();
+        let mut child = builder.spawn(cmd, bwrap_args, vec![], "true", &[]).unwrap();
+        assert!(child.wait().unwrap().success());
+
+        let config_json = fs::read_to_string(out_dir.path().join("config.json")).unwrap();
+        assert_eq!(config_json, "{ \"debug\": true }\n");
+        let token = fs::read_to_string(out_dir.path().join("token")).unwrap();
+        assert_eq!(token, "secret");
+    }
+
+    #[test]
+    fn test_spawn_inherits_fds() {
+        let out_dir = tempfile::TempDir::new().unwrap();
+
+        // Stand-in for bwrap reading the inherited fd, as the command would
+        let stub = r#"cat <&"$FD" > "$OUT_DIR/out""#;
+        let (reader, mut writer) = io::pipe().unwrap();
+        writer.write_all(b"from the parent").unwrap();
+        drop(writer);
+
+        let fd = reader.as_raw_fd();
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", stub, "bwrap"])
+            .env("FD", fd.to_string())
+            .env("OUT_DIR", out_dir.path());
+
+        let mut config = create_test_config();
+        config.inherit_fds = vec![fd];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let bwrap_args = builder.build_args().unwrap();
+        let mut child = builder.spawn(cmd, bwrap_args, vec![], "true", &[]).unwrap();
+        assert!(child.wait().unwrap().success());
+        drop(reader);
+
+        let out = fs::read_to_string(out_dir.path().join("out")).unwrap();
+        assert_eq!(out, "from the parent");
+
+        // Closed descriptors are refused (fds are reused, pick one never opened)
+        let mut config = create_test_config();
+        config.inherit_fds = vec![999_999];
+        let err = WrappedCommandBuilder::new(config)
+            .spawn(Command::new("true"), vec![], vec![], "true", &[])
+            .unwrap_err();
+        assert!(err.to_string().contains("is not open"));
+    }
+
+    #[test]
+    fn test_spawn_passes_long_args_through_fd() {
+        let out_dir = tempfile::TempDir::new().unwrap();
+
+        // Stand-in for bwrap saving the --args fd content and its own argv
+        let stub = indoc! {r#"
+            printf '%s\n' "$@" > "$OUT_DIR/argv"
+            while [ $# -gt 0 ]; do
+              [ "$1" = --args ] && cat <&"$2" > "$OUT_DIR/args"
+              shift
+            done
+        "#};
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", stub, "bwrap"])
+            .env("OUT_DIR", out_dir.path());
+
+        let mut config = create_test_config();
+        config.bind = (0..2000)
+            .map(|i| format!("/some/rather/long/source/directory/{i:04}:/sandbox/target/{i:04}"))
+            .collect();
+
+        let builder = WrappedCommandBuilder::new(config);
+        let expected = builder.build_args().unwrap();
+        assert!(args_size(&expected) > ARGS_FD_THRESHOLD);
+        assert!(builder.needs_supervision());
+
+        let mut child = builder
+            .spawn(cmd, expected.clone(), vec![], "make", &["all".to_string()])
+            .unwrap();
+        assert!(child.wait().unwrap().success());
+
+        let args = fs::read(out_dir.path().join("args")).unwrap();
+        let args: Vec<_> = args
+            .strip_suffix(b"\0")
+            .unwrap()
+            .split(|byte| *byte == 0)
+            .map(|arg| String::from_utf8(arg.to_vec()).unwrap())
+            .collect();
+        assert_eq!(args, expected);
+
+        let argv = fs::read_to_string(out_dir.path().join("argv")).unwrap();
+        let argv: Vec<_> = argv.lines().collect();
+        assert_eq!(argv.len(), 4);
+        assert_eq!(argv[0], "--args");
+        assert_eq!(argv[2..], ["make", "all"]);
+
+        // Short argument lists stay on the command line
+        let builder = WrappedCommandBuilder::new(create_test_config());
+        assert!(!builder.needs_supervision());
+    }
+
+    #[test]
+    fn test_signal_forwarder_forwards_sigterm() {
+        use std::io::{BufRead, BufReader};
+        use std::process::Stdio;
+
+        // The signal goes to a helper process forwarding it, not to the tests
+        let mut helper = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "bwrap::run::tests::signal_forwarder_helper",
+                "--ignored",
+                "--nocapture",
+            ])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // Kept open until the helper exits, it writes the test result last
+        let mut lines = BufReader::new(helper.stdout.take().unwrap()).lines();
+        assert!(lines.any(|line| line.unwrap().contains("forwarder ready")));
+
+        // SAFETY: kill has no memory safety requirements
+        unsafe {
+            libc::kill(helper.id() as libc::pid_t, SIGTERM);
+        }
+
+        let status = wait_with_timeout(&mut helper, Duration::from_secs(10)).unwrap();
+        if status.is_none() {
+            helper.kill().unwrap();
+        }
+        assert!(status.is_some_and(|status| status.success()));
+        drop(lines);
+    }
+
+    /// Stand-in for shwrap forwarding SIGTERM to its child, run in its own
+    /// process by test_signal_forwarder_forwards_sigterm
+    #[test]
+    #[ignore = "run by test_signal_forwarder_forwards_sigterm"]
+    fn signal_forwarder_helper() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let _forwarder = SignalForwarder::new(&child, false).unwrap();
+        println!("forwarder ready");
+
+        let status = child.wait().unwrap();
+        assert_eq!(status.signal(), Some(SIGTERM));
+    }
+
+    #[test]
+    fn test_build_command() {
+        let mut config = create_test_config();
+        config.share = vec!["network".to_string()];
+
+        let builder = WrappedCommandBuilder::new(config);
+        let mut expected = builder.build_args().unwrap();
+        let cmd = builder
+            .build_command(
+                expected.clone(),
+                "curl",
+                &["-s".to_string(), "example.com".to_string()],
+            )
+            .unwrap();
+
+        expected.extend([
+            "curl".to_string(),
+            "-s".to_string(),
+            "example.com".to_string(),
+        ]);
+
+        let args: Vec<_> = cmd
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+
+        assert_eq!(cmd.get_program(), "bwrap");
+        assert_eq!(args, expected);
+
+        let mut config = create_test_config();
+        config.bwrap_path = Some("/usr/local/bin/bwrap-shim".to_string());
+        let cmd = WrappedCommandBuilder::new(config)
+            .build_command(vec![], "npm", &[])
+            .unwrap();
+        assert_eq!(cmd.get_program(), "/usr/local/bin/bwrap-shim");
+    }
+
+    #[test]
+    fn test_parse_json_status() {
+        let status = parse_json_status(indoc! {r#"
+            { "child-pid": 1234, "cgroup-namespace": 4026531835 }
+            { "exit-code": 7 }
+        "#})
+        .unwrap();
+
+        assert_eq!(status.child_pid, Some(1234));
+        assert_eq!(status.exit_code, Some(7));
+
+        assert!(parse_json_status("").unwrap().child_pid.is_none());
+        assert!(parse_json_status("{ invalid").is_err());
+    }
+
+    #[test]
+    fn test_overlay_cache_created_on_exec() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut config = create_test_config();
+        config.overlay_cache = true;
+        config.overlay = vec![Overlay {
+            src: "/srv/app".to_string(),
+            dst: "/app".to_string(),
+            upper: None,
+            work: None,
+            tmp: false,
+        }];
+        let builder = WrappedCommandBuilder::new(config).with_overlay_cache_dir(temp_dir.path());
+
+        let (upper, work) = builder.overlay_cache(&builder.config.overlay[0]).unwrap();
+        assert!(upper.starts_with(temp_dir.path()));
+        builder.create_overlay_cache().unwrap();
+        assert!(upper.is_dir() && work.is_dir());
+    }
+}
@@ -1,16 +1,106 @@
 // Copyright (C) 2025 Pierre Le Gall
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use clap::{Parser, Subcommand};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use shwrap::bwrap::NAMESPACES;
+use shwrap::config::Entry;
+use std::path::PathBuf;
+
+use crate::shell_hooks::Shell;
 
 #[derive(Parser)]
 #[command(name = "shwrap")]
 #[command(about = "A profile manager for Bubblewrap (bwrap)", long_about = None)]
 pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
     #[command(subcommand)]
     pub subject: Subject,
 }
 
+/// Entry built from the command line, for commands without configuration
+#[derive(Args)]
+pub struct EntryFlags {
+    /// Namespace to share with the host, `net` for network (can be repeated)
+    #[arg(long = "share", value_name = "NAMESPACE", value_parser = parse_namespace)]
+    pub share: Vec<String>,
+
+    /// Path to bind read-only (can be repeated)
+    #[arg(long = "ro-bind", value_name = "PATH")]
+    pub ro_bind: Vec<String>,
+
+    /// Path to bind read-write, as `src:dest` (can be repeated)
+    #[arg(long = "bind", value_name = "SRC:DEST")]
+    pub bind: Vec<String>,
+
+    /// Environment variable to set (can be repeated)
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    pub env: Vec<(String, String)>,
+}
+
+impl EntryFlags {
+    /// Get the entry described by the flags, None if no flag is given
+    pub fn to_entry(&self) -> Option<Entry> {
+        let entry = Entry {
+            share: self.share.clone(),
+            ro_bind: self
+                .ro_bind
+                .iter()
+                .map(|path| path.as_str().into())
+                .collect(),
+            bind: self.bind.clone(),
+            env: self.env.iter().cloned().collect(),
+            ..Default::default()
+        };
+
+        let empty = entry.share.is_empty()
+            && entry.ro_bind.is_empty()
+            && entry.bind.is_empty()
+            && entry.env.is_empty();
+        (!empty).then_some(entry)
+    }
+}
+
+fn parse_namespace(value: &str) -> Result<String, String> {
+    match value {
+        "net" => Ok("network".to_string()),
+        namespace if NAMESPACES.contains(&namespace) => Ok(namespace.to_string()),
+        _ => Err(format!(
+            "unknown namespace '{}' (expected one of: {})",
+            value,
+            NAMESPACES.join(", ")
+        )),
+    }
+}
+
+fn parse_env_var(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or(format!("expected KEY=VALUE, got '{}'", value))
+}
+
+#[derive(Args)]
+pub struct GlobalArgs {
+    /// Use this config file instead of searching the hierarchy
+    #[arg(short, long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Merge local, user and system configs instead of using the first found
+    #[arg(long, global = true)]
+    pub merge: bool,
+
+    /// Don't print non-fatal warnings (such as invalid binds) to stderr
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Log the resolved configuration and bwrap command to stderr
+    /// (repeat for more details: -v for debug logs, -vv for trace logs)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+}
+
 #[derive(Subcommand)]
 pub enum Subject {
     /// Configuration management
@@ -22,7 +112,13 @@ pub enum Subject {
     /// Command management
     Command {
         #[command(subcommand)]
-        action: CommandAction,
+        action: Box<CommandAction>,
+    },
+
+    /// Model management
+    Model {
+        #[command(subcommand)]
+        action: ModelAction,
     },
 
     /// Shell integration
@@ -31,15 +127,33 @@ pub enum Subject {
         #[command(subcommand)]
         action: ShellHookAction,
     },
+
+    /// Check the installation: bubblewrap, user namespaces and configuration
+    Doctor,
 }
 
 #[derive(Subcommand)]
 pub enum ConfigAction {
     /// Initialize a new .shwrap.yaml file with templates
     Init {
-        /// Template to use (nodejs, python, ruby, go, rust)
+        /// Template to use (default, nodejs, python, ruby, go, rust), or the path to a YAML file
         #[arg(short, long)]
         template: Option<String>,
+
+        /// List the bundled templates
+        #[arg(long, conflicts_with = "template")]
+        list: bool,
+
+        /// Ask which namespaces to share and which paths to bind (the default
+        /// template is used when stdin is not a terminal)
+        #[arg(short, long, conflicts_with_all = ["template", "list"])]
+        interactive: bool,
+    },
+
+    /// Upgrade a configuration file to the current format version (comments are lost)
+    Migrate {
+        /// Path to config file (defaults to searching hierarchy)
+        path: Option<PathBuf>,
     },
 
     /// Validate configuration syntax
@@ -49,10 +163,36 @@ pub enum ConfigAction {
         /// To enable no output (useful for shell exit code returns)
         #[arg(long)]
         silent: bool,
+        /// Check that bubblewrap is installed instead of the config file
+        #[arg(long, conflicts_with = "path")]
+        bwrap: bool,
+        /// Output format (default: human readable)
+        #[arg(long, conflicts_with_all = ["silent", "bwrap"])]
+        format: Option<CheckFormat>,
     },
 
     /// Show which .shwrap.yaml file would be used
-    Which,
+    Which {
+        /// Output format for scripts (default: human readable)
+        #[arg(long)]
+        format: Option<WhichFormat>,
+    },
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum CheckFormat {
+    /// Problems on stderr, commands and their warnings on stdout
+    Human,
+    /// JSON report on stdout: path, validity, commands and errors
+    Json,
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum WhichFormat {
+    /// Only the path (nothing if no configuration is found)
+    Path,
+    /// No output, exit code 0 if a configuration is found, 1 otherwise
+    Exists,
 }
 
 #[derive(Subcommand)]
@@ -62,10 +202,74 @@ pub enum CommandAction {
         /// To enable simple output (useful for shell inputs)
         #[arg(long)]
         simple: bool,
+
+        /// Print the active entries as JSON
+        #[arg(long, conflicts_with = "simple")]
+        json: bool,
+
+        /// Only list the disabled commands (names only with --simple)
+        #[arg(long)]
+        disabled: bool,
     },
 
     /// Manually wrap and execute a command
     Exec {
+        /// Share all namespaces with the host (debugging aid)
+        #[arg(long)]
+        no_isolation: bool,
+
+        /// Kill the command after this many seconds (exit code 124)
+        #[arg(long, value_name = "SECONDS")]
+        timeout: Option<u64>,
+
+        /// Profile of the command entry to merge over it
+        #[arg(long)]
+        profile: Option<String>,
+
+        /// Apply the strict preset (as with `harden: true`)
+        #[arg(long)]
+        harden: bool,
+
+        /// Run as root in the sandbox (as with `run_as_root: true`)
+        #[arg(long)]
+        as_root: bool,
+
+        /// Print timings and the exit code to stderr once the command exits
+        #[arg(long)]
+        stats: bool,
+
+        /// Append a JSON line describing the invocation to this file once the
+        /// command exits (defaults to $SHWRAP_AUDIT_LOG)
+        #[arg(long, value_name = "FILE")]
+        record: Option<PathBuf>,
+
+        /// Feed this host file to the command stdin
+        #[arg(long, value_name = "PATH")]
+        stdin_file: Option<PathBuf>,
+
+        /// Write the command stdout to this host file (created or truncated)
+        #[arg(long, value_name = "PATH")]
+        stdout_file: Option<PathBuf>,
+
+        /// Host variable to pass to the sandbox, skipped if unset (can be repeated)
+        #[arg(long, value_name = "VAR")]
+        keep_env: Vec<String>,
+
+        /// Start from an empty environment, with only the variables set by the config
+        #[arg(long, conflicts_with = "keep_env")]
+        unset_all_env: bool,
+
+        /// Set the leading KEY=VALUE arguments in the sandbox instead of passing them
+        #[arg(long)]
+        env_prefix: bool,
+
+        /// Ignore the configuration, the entry is built from the flags below
+        #[arg(long, conflicts_with = "profile")]
+        no_config: bool,
+
+        #[command(flatten)]
+        entry: EntryFlags,
+
         /// Command to execute
         command: String,
 
@@ -74,8 +278,82 @@ pub enum CommandAction {
         args: Vec<String>,
     },
 
+    /// Execute a wrapped command in several directories, each with its own configuration
+    RunAll {
+        /// Directory to run the command in (can be repeated)
+        #[arg(long = "dir", value_name = "PATH", required = true)]
+        dirs: Vec<PathBuf>,
+
+        /// Command to execute
+        command: String,
+
+        /// Arguments to pass to the command
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Add a command to the active configuration file
+    Add {
+        /// Command to add
+        command: String,
+
+        /// Model to extend
+        #[arg(long)]
+        extends: Option<String>,
+
+        /// Namespace to share (can be repeated)
+        #[arg(long)]
+        share: Vec<String>,
+    },
+
+    /// Enable a command in the active configuration file
+    Enable {
+        /// Command to enable
+        command: String,
+    },
+
+    /// Disable a command in the active configuration file
+    Disable {
+        /// Command to disable
+        command: String,
+    },
+
+    /// Print the host executable a command resolves to (through $PATH)
+    Which {
+        /// Command to resolve
+        command: String,
+    },
+
     /// Show the bwrap command that would be executed
     Show {
+        /// Print the resolved configuration (after extends) as YAML instead
+        #[arg(long)]
+        print_config: bool,
+
+        /// Print one bwrap flag per line, with `\` line continuations
+        #[arg(long, conflicts_with = "print_config")]
+        pretty: bool,
+
+        /// Print the bwrap argv as NUL-terminated tokens (for `xargs -0`)
+        #[arg(long, conflicts_with_all = ["print_config", "pretty"])]
+        null: bool,
+
+        /// Print one bwrap flag per line with a description of what it does
+        #[arg(long, conflicts_with_all = ["print_config", "pretty", "null"])]
+        explain: bool,
+
+        /// Print the command line quoted for this shell (bash, zsh, fish), to eval
+        #[arg(long, value_name = "SHELL", conflicts_with_all = ["print_config", "pretty", "null", "explain"])]
+        shell: Option<Shell>,
+
+        /// Print the differences of the resolved configuration of another command from this one
+        #[arg(long, value_name = "COMMAND", conflicts_with_all = ["print_config", "pretty", "null", "explain", "shell"])]
+        diff: Option<String>,
+
+        /// Profile of the command entry to merge over it
+        #[arg(long)]
+        profile: Option<String>,
+
         /// Command to show
         command: String,
 
@@ -85,11 +363,26 @@ pub enum CommandAction {
     },
 }
 
+#[derive(Subcommand)]
+pub enum ModelAction {
+    /// List models (entries with type: model) and their contents
+    List {
+        /// To enable simple output (useful for shell inputs)
+        #[arg(long)]
+        simple: bool,
+    },
+}
+
 #[derive(Subcommand)]
 pub enum ShellHookAction {
     /// Get shell integration code
     Get {
-        /// Shell name
-        shell: String,
+        /// Shell name (bash, zsh, fish), detected from $SHELL when omitted
+        shell: Option<Shell>,
+
+        /// Only wrap the commands enabled in the current config, instead of
+        /// reloading them on each directory change
+        #[arg(long)]
+        commands_from_config: bool,
     },
 }
@@ -0,0 +1,234 @@
+// Copyright (C) 2025 Pierre Le Gall
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use super::{Enabled, Entry, EntryType, Network, Overlay, RoBind};
+
+/// Builds an entry value by value, the other fields keep their defaults
+///
+/// Values use the config file notation (`src:dst` binds, `~` and variables
+/// expanded when the command runs), methods of list fields append one value.
+#[derive(Debug, Clone, Default)]
+pub struct EntryBuilder {
+    entry: Entry,
+}
+
+impl Entry {
+    /// Start building a command entry
+    pub fn builder() -> EntryBuilder {
+        EntryBuilder::default()
+    }
+}
+
+impl EntryBuilder {
+    /// Build a model (`type: model`) instead of a command
+    pub fn model(mut self) -> Self {
+        self.entry.entry_type = EntryType::Model;
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.entry.enabled = Enabled::Always(enabled);
+        self
+    }
+
+    pub fn extends(mut self, model: impl Into<String>) -> Self {
+        self.entry.extends = Some(model.into());
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.entry.description = Some(description.into());
+        self
+    }
+
+    /// Share a namespace with the host
+    pub fn share(mut self, namespace: impl Into<String>) -> Self {
+        self.entry.share.push(namespace.into());
+        self
+    }
+
+    pub fn dir(mut self, path: impl Into<String>) -> Self {
+        self.entry.dir.push(path.into());
+        self
+    }
+
+    /// Read-write bind, `src:dst` or a path bound at the same location
+    pub fn bind(mut self, bind: impl Into<String>) -> Self {
+        self.entry.bind.push(bind.into());
+        self
+    }
+
+    pub fn bind_try(mut self, bind: impl Into<String>) -> Self {
+        self.entry.bind_try.push(bind.into());
+        self
+    }
+
+    pub fn ro_bind(mut self, ro_bind: impl Into<RoBind>) -> Self {
+        self.entry.ro_bind.push(ro_bind.into());
+        self
+    }
+
+    pub fn ro_bind_try(mut self, path: impl Into<String>) -> Self {
+        self.entry.ro_bind_try.push(path.into());
+        self
+    }
+
+    pub fn dev_bind(mut self, path: impl Into<String>) -> Self {
+        self.entry.dev_bind.push(path.into());
+        self
+    }
+
+    pub fn tmpfs(mut self, path: impl Into<String>) -> Self {
+        self.entry.tmpfs.push(path.into());
+        self
+    }
+
+    pub fn overlay(mut self, overlay: Overlay) -> Self {
+        self.entry.overlay.push(overlay);
+        self
+    }
+
+    pub fn remount_ro(mut self, path: impl Into<String>) -> Self {
+        self.entry.remount_ro.push(path.into());
+        self
+    }
+
+    pub fn proc(mut self, path: impl Into<String>) -> Self {
+        self.entry.proc = Some(path.into());
+        self
+    }
+
+    /// Set a variable, over a previous value
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.entry.env.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn env_file(mut self, path: impl Into<String>) -> Self {
+        self.entry.env_file.push(path.into());
+        self
+    }
+
+    pub fn env_prefix_passthrough(mut self, prefix: impl Into<String>) -> Self {
+        self.entry.env_prefix_passthrough.push(prefix.into());
+        self
+    }
+
+    pub fn unset_env(mut self, key: impl Into<String>) -> Self {
+        self.entry.unset_env.push(key.into());
+        self
+    }
+
+    pub fn uid(mut self, uid: u32) -> Self {
+        self.entry.uid = Some(uid);
+        self
+    }
+
+    pub fn gid(mut self, gid: u32) -> Self {
+        self.entry.gid = Some(gid);
+        self
+    }
+
+    /// Timeout in seconds
+    pub fn timeout(mut self, seconds: u64) -> Self {
+        self.entry.timeout = Some(seconds);
+        self
+    }
+
+    pub fn network(mut self, network: Network) -> Self {
+        self.entry.network = Some(network);
+        self
+    }
+
+    pub fn allow_host(mut self, host: impl Into<String>) -> Self {
+        self.entry.allow_hosts.push(host.into());
+        self
+    }
+
+    pub fn harden(mut self) -> Self {
+        self.entry.harden = true;
+        self
+    }
+
+    pub fn run_as_root(mut self) -> Self {
+        self.entry.run_as_root = true;
+        self
+    }
+
+    pub fn allow_tty(mut self) -> Self {
+        self.entry.allow_tty = true;
+        self
+    }
+
+    pub fn prepend_arg(mut self, arg: impl Into<String>) -> Self {
+        self.entry.prepend_args.push(arg.into());
+        self
+    }
+
+    pub fn append_arg(mut self, arg: impl Into<String>) -> Self {
+        self.entry.append_args.push(arg.into());
+        self
+    }
+
+    pub fn build(self) -> Entry {
+        self.entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_entry_builder() {
+        let entry = Entry::builder()
+            .extends("base")
+            .share("network")
+            .share("user")
+            .ro_bind("/usr")
+            .ro_bind(RoBind::Tree {
+                path: "/etc".to_string(),
+                exclude: vec!["/etc/ssh".to_string()],
+            })
+            .bind("~/.npm:~/.npm")
+            .tmpfs("/tmp")
+            .env("K", "V")
+            .unset_env("DEBUG")
+            .timeout(60)
+            .append_arg("--verbose")
+            .build();
+
+        let manual = Entry {
+            extends: Some("base".to_string()),
+            share: vec!["network".to_string(), "user".to_string()],
+            ro_bind: vec![
+                "/usr".into(),
+                RoBind::Tree {
+                    path: "/etc".to_string(),
+                    exclude: vec!["/etc/ssh".to_string()],
+                },
+            ],
+            bind: vec!["~/.npm:~/.npm".to_string()],
+            tmpfs: vec!["/tmp".to_string()],
+            env: HashMap::from([("K".to_string(), "V".to_string())]),
+            unset_env: vec!["DEBUG".to_string()],
+            timeout: Some(60),
+            append_args: vec!["--verbose".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(entry, manual);
+    }
+
+    #[test]
+    fn test_entry_builder_defaults() {
+        let entry = Entry::builder().build();
+        assert_eq!(entry, Entry::default());
+        assert_eq!(entry.entry_type, EntryType::Command);
+        assert!(entry.is_enabled());
+
+        let model = Entry::builder().model().enabled(false).build();
+        assert_eq!(model.entry_type, EntryType::Model);
+        assert!(!model.is_enabled());
+    }
+}
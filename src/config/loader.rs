@@ -4,6 +4,7 @@
 use anyhow::{Context, Result};
 use std::env;
 use std::path::PathBuf;
+use tracing::{debug, trace};
 
 use super::Config;
 
@@ -16,6 +17,27 @@ const USER_CONFIG_FILE_NAME: &str = "default.yaml";
 /// User config directory path relative to HOME
 const USER_CONFIG_DIR_PATH: &str = "~/.config/shwrap";
 
+/// User config directory name relative to XDG_CONFIG_HOME
+const USER_CONFIG_DIR_NAME: &str = "shwrap";
+
+/// Cache directory path relative to HOME
+const CACHE_DIR_PATH: &str = "~/.cache/shwrap";
+
+/// System config file name
+const SYSTEM_CONFIG_FILE_NAME: &str = "default.yaml";
+
+/// System config directory path
+const SYSTEM_CONFIG_DIR_PATH: &str = "/etc/shwrap";
+
+/// Environment variable overriding the system config directory path
+const SYSTEM_CONFIG_DIR_ENV: &str = "SHWRAP_SYSTEM_CONFIG_DIR";
+
+/// Environment variable caching the config file path found from CONFIG_CACHE_DIR_ENV
+const CONFIG_CACHE_ENV: &str = "SHWRAP_CONFIG";
+
+/// Environment variable holding the directory the cached config path was found from
+const CONFIG_CACHE_DIR_ENV: &str = "SHWRAP_CONFIG_CWD";
+
 pub struct ConfigLoader;
 
 impl ConfigLoader {
@@ -34,6 +56,24 @@ impl ConfigLoader {
         USER_CONFIG_DIR_PATH
     }
 
+    /// Get the cache directory (expanded) path
+    /// Uses XDG_CACHE_HOME when set and not empty, otherwise falls back to ~/.cache
+    pub fn get_cache_dir() -> PathBuf {
+        if let Some(xdg_cache_home) = env::var_os("XDG_CACHE_HOME")
+            && !xdg_cache_home.is_empty()
+        {
+            return PathBuf::from(xdg_cache_home).join(USER_CONFIG_DIR_NAME);
+        }
+
+        let expanded_dir = shellexpand::tilde(CACHE_DIR_PATH);
+        PathBuf::from(expanded_dir.as_ref())
+    }
+
+    /// Get the system config directory path (constant)
+    pub fn system_config_dir() -> &'static str {
+        SYSTEM_CONFIG_DIR_PATH
+    }
+
     /// Get the directory containing the local config file by walking up from current directory
     /// Returns None if no directory contains a local config file
     pub fn get_local_config_dir() -> Result<Option<PathBuf>> {
@@ -57,24 +97,90 @@ impl ConfigLoader {
     }
 
     /// Get the user config directory (expanded) path
+    /// Uses XDG_CONFIG_HOME when set and not empty, otherwise falls back to ~/.config
     pub fn get_user_config_dir() -> PathBuf {
+        if let Some(xdg_config_home) = env::var_os("XDG_CONFIG_HOME")
+            && !xdg_config_home.is_empty()
+        {
+            return PathBuf::from(xdg_config_home).join(USER_CONFIG_DIR_NAME);
+        }
+
         let expanded_dir = shellexpand::tilde(USER_CONFIG_DIR_PATH);
         PathBuf::from(expanded_dir.as_ref())
     }
 
-    /// Get config file path in hierarchical order (local first, then user)
+    /// Get the system config directory path
+    /// Can be overridden with SHWRAP_SYSTEM_CONFIG_DIR (mostly useful for tests)
+    pub fn get_system_config_dir() -> PathBuf {
+        match env::var_os(SYSTEM_CONFIG_DIR_ENV) {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(SYSTEM_CONFIG_DIR_PATH),
+        }
+    }
+
+    /// Get config file path in hierarchical order (local, then user, then system)
+    /// A path cached for the current directory (see get_cached_config_file) skips the search
     pub fn get_config_file() -> Result<Option<PathBuf>> {
-        // Look for local config in current directory and parent directories
-        if let Some(local_config) = Self::get_local_config_file()? {
-            return Ok(Some(local_config));
+        if let Some(cached_config) = Self::get_cached_config_file()? {
+            return Ok(Some(cached_config));
         }
 
-        // Look for user-level config
-        if let Some(user_config) = Self::get_user_config_file()? {
-            return Ok(Some(user_config));
+        Ok(Self::find_all()?.into_iter().next())
+    }
+
+    /// Get every config file of the hierarchy, in precedence order: local ones
+    /// (from the current directory up to the root), then user, then system
+    pub fn find_all() -> Result<Vec<PathBuf>> {
+        let mut paths = Self::get_local_config_files()?;
+        paths.extend(Self::get_user_config_file()?);
+        paths.extend(Self::get_system_config_file()?);
+
+        trace!(?paths, "found config files");
+        Ok(paths)
+    }
+
+    /// Get every local config file, from the current directory up to the root
+    pub fn get_local_config_files() -> Result<Vec<PathBuf>> {
+        let current_dir = env::current_dir().context("Failed to get current directory")?;
+
+        Ok(current_dir
+            .ancestors()
+            .map(|dir| dir.join(LOCAL_CONFIG_FILE_NAME))
+            .filter(|config_path| config_path.exists())
+            .collect())
+    }
+
+    /// Get the config file path cached in SHWRAP_CONFIG (set by shell hooks)
+    /// The cache is stale, and ignored, when SHWRAP_CONFIG_CWD is not the current
+    /// directory, the file no longer exists or another local config file was created
+    /// since in the current directory or an ancestor up to the cached one
+    pub fn get_cached_config_file() -> Result<Option<PathBuf>> {
+        let (Some(cached_config), Some(cached_dir)) = (
+            env::var_os(CONFIG_CACHE_ENV),
+            env::var_os(CONFIG_CACHE_DIR_ENV),
+        ) else {
+            return Ok(None);
+        };
+
+        let current_dir = env::current_dir().context("Failed to get current directory")?;
+        let config_path = PathBuf::from(cached_config);
+        if current_dir.as_os_str() != cached_dir || !config_path.is_file() {
+            return Ok(None);
         }
 
-        Ok(None)
+        // A local config file closer than the cached one would be found instead
+        for dir in current_dir.ancestors() {
+            let local_config_path = dir.join(LOCAL_CONFIG_FILE_NAME);
+            if local_config_path == config_path {
+                break;
+            }
+            if local_config_path.exists() {
+                return Ok(None);
+            }
+        }
+
+        trace!(path = %config_path.display(), "using cached config file");
+        Ok(Some(config_path))
     }
 
     /// Get local config file by searching in current and parent directories
@@ -98,6 +204,17 @@ impl ConfigLoader {
         Ok(None)
     }
 
+    /// Get system-wide config file
+    pub fn get_system_config_file() -> Result<Option<PathBuf>> {
+        let config_path = Self::get_system_config_dir().join(SYSTEM_CONFIG_FILE_NAME);
+
+        if config_path.exists() {
+            return Ok(Some(config_path));
+        }
+
+        Ok(None)
+    }
+
     /// Load config from the found path
     pub fn load() -> Result<Option<Config>> {
         if let Some(path) = Self::get_config_file()? {
@@ -107,4 +224,26 @@ impl ConfigLoader {
             Ok(None)
         }
     }
+
+    /// Load a config from memory (for embedders), `source` labels it in errors
+    pub fn load_from_str_with_source(yaml: &str, source: &str) -> Result<Config> {
+        Config::from_yaml_with_source(yaml, source)
+    }
+
+    /// Load and merge every config of the hierarchy (see find_all)
+    /// Local entries override user entries, which override system entries (by
+    /// name), and the nearest local entries override the ones of parent directories
+    pub fn load_merged() -> Result<Option<Config>> {
+        let mut merged: Option<Config> = None;
+        for path in Self::find_all()?.into_iter().rev() {
+            debug!(path = %path.display(), "merging config layer");
+            let config = Config::from_file(&path)?;
+            match merged.as_mut() {
+                Some(merged) => merged.merge(config),
+                None => merged = Some(config),
+            }
+        }
+
+        Ok(merged)
+    }
 }
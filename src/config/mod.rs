@@ -1,80 +1,948 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow, bail};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use tracing::{debug, debug_span, trace};
 
+use crate::bwrap::NAMESPACES;
+
+pub mod builder;
 pub mod loader;
+pub mod writer;
+
+pub use builder::EntryBuilder;
+
+/// Name of the entry applied to commands without their own entry
+pub const WILDCARD_COMMAND: &str = "*";
+
+/// Current version of the config format
+/// Version 0 is the old layout, with entries nested under `commands` and `templates`
+pub const CONFIG_VERSION: u32 = 1;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
+    /// Version of the config format (see CONFIG_VERSION)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+    /// Non-fatal problems found while loading (such as an old format)
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+    /// Values substituted for `${NAME}` references at load time
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub vars: HashMap<String, String>,
+    /// Path of the config file, None if not loaded from a file
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+    /// Directory of the config file, relative bind sources are resolved against it
+    #[serde(skip)]
+    pub base_dir: Option<PathBuf>,
+    /// Config file of each entry, when merged from several (see entry_base_dir)
+    #[serde(skip)]
+    pub entry_paths: HashMap<String, PathBuf>,
     #[serde(flatten)]
     pub entries: HashMap<String, Entry>,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum EntryType {
+    #[default]
     Command,
     Model,
 }
 
-impl Default for EntryType {
-    fn default() -> Self {
-        EntryType::Command
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Entry {
     #[serde(default, rename = "type")]
     pub entry_type: EntryType,
-    #[serde(default = "default_enabled")]
-    pub enabled: bool,
+    /// Either a bool or a host condition (see `Enabled`)
+    #[serde(default)]
+    pub enabled: Enabled,
     #[serde(default)]
     pub extends: Option<String>,
+    /// Free text note, not used to build the command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
     #[serde(default)]
     pub share: Vec<String>,
     #[serde(default)]
+    pub dir: Vec<String>,
+    #[serde(default)]
     pub bind: Vec<String>,
     #[serde(default)]
-    pub ro_bind: Vec<String>,
+    pub bind_try: Vec<String>,
+    #[serde(default)]
+    pub ro_bind: Vec<RoBind>,
+    #[serde(default)]
+    pub ro_bind_try: Vec<String>,
     #[serde(default)]
     pub dev_bind: Vec<String>,
     #[serde(default)]
     pub tmpfs: Vec<String>,
     #[serde(default)]
+    pub overlay: Vec<Overlay>,
+    #[serde(default)]
+    pub remount_ro: Vec<String>,
+    #[serde(default)]
+    pub files: Vec<InlineFile>,
+    #[serde(default)]
+    pub lock_file: Vec<String>,
+    /// Open file descriptors of shwrap passed to the command
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inherit_fds: Vec<i32>,
+    #[serde(default)]
     pub env: HashMap<String, String>,
     #[serde(default)]
+    pub env_file: Vec<String>,
+    /// Host variables passed through when their name starts with one of these
+    #[serde(default)]
+    pub env_prefix_passthrough: Vec<String>,
+    #[serde(default)]
     pub unset_env: Vec<String>,
+    #[serde(default)]
+    pub uid: Option<u32>,
+    #[serde(default)]
+    pub gid: Option<u32>,
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// bwrap executable to run, instead of `$SHWRAP_BWRAP_PATH` or `bwrap`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bwrap_path: Option<String>,
+    /// Run as uid/gid 0 in the sandbox (overrides uid and gid)
+    #[serde(default)]
+    pub run_as_root: bool,
+    /// Apply the strict preset (see WrappedCommandBuilder::build_args)
+    #[serde(default)]
+    pub harden: bool,
+    /// Give access to the display server (see DesktopAccess)
+    #[serde(default)]
+    pub gui: bool,
+    /// Give access to the PulseAudio/PipeWire sockets (see DesktopAccess)
+    #[serde(default)]
+    pub audio: bool,
+    /// Message bus exposed to the sandbox, unset to leave the bus variables
+    /// alone (see DesktopAccess)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dbus: Option<Dbus>,
+    /// Network mode, unset to follow `share` (see RestrictedNetwork)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<Network>,
+    /// Hosts reachable by name with `network: restricted`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow_hosts: Vec<String>,
+    /// Give access to the controlling terminal (implies no new session)
+    #[serde(default)]
+    pub allow_tty: bool,
+    /// Mount a tmpfs on `/` first, so only bound paths are visible
+    #[serde(default)]
+    pub root_tmpfs: bool,
+    /// Keep the writes of overlays without upper/work directories in the cache
+    /// directory, across runs (see WrappedCommandBuilder::from_config)
+    #[serde(default)]
+    pub overlay_cache: bool,
+    /// Mount a new procfs at this path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proc: Option<String>,
+    /// Mount a procfs on `/proc` if the pid namespace is unshared without one
+    #[serde(default)]
+    pub auto_proc: bool,
+    /// Run in a new terminal session (defaults to false, or true with harden)
+    #[serde(default)]
+    pub new_session: Option<bool>,
+    #[serde(default)]
+    pub allow_args: Vec<String>,
+    #[serde(default)]
+    pub deny_args: Vec<String>,
+    /// Arguments always passed to the command, before the given ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prepend_args: Vec<String>,
+    /// Arguments always passed to the command, after the given ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub append_args: Vec<String>,
+    /// Shell commands run on the host (unsandboxed) before the command
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_exec: Vec<String>,
+    /// Named variants merged over the entry when selected
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, Entry>,
+    /// How list fields of the extended models are merged, by field name
+    /// (append when unset, see MERGE_FIELDS)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub merge: HashMap<String, MergeStrategy>,
+}
+
+/// Whether an entry is enabled, unconditionally or on matching hosts only
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Enabled {
+    Always(bool),
+    When(HostCondition),
+}
+
+impl Default for Enabled {
+    fn default() -> Self {
+        Enabled::Always(true)
+    }
+}
+
+/// Fields supporting a merge strategy
+pub const MERGE_FIELDS: &[&str] = &[
+    "share",
+    "dir",
+    "bind",
+    "bind_try",
+    "ro_bind",
+    "ro_bind_try",
+    "dev_bind",
+    "tmpfs",
+    "overlay",
+    "remount_ro",
+    "files",
+    "lock_file",
+    "inherit_fds",
+    "env",
+    "env_file",
+    "env_prefix_passthrough",
+    "unset_env",
+    "allow_args",
+    "deny_args",
+    "prepend_args",
+    "append_args",
+    "pre_exec",
+    "allow_hosts",
+];
+
+/// How a field of an entry is merged with the one of the model it extends
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeStrategy {
+    /// Keep the values of both (the entry wins on `env` keys)
+    #[default]
+    Append,
+    /// Keep the values of the entry only
+    Replace,
+}
+
+/// DBus bus an entry is given access to, `none` hides the session bus
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dbus {
+    None,
+    Session,
+    System,
+}
+
+/// Problem found by Config::validate
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    /// Name of the offending entry
+    pub entry: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid configuration for '{}': {}",
+            self.entry, self.message
+        )
+    }
+}
+
+/// Network mode of an entry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    /// Own network through a user-mode helper, only listed hosts resolve
+    Restricted,
+}
+
+/// Host platform an entry is restricted to (unset fields match any host)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostCondition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+}
+
+impl HostCondition {
+    /// Get why the condition doesn't match the given host, if it doesn't
+    pub fn mismatch(&self, os: &str, arch: &str) -> Option<String> {
+        let mut reasons = vec![];
+        if let Some(expected) = &self.os
+            && expected != os
+        {
+            reasons.push(format!("requires os {} (host: {})", expected, os));
+        }
+        if let Some(expected) = &self.arch
+            && expected != arch
+        {
+            reasons.push(format!("requires arch {} (host: {})", expected, arch));
+        }
+
+        match reasons.is_empty() {
+            true => None,
+            false => Some(reasons.join(", ")),
+        }
+    }
+}
+
+/// Render a YAML value of a diff on one line
+fn diff_value_str(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Null => "null".to_string(),
+        Value::Bool(_) | Value::Number(_) => serde_yaml::to_string(value)
+            .map(|value| value.trim_end().to_string())
+            .unwrap_or_default(),
+        _ => serde_json::to_string(value).unwrap_or_default(),
+    }
+}
+
+/// Push the items of a list removed from `old` and added to `new`
+fn diff_items(lines: &mut Vec<String>, field: &str, old: &[Value], new: &[Value]) {
+    for item in old.iter().filter(|item| !new.contains(item)) {
+        lines.push(format!("- {}: {}", field, diff_value_str(item)));
+    }
+    for item in new.iter().filter(|item| !old.contains(item)) {
+        lines.push(format!("+ {}: {}", field, diff_value_str(item)));
+    }
+}
+
+/// Push the change of a value, if any
+fn diff_scalar(lines: &mut Vec<String>, field: &str, old: Option<&Value>, new: Option<&Value>) {
+    match (old, new) {
+        (old, new) if old == new => {}
+        (Some(old), None) => lines.push(format!("- {}: {}", field, diff_value_str(old))),
+        (None, Some(new)) => lines.push(format!("+ {}: {}", field, diff_value_str(new))),
+        (Some(old), Some(new)) => lines.push(format!(
+            "~ {}: {} -> {}",
+            field,
+            diff_value_str(old),
+            diff_value_str(new)
+        )),
+        (None, None) => {}
+    }
+}
+
+impl Entry {
+    /// Check if the entry is enabled on this host
+    pub fn is_enabled(&self) -> bool {
+        match &self.enabled {
+            Enabled::Always(enabled) => *enabled,
+            Enabled::When(condition) => condition
+                .mismatch(std::env::consts::OS, std::env::consts::ARCH)
+                .is_none(),
+        }
+    }
+
+    /// Get why the entry is disabled on this host by its condition, if it is
+    pub fn disabled_reason(&self) -> Option<String> {
+        match &self.enabled {
+            Enabled::Always(_) => None,
+            Enabled::When(condition) => {
+                condition.mismatch(std::env::consts::OS, std::env::consts::ARCH)
+            }
+        }
+    }
+
+    /// Replace `${NAME}` references to vars in mount paths and env values (profiles included)
+    fn substitute_vars(&mut self, name: &str, vars: &HashMap<String, String>) -> Result<()> {
+        for (field, values) in [
+            ("bind", &mut self.bind),
+            ("bind_try", &mut self.bind_try),
+            ("ro_bind_try", &mut self.ro_bind_try),
+            ("dev_bind", &mut self.dev_bind),
+            ("tmpfs", &mut self.tmpfs),
+            ("lock_file", &mut self.lock_file),
+        ] {
+            for value in values.iter_mut() {
+                *value = substitute_vars(value, vars)
+                    .context(format!("Invalid value in '{}.{}'", name, field))?;
+            }
+        }
+        for ro_bind in self.ro_bind.iter_mut() {
+            ro_bind
+                .substitute_vars(vars)
+                .context(format!("Invalid value in '{}.ro_bind'", name))?;
+        }
+        for (key, value) in self.env.iter_mut() {
+            *value = substitute_vars(value, vars)
+                .context(format!("Invalid value in '{}.env.{}'", name, key))?;
+        }
+        for (profile_name, profile) in self.profiles.iter_mut() {
+            profile.substitute_vars(&format!("{}.profiles.{}", name, profile_name), vars)?;
+        }
+
+        Ok(())
+    }
+
+    /// Merge the given profile over this entry
+    /// Profile lists are appended, its env values and options win
+    pub fn with_profile(mut self, name: &str) -> Result<Entry> {
+        let profile = self
+            .profiles
+            .remove(name)
+            .context(format!("No profile '{}' found", name))?;
+        self.profiles.clear();
+
+        if profile.extends.is_some() {
+            self.extends = profile.extends;
+        }
+        self.share.extend(profile.share);
+        self.dir.extend(profile.dir);
+        self.bind.extend(profile.bind);
+        self.bind_try.extend(profile.bind_try);
+        self.ro_bind.extend(profile.ro_bind);
+        self.ro_bind_try.extend(profile.ro_bind_try);
+        self.dev_bind.extend(profile.dev_bind);
+        self.tmpfs.extend(profile.tmpfs);
+        self.overlay.extend(profile.overlay);
+        self.remount_ro.extend(profile.remount_ro);
+        self.files.extend(profile.files);
+        self.lock_file.extend(profile.lock_file);
+        self.inherit_fds.extend(profile.inherit_fds);
+        self.env.extend(profile.env);
+        self.env_file.extend(profile.env_file);
+        self.env_prefix_passthrough
+            .extend(profile.env_prefix_passthrough);
+        self.unset_env.extend(profile.unset_env);
+        self.allow_args.extend(profile.allow_args);
+        self.deny_args.extend(profile.deny_args);
+        self.prepend_args.extend(profile.prepend_args);
+        self.append_args.extend(profile.append_args);
+        self.pre_exec.extend(profile.pre_exec);
+        if profile.uid.is_some() {
+            self.uid = profile.uid;
+        }
+        if profile.gid.is_some() {
+            self.gid = profile.gid;
+        }
+        if profile.timeout.is_some() {
+            self.timeout = profile.timeout;
+        }
+        self.harden |= profile.harden;
+        self.run_as_root |= profile.run_as_root;
+        if profile.bwrap_path.is_some() {
+            self.bwrap_path = profile.bwrap_path;
+        }
+        self.root_tmpfs |= profile.root_tmpfs;
+        if profile.proc.is_some() {
+            self.proc = profile.proc;
+        }
+        self.auto_proc |= profile.auto_proc;
+        self.overlay_cache |= profile.overlay_cache;
+        self.merge.extend(profile.merge);
+        self.allow_tty |= profile.allow_tty;
+        self.gui |= profile.gui;
+        self.audio |= profile.audio;
+        if profile.dbus.is_some() {
+            self.dbus = profile.dbus.clone();
+        }
+        if profile.network.is_some() {
+            self.network = profile.network.clone();
+        }
+        self.allow_hosts.extend(profile.allow_hosts);
+        if profile.new_session.is_some() {
+            self.new_session = profile.new_session;
+        }
+
+        Ok(self)
+    }
+
+    /// Check command arguments against the allow_args and deny_args patterns
+    /// Each argument must match an allow pattern (if any) and no deny pattern
+    pub fn check_args(&self, args: &[String]) -> Result<()> {
+        let allow = compile_patterns(&self.allow_args)?;
+        let deny = compile_patterns(&self.deny_args)?;
+
+        for arg in args {
+            if let Some(pattern) = deny.iter().find(|pattern| pattern.is_match(arg)) {
+                bail!("Argument '{}' is denied by pattern '{}'", arg, pattern);
+            }
+            if !allow.is_empty() && !allow.iter().any(|pattern| pattern.is_match(arg)) {
+                bail!("Argument '{}' is not allowed", arg);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the arguments passed to the command: prepend_args, the given ones
+    /// then append_args
+    pub fn command_args(&self, args: &[String]) -> Vec<String> {
+        self.prepend_args
+            .iter()
+            .chain(args)
+            .chain(&self.append_args)
+            .cloned()
+            .collect()
+    }
+
+    /// Get the namespaces shared with the host and the unshared ones, in NAMESPACES order
+    /// Namespaces not in `share` are unshared (unknown names are ignored), a
+    /// restricted network is unshared too (in the namespace of the network helper)
+    pub fn effective_namespaces(&self) -> (Vec<&'static str>, Vec<&'static str>) {
+        let restricted = self.network == Some(Network::Restricted);
+
+        NAMESPACES.iter().partition(|namespace| {
+            let shared = self.share.iter().any(|shared| shared == *namespace);
+            shared && !(restricted && **namespace == "network")
+        })
+    }
+
+    /// Get the differences of the fields of `other` from this entry, one per line:
+    /// `- field: value` only here, `+ field: value` only in `other`, and
+    /// `~ field: value -> value` changed
+    /// Lists (binds, share) and maps (env) are compared by items
+    pub fn diff(&self, other: &Entry) -> Result<Vec<String>> {
+        let (Value::Mapping(old), Value::Mapping(new)) =
+            (serde_yaml::to_value(self)?, serde_yaml::to_value(other)?)
+        else {
+            bail!("Entries are not mappings");
+        };
+
+        let mut lines = Vec::new();
+        let fields = old
+            .keys()
+            .chain(new.keys().filter(|key| !old.contains_key(*key)));
+        for field in fields {
+            let field = diff_value_str(field);
+            match (old.get(&field), new.get(&field)) {
+                (old, new) if old == new => {}
+                (Some(Value::Sequence(old)), Some(Value::Sequence(new))) => {
+                    diff_items(&mut lines, &field, old, new)
+                }
+                (Some(Value::Sequence(old)), None) => diff_items(&mut lines, &field, old, &[]),
+                (None, Some(Value::Sequence(new))) => diff_items(&mut lines, &field, &[], new),
+                (old, new)
+                    if [old, new]
+                        .iter()
+                        .all(|value| value.is_none_or(Value::is_mapping)) =>
+                {
+                    let empty = Mapping::new();
+                    let old = old.and_then(Value::as_mapping).unwrap_or(&empty);
+                    let new = new.and_then(Value::as_mapping).unwrap_or(&empty);
+                    let mut keys: Vec<String> =
+                        old.keys().chain(new.keys()).map(diff_value_str).collect();
+                    keys.sort();
+                    keys.dedup();
+                    for key in keys {
+                        let name = format!("{}.{}", field, key);
+                        diff_scalar(&mut lines, &name, old.get(&key), new.get(&key));
+                    }
+                }
+                (old, new) => diff_scalar(&mut lines, &field, old, new),
+            }
+        }
+
+        Ok(lines)
+    }
+
+    /// Check that uid/gid are only set along with an unshared user namespace
+    /// bwrap refuses --uid and --gid otherwise
+    pub fn check_id_mapping(&self) -> Result<()> {
+        let (shared, _) = self.effective_namespaces();
+        let shared_user = shared.contains(&"user");
+        if (self.uid.is_some() || self.gid.is_some()) && shared_user {
+            bail!("'uid' and 'gid' require the user namespace to be unshared");
+        }
+        if self.run_as_root && shared_user {
+            bail!("'run_as_root' requires the user namespace to be unshared");
+        }
+
+        Ok(())
+    }
+
+    /// Check that `network: restricted` comes with an unshared network namespace
+    /// and the hosts to allow
+    pub fn check_network(&self) -> Result<()> {
+        let restricted = self.network == Some(Network::Restricted);
+        if restricted && self.share.iter().any(|namespace| namespace == "network") {
+            bail!("'network: restricted' requires the network namespace to be unshared");
+        }
+        if restricted && self.allow_hosts.is_empty() {
+            bail!("'network: restricted' requires a non-empty 'allow_hosts' list");
+        }
+        if !restricted && !self.allow_hosts.is_empty() {
+            bail!("'allow_hosts' requires 'network: restricted'");
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the fields of an entry replacing the ones of the models it extends
+fn replaced_fields(entry: &Entry) -> HashSet<String> {
+    entry
+        .merge
+        .iter()
+        .filter(|(_, strategy)| **strategy == MergeStrategy::Replace)
+        .map(|(field, _)| field.clone())
+        .collect()
+}
+
+fn compile_patterns(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).context(format!("Invalid argument pattern '{}'", pattern))
+        })
+        .collect()
+}
+
+/// `${NAME}` reference to a var
+static VAR_REFERENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap());
+
+/// Replace `${NAME}` references with their value in vars, undefined names are
+/// errors whatever the host environment (`$NAME` references environment
+/// variables, expanded at build time)
+fn substitute_vars(value: &str, vars: &HashMap<String, String>) -> Result<String> {
+    let mut result = String::new();
+    let mut last = 0;
+
+    for captures in VAR_REFERENCE.captures_iter(value) {
+        let reference = captures.get(0).unwrap();
+        let name = &captures[1];
+
+        result.push_str(&value[last..reference.start()]);
+        match vars.get(name) {
+            Some(var) => result.push_str(var),
+            None => bail!("Undefined variable '{}'", reference.as_str()),
+        }
+        last = reference.end();
+    }
+    result.push_str(&value[last..]);
+
+    Ok(result)
+}
+
+/// Overlay filesystem mounted at `dst` on top of the read-only `src`
+///
+/// Writes go to `upper` (with `work` as scratch space), or to an ephemeral
+/// tmpfs when `tmp` is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Overlay {
+    pub src: String,
+    pub dst: String,
+    #[serde(default)]
+    pub upper: Option<String>,
+    #[serde(default)]
+    pub work: Option<String>,
+    #[serde(default)]
+    pub tmp: bool,
+}
+
+impl Overlay {
+    /// Check that a persistent overlay has both its upper and work directories
+    pub fn validate(&self) -> Result<()> {
+        if !self.tmp && (self.upper.is_none() || self.work.is_none()) {
+            bail!(
+                "overlay on '{}' needs both 'upper' and 'work' (or 'tmp: true')",
+                self.dst
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Read-only bind of a path at the same location, either a plain path
+/// or `{ path, exclude }` to hide subpaths under a tmpfs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RoBind {
+    Path(String),
+    Tree {
+        path: String,
+        #[serde(default)]
+        exclude: Vec<String>,
+    },
+}
+
+impl RoBind {
+    pub fn path(&self) -> &str {
+        match self {
+            RoBind::Path(path) | RoBind::Tree { path, .. } => path,
+        }
+    }
+
+    /// Get the subpaths hidden under a tmpfs after the bind
+    pub fn exclude(&self) -> &[String] {
+        match self {
+            RoBind::Path(_) => &[],
+            RoBind::Tree { exclude, .. } => exclude,
+        }
+    }
+
+    fn substitute_vars(&mut self, vars: &HashMap<String, String>) -> Result<()> {
+        match self {
+            RoBind::Path(path) => *path = substitute_vars(path, vars)?,
+            RoBind::Tree { path, exclude } => {
+                *path = substitute_vars(path, vars)?;
+                for subpath in exclude.iter_mut() {
+                    *subpath = substitute_vars(subpath, vars)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl From<&str> for RoBind {
+    fn from(path: &str) -> Self {
+        RoBind::Path(path.to_string())
+    }
+}
+
+impl fmt::Display for RoBind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.exclude() {
+            [] => f.write_str(self.path()),
+            exclude => write!(f, "{} (excluding {})", self.path(), exclude.join(", ")),
+        }
+    }
+}
+
+/// Check if a config document uses the old layout: entries nested under
+/// `commands` and `templates` maps, without a version
+pub fn is_legacy_document(document: &Mapping) -> bool {
+    if document.contains_key("version") {
+        return false;
+    }
+
+    // A `commands` command entry would have fields, not entries
+    let is_entry_map = |value: &Value| {
+        value.as_mapping().is_some_and(|entries| {
+            entries
+                .values()
+                .all(|entry| entry.is_null() || entry.is_mapping())
+                && !["env", "profiles", "enabled"]
+                    .iter()
+                    .any(|field| entries.contains_key(*field))
+        })
+    };
+
+    ["commands", "templates"]
+        .iter()
+        .any(|key| document.get(*key).is_some_and(is_entry_map))
+}
+
+/// Point a YAML error at its location, with the offending line
+fn yaml_error(yaml: &str, err: serde_yaml::Error) -> anyhow::Error {
+    let Some(location) = err.location() else {
+        return err.into();
+    };
+    let line = yaml.lines().nth(location.line() - 1).unwrap_or_default();
+    let gutter = location.line().to_string();
+
+    anyhow!(
+        "{}\n {} | {}\n {} | {}^",
+        err,
+        gutter,
+        line,
+        " ".repeat(gutter.len()),
+        " ".repeat(location.column() - 1)
+    )
+}
+
+/// Upgrade a config document to the current layout
+/// Old `commands` entries are moved to the top level, old `templates` become
+/// entries with `type: model`
+pub fn migrate_document(document: Mapping) -> Mapping {
+    let legacy = is_legacy_document(&document);
+    let mut migrated = Mapping::new();
+    migrated.insert(Value::from("version"), Value::from(CONFIG_VERSION));
+
+    for (key, value) in document {
+        match key.as_str() {
+            Some("version") => {}
+            Some("commands") if legacy => {
+                if let Value::Mapping(entries) = value {
+                    migrated.extend(entries);
+                }
+            }
+            Some("templates") if legacy => {
+                if let Value::Mapping(entries) = value {
+                    for (name, entry) in entries {
+                        let mut entry = match entry {
+                            Value::Mapping(entry) => entry,
+                            _ => Mapping::new(),
+                        };
+                        entry.insert(Value::from("type"), Value::from("model"));
+                        migrated.insert(name, Value::Mapping(entry));
+                    }
+                }
+            }
+            _ => {
+                migrated.insert(key, value);
+            }
+        }
+    }
+
+    migrated
 }
 
-fn default_enabled() -> bool {
-    true
+/// File created at `dest` inside the sandbox, streamed to bwrap from memory
+/// so it never exists on the host
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InlineFile {
+    pub dest: String,
+    pub content: String,
 }
 
 impl Config {
     pub fn from_yaml(yaml: &str) -> Result<Self> {
-        let config: Config = serde_yaml::from_str(yaml).context("Failed to parse YAML config")?;
+        let mut config = Self::parse(yaml).context("Failed to parse YAML config")?;
+        config.substitute_vars()?;
 
         Ok(config)
     }
 
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        debug!(path = %path.as_ref().display(), "loading config");
         let yaml = fs::read_to_string(path.as_ref())
             .context(format!("Failed to read config file: {:?}", path.as_ref()))?;
 
-        let config: Config = serde_yaml::from_str(&yaml)
-            .context(format!("Failed to parse YAML config {:?}", path.as_ref()))?;
+        let mut config = Self::from_yaml_with_source(&yaml, &format!("{:?}", path.as_ref()))?;
+        let path = fs::canonicalize(path.as_ref()).unwrap_or(path.as_ref().to_path_buf());
+        config.base_dir = path.parent().map(Path::to_path_buf);
+        config.path = Some(path);
+
+        Ok(config)
+    }
+
+    /// Same as from_yaml, naming the source of the YAML (a path, or any label)
+    /// in errors and warnings
+    pub fn from_yaml_with_source(yaml: &str, source: &str) -> Result<Self> {
+        let mut config =
+            Self::parse(yaml).context(format!("Failed to parse YAML config in {}", source))?;
+        config
+            .substitute_vars()
+            .context(format!("Invalid config in {}", source))?;
+        for warning in config.warnings.iter_mut() {
+            *warning = format!("{} {}", source, warning);
+        }
+
+        Ok(config)
+    }
+
+    /// Parse a config, upgrading the old layout in memory
+    /// Warns about old or missing versions
+    fn parse(yaml: &str) -> Result<Self> {
+        let document: Value = serde_yaml::from_str(yaml).map_err(|err| yaml_error(yaml, err))?;
+
+        let mut config: Config = match document {
+            Value::Mapping(document) if is_legacy_document(&document) => {
+                let mut config: Config =
+                    serde_yaml::from_value(Value::Mapping(migrate_document(document)))?;
+                config.warnings.push(
+                    "uses the old 'commands'/'templates' layout, run 'shwrap config migrate' to upgrade it"
+                        .to_string(),
+                );
+                return Ok(config);
+            }
+            _ => serde_yaml::from_str(yaml).map_err(|err| yaml_error(yaml, err))?,
+        };
+
+        match config.version {
+            None => config.warnings.push(format!(
+                "has no 'version' field, run 'shwrap config migrate' to set it to {}",
+                CONFIG_VERSION
+            )),
+            Some(version) if version < CONFIG_VERSION => config.warnings.push(format!(
+                "uses the old version {}, run 'shwrap config migrate' to upgrade it",
+                version
+            )),
+            Some(version) if version > CONFIG_VERSION => config.warnings.push(format!(
+                "uses version {}, newer than the supported version {}",
+                version, CONFIG_VERSION
+            )),
+            Some(_) => {}
+        }
 
         Ok(config)
     }
 
+    /// Replace `${NAME}` references to vars in mount paths and env values
+    fn substitute_vars(&mut self) -> Result<()> {
+        for (name, entry) in self.entries.iter_mut() {
+            entry.substitute_vars(name, &self.vars)?;
+        }
+
+        Ok(())
+    }
+
+    /// Merge another config on top of this one (entries of `other` win by name)
+    pub fn merge(&mut self, other: Config) {
+        self.warnings.extend(other.warnings);
+        self.vars.extend(other.vars);
+
+        // Each entry keeps the file it comes from, for its relative paths
+        if let Some(path) = &self.path {
+            for name in self.entries.keys() {
+                self.entry_paths
+                    .entry(name.clone())
+                    .or_insert_with(|| path.clone());
+            }
+        }
+        for name in other.entries.keys() {
+            match &other.path {
+                Some(path) => self.entry_paths.insert(name.clone(), path.clone()),
+                None => self.entry_paths.remove(name),
+            };
+        }
+        self.entry_paths.extend(other.entry_paths);
+
+        // The last layer (the nearest config) is the base of the other paths
+        if other.base_dir.is_some() {
+            self.path = other.path;
+            self.base_dir = other.base_dir;
+        }
+        self.entries.extend(other.entries);
+    }
+
+    /// Get the config file of the entry used for a command (see get_command)
+    pub fn entry_path(&self, name: &str) -> Option<&Path> {
+        self.entry_paths
+            .get(self.entry_name(name))
+            .or(self.path.as_ref())
+            .map(PathBuf::as_path)
+    }
+
+    /// Get the directory relative paths of the entry used for a command are
+    /// resolved against: the one of the config file defining it
+    pub fn entry_base_dir(&self, name: &str) -> Option<PathBuf> {
+        match self.entry_paths.get(self.entry_name(name)) {
+            Some(path) => path.parent().map(Path::to_path_buf),
+            None => self.base_dir.clone(),
+        }
+    }
+
+    /// Get the name of the entry used for a command, the wildcard one if it has none
+    /// (a model of the same name is not the command's)
+    fn entry_name<'a>(&self, name: &'a str) -> &'a str {
+        match self.entries.get(name) {
+            Some(entry) if entry.entry_type == EntryType::Command => name,
+            _ => WILDCARD_COMMAND,
+        }
+    }
+
     /// Get all entries
     pub fn get_entries(&self) -> HashMap<String, Entry> {
         self.entries
             .iter()
-            .map(|(name, entry)| (name.clone(), entry.clone().into()))
+            .map(|(name, entry)| (name.clone(), entry.clone()))
             .collect()
     }
 
@@ -86,13 +954,13 @@ impl Config {
         self.entries
             .iter()
             .filter(|(_, entry)| predicate(entry))
-            .map(|(name, entry)| (name.clone(), entry.clone().into()))
+            .map(|(name, entry)| (name.clone(), entry.clone()))
             .collect()
     }
 
     /// Get a specific command configuration
     pub fn get_entry(&self, command: &str) -> Option<Entry> {
-        self.entries.get(command).map(|entry| entry.clone().into())
+        self.entries.get(command).cloned()
     }
 
     /// Get an entry with constrains
@@ -103,7 +971,7 @@ impl Config {
         self.entries
             .get(name)
             .filter(|entry| predicate(entry))
-            .map(|entry| entry.clone().into())
+            .cloned()
     }
 
     /// Get all command entries (filtering by type: command)
@@ -111,16 +979,37 @@ impl Config {
         self.entries
             .iter()
             .filter(|(_, entry)| entry.entry_type == EntryType::Command)
-            .map(|(name, entry)| (name.clone(), entry.clone().into()))
+            .map(|(name, entry)| (name.clone(), entry.clone()))
+            .collect()
+    }
+
+    /// Get the enabled command entries, merged with their models, sorted by name
+    pub fn get_enabled_commands(&self) -> BTreeMap<String, Entry> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.entry_type == EntryType::Command && entry.is_enabled())
+            .map(|(name, entry)| (name.clone(), self.merge_with_template(entry.clone())))
+            .collect()
+    }
+
+    /// Get the disabled command entries (conditions included), merged with
+    /// their models, sorted by name
+    pub fn get_disabled_commands(&self) -> BTreeMap<String, Entry> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.entry_type == EntryType::Command && !entry.is_enabled())
+            .map(|(name, entry)| (name.clone(), self.merge_with_template(entry.clone())))
             .collect()
     }
 
     /// Get a specific command configuration
+    /// Falls back to the wildcard entry ("*") when the command has no entry, or
+    /// only a model of that name
     pub fn get_command(&self, name: &str) -> Option<Entry> {
         self.entries
-            .get(name)
+            .get(self.entry_name(name))
             .filter(|entry| entry.entry_type == EntryType::Command)
-            .map(|entry| entry.clone().into())
+            .cloned()
     }
 
     /// Get all model entries (filtering by type: command)
@@ -128,7 +1017,7 @@ impl Config {
         self.entries
             .iter()
             .filter(|(_, entry)| entry.entry_type == EntryType::Model)
-            .map(|(name, entry)| (name.clone(), entry.clone().into()))
+            .map(|(name, entry)| (name.clone(), entry.clone()))
             .collect()
     }
 
@@ -137,35 +1026,245 @@ impl Config {
         self.entries
             .get(name)
             .filter(|entry| entry.entry_type == EntryType::Model)
-            .map(|entry| entry.clone().into())
+            .cloned()
     }
 
-    /// Merge command config with its template (if extends is set)
-    pub fn merge_with_template(&self, mut cmd_config: Entry) -> Entry {
-        if let Some(extends) = &cmd_config.extends {
-            if let Some(template) = self.get_model(extends) {
-                // Merge template config into command config
-                cmd_config.share.extend(template.share.clone());
-                cmd_config.bind.extend(template.bind.clone());
-                cmd_config.ro_bind.extend(template.ro_bind.clone());
-                cmd_config.dev_bind.extend(template.dev_bind.clone());
-                cmd_config.tmpfs.extend(template.tmpfs.clone());
-                // Merge env vars (command-specific takes precedence)
-                for (key, value) in template.env.iter() {
-                    cmd_config.env.entry(key.clone()).or_insert(value.clone());
-                }
-                cmd_config.unset_env.extend(template.unset_env.clone());
+    /// Get the names of the models an entry extends, nearest first
+    /// Fails if the extends chain loops back on itself
+    pub fn get_extends_chain(&self, name: &str) -> Result<Vec<String>> {
+        let mut visited = vec![name.to_string()];
+        let mut current = self.entries.get(name).and_then(|e| e.extends.clone());
+
+        while let Some(extends) = current {
+            if let Some(start) = visited.iter().position(|n| *n == extends) {
+                let mut cycle = visited[start..].to_vec();
+                cycle.push(extends);
+                bail!("Circular extends chain: {}", cycle.join(" -> "));
             }
+
+            let Some(model) = self.get_model(&extends) else {
+                break;
+            };
+            current = model.extends.clone();
+            visited.push(extends);
         }
 
-        cmd_config
+        Ok(visited.split_off(1))
     }
 
-    // Deprecated: use merge_with_template instead
-    pub fn merge_with_base(&self, cmd_config: Entry) -> Entry {
-        self.merge_with_template(cmd_config)
+    /// Check every entry for circular extends chains
+    pub fn check_extends(&self) -> Result<()> {
+        let mut names: Vec<_> = self.entries.keys().collect();
+        names.sort();
+
+        for name in names {
+            self.get_extends_chain(name)?;
+        }
+
+        Ok(())
     }
-}
+
+    /// Collect every problem of the config, across all entries
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        let mut names: Vec<_> = self.entries.keys().collect();
+        names.sort();
+
+        for name in names {
+            let entry = &self.entries[name];
+            let mut report = |message: String| {
+                errors.push(ValidationError {
+                    entry: name.clone(),
+                    message,
+                })
+            };
+
+            for namespace in &entry.share {
+                if !NAMESPACES.contains(&namespace.as_str()) {
+                    report(format!("unknown namespace '{}' in share", namespace));
+                }
+            }
+            for bind in entry.bind.iter().chain(&entry.bind_try) {
+                if bind.split(':').count() != 2 {
+                    report(format!("invalid bind format '{}'", bind));
+                }
+            }
+            let mut merge_fields: Vec<_> = entry.merge.keys().collect();
+            merge_fields.sort();
+            for field in merge_fields {
+                if !MERGE_FIELDS.contains(&field.as_str()) {
+                    report(format!("unknown field '{}' in merge", field));
+                }
+            }
+            if let Some(extends) = &entry.extends
+                && self.get_model(extends).is_none()
+            {
+                report(format!("extends unknown model '{}'", extends));
+            }
+            if let Err(err) = self.get_extends_chain(name) {
+                report(err.to_string());
+                // The merged entry can't be built
+                continue;
+            }
+
+            if entry.entry_type == EntryType::Command {
+                let merged = self.merge_with_template(entry.clone());
+                for check in [Entry::check_id_mapping, Entry::check_network] {
+                    if let Err(err) = check(&merged) {
+                        report(err.to_string());
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+
+    /// Merge command config with its template (if extends is set)
+    /// Templates can extend other templates, the nearest one taking precedence
+    pub fn merge_with_template(&self, mut cmd_config: Entry) -> Entry {
+        let _span = debug_span!("merge_with_template", extends = ?cmd_config.extends).entered();
+        let mut visited = HashSet::new();
+        let mut current = cmd_config.extends.clone();
+        let mut replaced = replaced_fields(&cmd_config);
+
+        // Stop on cycles (reported by get_extends_chain)
+        while let Some(extends) = current
+            && visited.insert(extends.clone())
+            && let Some(template) = self.get_model(&extends)
+        {
+            trace!(model = %extends, "merging model");
+            let appends = |field: &str| !replaced.contains(field);
+            // Merge template config into command config
+            if appends("share") {
+                cmd_config.share.extend(template.share.clone());
+            }
+            if appends("dir") {
+                cmd_config.dir.extend(template.dir.clone());
+            }
+            if appends("bind") {
+                cmd_config.bind.extend(template.bind.clone());
+            }
+            if appends("bind_try") {
+                cmd_config.bind_try.extend(template.bind_try.clone());
+            }
+            if appends("ro_bind") {
+                cmd_config.ro_bind.extend(template.ro_bind.clone());
+            }
+            if appends("ro_bind_try") {
+                cmd_config.ro_bind_try.extend(template.ro_bind_try.clone());
+            }
+            if appends("dev_bind") {
+                cmd_config.dev_bind.extend(template.dev_bind.clone());
+            }
+            if appends("tmpfs") {
+                cmd_config.tmpfs.extend(template.tmpfs.clone());
+            }
+            if appends("overlay") {
+                cmd_config.overlay.extend(template.overlay.clone());
+            }
+            if appends("remount_ro") {
+                cmd_config.remount_ro.extend(template.remount_ro.clone());
+            }
+            if appends("files") {
+                cmd_config.files.extend(template.files.clone());
+            }
+            if appends("lock_file") {
+                cmd_config.lock_file.extend(template.lock_file.clone());
+            }
+            if appends("inherit_fds") {
+                cmd_config.inherit_fds.extend(template.inherit_fds.clone());
+            }
+            // Merge env vars (command-specific takes precedence)
+            if appends("env") {
+                for (key, value) in template.env.iter() {
+                    cmd_config.env.entry(key.clone()).or_insert(value.clone());
+                }
+            }
+            // Template env files are read first, so the command ones override them
+            if appends("env_file") {
+                cmd_config
+                    .env_file
+                    .splice(0..0, template.env_file.iter().cloned());
+            }
+            if appends("env_prefix_passthrough") {
+                cmd_config
+                    .env_prefix_passthrough
+                    .extend(template.env_prefix_passthrough.clone());
+            }
+            if appends("unset_env") {
+                cmd_config.unset_env.extend(template.unset_env.clone());
+            }
+            if appends("allow_args") {
+                cmd_config.allow_args.extend(template.allow_args.clone());
+            }
+            if appends("deny_args") {
+                cmd_config.deny_args.extend(template.deny_args.clone());
+            }
+            // Template arguments are the outer ones
+            if appends("prepend_args") {
+                cmd_config
+                    .prepend_args
+                    .splice(0..0, template.prepend_args.iter().cloned());
+            }
+            if appends("append_args") {
+                cmd_config.append_args.extend(template.append_args.clone());
+            }
+            // Template setup steps run first
+            if appends("pre_exec") {
+                cmd_config
+                    .pre_exec
+                    .splice(0..0, template.pre_exec.iter().cloned());
+            }
+            if cmd_config.uid.is_none() {
+                cmd_config.uid = template.uid;
+            }
+            if cmd_config.gid.is_none() {
+                cmd_config.gid = template.gid;
+            }
+            if cmd_config.timeout.is_none() {
+                cmd_config.timeout = template.timeout;
+            }
+            cmd_config.harden |= template.harden;
+            cmd_config.run_as_root |= template.run_as_root;
+            if cmd_config.bwrap_path.is_none() {
+                cmd_config.bwrap_path = template.bwrap_path.clone();
+            }
+            cmd_config.root_tmpfs |= template.root_tmpfs;
+            if cmd_config.proc.is_none() {
+                cmd_config.proc = template.proc.clone();
+            }
+            cmd_config.auto_proc |= template.auto_proc;
+            cmd_config.overlay_cache |= template.overlay_cache;
+            cmd_config.allow_tty |= template.allow_tty;
+            cmd_config.gui |= template.gui;
+            cmd_config.audio |= template.audio;
+            if cmd_config.dbus.is_none() {
+                cmd_config.dbus = template.dbus.clone();
+            }
+            if cmd_config.network.is_none() {
+                cmd_config.network = template.network.clone();
+            }
+            if appends("allow_hosts") {
+                cmd_config.allow_hosts.extend(template.allow_hosts.clone());
+            }
+            if cmd_config.new_session.is_none() {
+                cmd_config.new_session = template.new_session;
+            }
+
+            // A replace on the model applies to the models it extends
+            replaced.extend(replaced_fields(&template));
+            current = template.extends;
+        }
+
+        cmd_config
+    }
+
+    // Deprecated: use merge_with_template instead
+    pub fn merge_with_base(&self, cmd_config: Entry) -> Entry {
+        self.merge_with_template(cmd_config)
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -191,7 +1290,7 @@ mod tests {
         assert!(commands.contains_key("node"));
 
         let node_cmd = commands.get("node").unwrap();
-        assert!(node_cmd.enabled);
+        assert!(node_cmd.is_enabled());
         assert_eq!(node_cmd.share, vec!["user", "network"]);
         assert_eq!(node_cmd.bind, vec!["~/.npm:~/.npm"]);
     }
@@ -219,6 +1318,73 @@ mod tests {
         assert_eq!(node_cmd.bind, vec!["~/.npm:~/.npm"]);
     }
 
+    #[test]
+    fn test_merge_configs() {
+        let mut config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              share:
+                - user
+            node:
+              share:
+                - network
+            python:
+              share:
+                - network
+        "})
+        .unwrap();
+        let local = Config::from_yaml(indoc! {"
+            node:
+              extends: base
+            ruby:
+              enabled: false
+        "})
+        .unwrap();
+
+        config.merge(local);
+
+        assert_eq!(config.get_commands().len(), 3);
+        assert!(config.get_models().contains_key("base"));
+        // Entries from the merged config replace existing ones by name
+        let node_cmd = config.get_command("node").unwrap();
+        assert!(node_cmd.share.is_empty());
+        assert_eq!(node_cmd.extends, Some("base".to_string()));
+        assert_eq!(config.get_command("python").unwrap().share, vec!["network"]);
+        assert!(!config.get_command("ruby").unwrap().is_enabled());
+    }
+
+    #[test]
+    fn test_merge_configs_entry_base_dir() {
+        let mut config = Config::from_yaml("node: {}\n'*': {}\npython: {}\n").unwrap();
+        config.path = Some(PathBuf::from("/home/me/.config/shwrap/shwrap.yaml"));
+        config.base_dir = Some(PathBuf::from("/home/me/.config/shwrap"));
+        let mut local = Config::from_yaml("python: {}\nruby: {}\n").unwrap();
+        local.path = Some(PathBuf::from("/srv/app/.shwrap.yaml"));
+        local.base_dir = Some(PathBuf::from("/srv/app"));
+
+        config.merge(local);
+
+        let base_dir = |name: &str| config.entry_base_dir(name).unwrap();
+        assert_eq!(base_dir("node"), Path::new("/home/me/.config/shwrap"));
+        assert_eq!(base_dir("python"), Path::new("/srv/app"));
+        assert_eq!(base_dir("ruby"), Path::new("/srv/app"));
+        // Commands without an entry use the wildcard one
+        assert_eq!(base_dir("make"), Path::new("/home/me/.config/shwrap"));
+        assert_eq!(
+            config.entry_path("node").unwrap(),
+            Path::new("/home/me/.config/shwrap/shwrap.yaml")
+        );
+        assert_eq!(config.base_dir.unwrap(), Path::new("/srv/app"));
+
+        // A single config is the base of all its entries
+        let mut config = Config::from_yaml("node: {}\n").unwrap();
+        config.base_dir = Some(PathBuf::from("/srv/app"));
+        assert_eq!(
+            config.entry_base_dir("node").unwrap(),
+            Path::new("/srv/app")
+        );
+    }
+
     #[test]
     fn test_get_command() {
         let config = Config::from_yaml(indoc! {"
@@ -234,6 +1400,32 @@ mod tests {
         assert!(config.get_command("ruby").is_none());
     }
 
+    #[test]
+    fn test_get_command_wildcard_fallback() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+            '*':
+              share:
+                - user
+            node:
+              share:
+                - network
+        "})
+        .unwrap();
+
+        // Exact match wins over the wildcard
+        assert_eq!(config.get_command("node").unwrap().share, vec!["network"]);
+        // Unconfigured commands get the wildcard entry
+        assert_eq!(config.get_command("ruby").unwrap().share, vec!["user"]);
+        // A model is not the command of its name, the wildcard entry is used
+        assert_eq!(config.get_command("base").unwrap().share, vec!["user"]);
+
+        // Without a wildcard, models are not commands
+        let config = Config::from_yaml("base:\n  type: model\n").unwrap();
+        assert!(config.get_command("base").is_none());
+    }
+
     #[test]
     fn test_merge_with_base() {
         let config = Config::from_yaml(indoc! {"
@@ -243,116 +1435,767 @@ mod tests {
                 - user
               ro_bind:
                 - /usr
-
-            node:
-              extends: base
-              bind:
-                - ~/.npm:~/.npm
+
+            node:
+              extends: base
+              bind:
+                - ~/.npm:~/.npm
+        "})
+        .unwrap();
+        let node_cmd = config.get_command("node").unwrap();
+        let merged = config.merge_with_base(node_cmd);
+
+        // Should have both base and command-specific settings
+        assert_eq!(merged.share, vec!["user"]);
+        assert_eq!(merged.ro_bind, vec![RoBind::from("/usr")]);
+        assert_eq!(merged.bind, vec!["~/.npm:~/.npm"]);
+    }
+
+    #[test]
+    fn test_merge_model_env_and_mounts() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              env:
+                NODE_ENV: development
+                TZ: UTC
+              unset_env:
+                - DEBUG
+              dev_bind:
+                - /dev/null
+              tmpfs:
+                - /tmp
+
+            node:
+              extends: base
+              env:
+                NODE_ENV: production
+              tmpfs:
+                - /var/tmp
+        "})
+        .unwrap();
+        let node_cmd = config.get_command("node").unwrap();
+        let merged = config.merge_with_template(node_cmd);
+
+        // The command wins on the same key
+        assert_eq!(merged.env.get("NODE_ENV").unwrap(), "production");
+        assert_eq!(merged.env.get("TZ").unwrap(), "UTC");
+        assert_eq!(merged.unset_env, vec!["DEBUG"]);
+        assert_eq!(merged.dev_bind, vec!["/dev/null"]);
+        assert_eq!(merged.tmpfs, vec!["/var/tmp", "/tmp"]);
+    }
+
+    #[test]
+    fn test_merge_strategy() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              ro_bind:
+                - /usr
+              tmpfs:
+                - /tmp
+
+            node:
+              extends: base
+              ro_bind:
+                - /opt/node
+              merge:
+                ro_bind: append
+
+            deno:
+              extends: base
+              ro_bind:
+                - /opt/deno
+              merge:
+                ro_bind: replace
+        "})
+        .unwrap();
+
+        let node = config.merge_with_template(config.get_command("node").unwrap());
+        assert_eq!(
+            node.ro_bind,
+            vec![RoBind::from("/opt/node"), RoBind::from("/usr")]
+        );
+
+        // Other fields are still appended
+        let deno = config.merge_with_template(config.get_command("deno").unwrap());
+        assert_eq!(deno.ro_bind, vec![RoBind::from("/opt/deno")]);
+        assert_eq!(deno.tmpfs, vec!["/tmp"]);
+    }
+
+    #[test]
+    fn test_merge_strategy_from_model() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              ro_bind:
+                - /usr
+            minimal:
+              type: model
+              extends: base
+              ro_bind:
+                - /lib
+              merge:
+                ro_bind: replace
+
+            node:
+              extends: minimal
+              ro_bind:
+                - /opt/node
+        "})
+        .unwrap();
+
+        let node = config.merge_with_template(config.get_command("node").unwrap());
+        assert_eq!(
+            node.ro_bind,
+            vec![RoBind::from("/opt/node"), RoBind::from("/lib")]
+        );
+    }
+
+    #[test]
+    fn test_merge_injected_args() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              prepend_args: [--base-first]
+              append_args: [--base-last]
+
+            node:
+              extends: base
+              prepend_args: [--node-first]
+              append_args: [--node-last]
+        "})
+        .unwrap();
+        let node = config.merge_with_template(config.get_command("node").unwrap());
+
+        assert_eq!(
+            node.command_args(&["app.js".to_string()]),
+            vec![
+                "--base-first",
+                "--node-first",
+                "app.js",
+                "--node-last",
+                "--base-last"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_effective_namespaces() {
+        let entry = |share: &[&str], network| Entry {
+            share: share.iter().map(|ns| ns.to_string()).collect(),
+            network,
+            ..Default::default()
+        };
+
+        // Everything is unshared by default
+        let (shared, unshared) = entry(&[], None).effective_namespaces();
+        assert!(shared.is_empty());
+        assert_eq!(unshared, NAMESPACES);
+
+        // Shared ones in NAMESPACES order, whatever the config order
+        let (shared, unshared) = entry(&["network", "user"], None).effective_namespaces();
+        assert_eq!(shared, vec!["user", "network"]);
+        assert_eq!(unshared, vec!["pid", "ipc", "uts", "cgroup"]);
+
+        // Unshare only: a restricted network unshares it along with the rest
+        let (shared, unshared) = entry(&[], Some(Network::Restricted)).effective_namespaces();
+        assert!(shared.is_empty());
+        assert_eq!(unshared, NAMESPACES);
+
+        // Conflicting inputs: a restricted network is never shared
+        // (check_network reports the conflict)
+        let conflicting = entry(&["network", "user"], Some(Network::Restricted));
+        let (shared, unshared) = conflicting.effective_namespaces();
+        assert_eq!(shared, vec!["user"]);
+        assert_eq!(unshared, vec!["pid", "network", "ipc", "uts", "cgroup"]);
+        assert!(conflicting.check_network().is_err());
+
+        // Unknown names and duplicates are ignored
+        let (shared, unshared) =
+            entry(&["network", "netwrok", "network"], None).effective_namespaces();
+        assert_eq!(shared, vec!["network"]);
+        assert_eq!(unshared, vec!["user", "pid", "ipc", "uts", "cgroup"]);
+    }
+
+    #[test]
+    fn test_merge_without_extends() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              share:
+                - user
+
+            node:
+              bind:
+                - ~/.npm:~/.npm
+        "})
+        .unwrap();
+        let node_cmd = config.get_command("node").unwrap();
+        let merged = config.merge_with_base(node_cmd.clone());
+
+        // Should not merge base since extends is not set
+        assert_eq!(merged.share, node_cmd.share);
+        assert_eq!(merged.bind, node_cmd.bind);
+    }
+
+    #[test]
+    fn test_from_file() {
+        let yaml = indoc! {"
+            test:
+              enabled: true
+        "};
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(yaml.as_bytes()).unwrap();
+
+        let config = Config::from_file(temp_file.path()).unwrap();
+        let commands = config.get_commands();
+        assert_eq!(commands.len(), 1);
+        assert!(commands.contains_key("test"));
+    }
+
+    #[test]
+    fn test_default_enabled() {
+        let config = Config::from_yaml(indoc! {"
+            node:
+              share:
+                - user
+        "})
+        .unwrap();
+        let node_cmd = config.get_command("node").unwrap();
+        // enabled should default to true
+        assert!(node_cmd.is_enabled());
+    }
+
+    #[test]
+    fn test_disabled_command() {
+        let config = Config::from_yaml(indoc! {"
+            node:
+              enabled: false
+              share:
+                - user
+        "})
+        .unwrap();
+        let node_cmd = config.get_command("node").unwrap();
+        assert!(!node_cmd.is_enabled());
+    }
+
+    #[test]
+    fn test_enabled_condition_matching() {
+        let config = Config::from_yaml(&format!(
+            "node:\n  enabled:\n    os: {}\n    arch: {}\n",
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        ))
+        .unwrap();
+        let node_cmd = config.get_command("node").unwrap();
+        assert!(node_cmd.is_enabled());
+        assert_eq!(node_cmd.disabled_reason(), None);
+    }
+
+    #[test]
+    fn test_enabled_condition_not_matching() {
+        let config = Config::from_yaml(indoc! {"
+            node:
+              enabled:
+                os: plan9
+        "})
+        .unwrap();
+        let node_cmd = config.get_command("node").unwrap();
+        assert!(!node_cmd.is_enabled());
+        assert_eq!(
+            node_cmd.disabled_reason(),
+            Some(format!(
+                "requires os plan9 (host: {})",
+                std::env::consts::OS
+            ))
+        );
+
+        let condition = HostCondition {
+            os: Some("linux".to_string()),
+            arch: Some("x86_64".to_string()),
+        };
+        assert_eq!(condition.mismatch("linux", "x86_64"), None);
+        assert_eq!(
+            condition.mismatch("linux", "aarch64"),
+            Some("requires arch x86_64 (host: aarch64)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_env_variables() {
+        let config = Config::from_yaml(indoc! {"
+            node:
+              env:
+                NODE_ENV: production
+                PATH: /custom/path
+              unset_env:
+                - DEBUG
+        "})
+        .unwrap();
+        let node_cmd = config.get_command("node").unwrap();
+
+        assert_eq!(node_cmd.env.len(), 2);
+        assert_eq!(
+            node_cmd.env.get("NODE_ENV"),
+            Some(&"production".to_string())
+        );
+        assert_eq!(node_cmd.unset_env, vec!["DEBUG"]);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              timeout: 60
+            node:
+              timeout: 30
+            npm:
+              extends: base
+            python:
+              share:
+                - user
+        "})
+        .unwrap();
+
+        assert_eq!(config.get_command("node").unwrap().timeout, Some(30));
+        assert_eq!(config.get_command("python").unwrap().timeout, None);
+
+        // Timeout is inherited from the template when not set
+        let npm_cmd = config.get_command("npm").unwrap();
+        assert_eq!(config.merge_with_template(npm_cmd).timeout, Some(60));
+    }
+
+    #[test]
+    fn test_deny_args() {
+        let config = Config::from_yaml(indoc! {"
+            npm:
+              deny_args:
+                - ^--unsafe-perm
+        "})
+        .unwrap();
+        let npm_cmd = config.get_command("npm").unwrap();
+
+        let err = npm_cmd
+            .check_args(&["install".to_string(), "--unsafe-perm".to_string()])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Argument '--unsafe-perm' is denied by pattern '^--unsafe-perm'"
+        );
+        assert!(npm_cmd.check_args(&["install".to_string()]).is_ok());
+    }
+
+    #[test]
+    fn test_allow_args() {
+        let config = Config::from_yaml(indoc! {"
+            git:
+              allow_args:
+                - ^(status|log|diff)$
+                - ^--
+        "})
+        .unwrap();
+        let git_cmd = config.get_command("git").unwrap();
+
+        assert!(
+            git_cmd
+                .check_args(&["log".to_string(), "--oneline".to_string()])
+                .is_ok()
+        );
+        let err = git_cmd.check_args(&["push".to_string()]).unwrap_err();
+        assert_eq!(err.to_string(), "Argument 'push' is not allowed");
+    }
+
+    #[test]
+    fn test_invalid_args_pattern() {
+        let config = Config::from_yaml(indoc! {"
+            npm:
+              deny_args:
+                - (unclosed
+        "})
+        .unwrap();
+        let npm_cmd = config.get_command("npm").unwrap();
+        assert!(npm_cmd.check_args(&["install".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_tmpfs() {
+        let config = Config::from_yaml(indoc! {"
+            node:
+              tmpfs:
+                - /tmp
+                - /var/tmp
+        "})
+        .unwrap();
+        let node_cmd = config.get_command("node").unwrap();
+        assert_eq!(node_cmd.tmpfs, vec!["/tmp", "/var/tmp"]);
+    }
+
+    #[test]
+    fn test_overlay() {
+        let config = Config::from_yaml(indoc! {"
+            make:
+              overlay:
+                - src: ~/project
+                  dst: /workspace
+                  tmp: true
+                - src: /srv/app
+                  dst: /app
+                  upper: ~/.cache/app/upper
+                  work: ~/.cache/app/work
+        "})
+        .unwrap();
+        let make_cmd = config.get_command("make").unwrap();
+        assert_eq!(make_cmd.overlay.len(), 2);
+
+        let tmp = &make_cmd.overlay[0];
+        assert!(tmp.tmp);
+        assert_eq!(tmp.src, "~/project");
+        assert_eq!(tmp.dst, "/workspace");
+        assert!(tmp.validate().is_ok());
+
+        let persistent = &make_cmd.overlay[1];
+        assert!(!persistent.tmp);
+        assert_eq!(persistent.upper, Some("~/.cache/app/upper".to_string()));
+        assert_eq!(persistent.work, Some("~/.cache/app/work".to_string()));
+        assert!(persistent.validate().is_ok());
+    }
+
+    #[test]
+    fn test_overlay_requires_upper_and_work() {
+        let config = Config::from_yaml(indoc! {"
+            make:
+              overlay:
+                - src: /srv/app
+                  dst: /app
+                  upper: ~/.cache/app/upper
+        "})
+        .unwrap();
+        let make_cmd = config.get_command("make").unwrap();
+        assert!(make_cmd.overlay[0].validate().is_err());
+    }
+
+    #[test]
+    fn test_dir() {
+        let config = Config::from_yaml(indoc! {"
+            node:
+              dir:
+                - /app/cache
+                - ~/.npm
+        "})
+        .unwrap();
+        let node_cmd = config.get_command("node").unwrap();
+        assert_eq!(node_cmd.dir, vec!["/app/cache", "~/.npm"]);
+    }
+
+    #[test]
+    fn test_bind_try() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              ro_bind_try:
+                - /etc/optional
+            tool:
+              extends: base
+              bind_try:
+                - ~/.cache/tool:~/.cache/tool
+              ro_bind_try:
+                - ~/.config/tool
+        "})
+        .unwrap();
+        let tool_cmd = config.get_command("tool").unwrap();
+        assert_eq!(tool_cmd.bind_try, vec!["~/.cache/tool:~/.cache/tool"]);
+        assert_eq!(tool_cmd.ro_bind_try, vec!["~/.config/tool"]);
+
+        let merged = config.merge_with_template(tool_cmd);
+        assert_eq!(merged.ro_bind_try, vec!["~/.config/tool", "/etc/optional"]);
+    }
+
+    #[test]
+    fn test_env_file_template_order() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              env_file:
+                - ~/.base.env
+            app:
+              extends: base
+              env_file:
+                - .env
+        "})
+        .unwrap();
+
+        let app_cmd = config.get_command("app").unwrap().clone();
+        let merged = config.merge_with_template(app_cmd);
+        assert_eq!(merged.env_file, vec!["~/.base.env", ".env"]);
+    }
+
+    #[test]
+    fn test_files() {
+        let config = Config::from_yaml(indoc! {r#"
+            app:
+              files:
+                - dest: /app/config.json
+                  content: |
+                    { "debug": true }
+        "#})
+        .unwrap();
+
+        let app_cmd = config.get_command("app").unwrap();
+        assert_eq!(
+            app_cmd.files,
+            vec![InlineFile {
+                dest: "/app/config.json".to_string(),
+                content: "{ \"debug\": true }\n".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_vars() {
+        let config = Config::from_yaml(indoc! {"
+            vars:
+              PROJECT: /srv/app
+            app:
+              bind:
+                - ${PROJECT}/data:/data
+              ro_bind:
+                - ${PROJECT}
+              tmpfs:
+                - ${PROJECT}/tmp
+              env:
+                APP_ROOT: ${PROJECT}
+                HOME_DIR: $HOME
+        "})
+        .unwrap();
+
+        assert!(config.get_entry("vars").is_none());
+
+        let app_cmd = config.get_command("app").unwrap();
+        assert_eq!(app_cmd.bind, vec!["/srv/app/data:/data"]);
+        assert_eq!(app_cmd.ro_bind, vec![RoBind::from("/srv/app")]);
+        assert_eq!(app_cmd.tmpfs, vec!["/srv/app/tmp"]);
+        assert_eq!(app_cmd.env.get("APP_ROOT").unwrap(), "/srv/app");
+        // Environment variables are left to shellexpand
+        assert_eq!(app_cmd.env.get("HOME_DIR").unwrap(), "$HOME");
+    }
+
+    #[test]
+    fn test_vars_undefined() {
+        let err = Config::from_yaml(indoc! {"
+            vars:
+              PROJECT: /srv/app
+            app:
+              ro_bind:
+                - ${PROJEKT_UNDEFINED}/lib
+        "})
+        .unwrap_err();
+
+        let message = format!("{:#}", err);
+        assert!(message.contains("'app.ro_bind'"));
+        assert!(message.contains("Undefined variable '${PROJEKT_UNDEFINED}'"));
+
+        // Not looked up in the host environment
+        let err = Config::from_yaml(indoc! {"
+            app:
+              env:
+                HOME_DIR: ${HOME}
+        "})
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("Undefined variable '${HOME}'"));
+    }
+
+    #[test]
+    fn test_profiles() {
+        let config = Config::from_yaml(indoc! {"
+            node:
+              ro_bind:
+                - /usr
+              env:
+                NODE_ENV: production
+              profiles:
+                dev:
+                  share:
+                    - network
+                  env:
+                    NODE_ENV: development
+                ci:
+                  timeout: 600
         "})
         .unwrap();
+
         let node_cmd = config.get_command("node").unwrap();
-        let merged = config.merge_with_base(node_cmd);
 
-        // Should have both base and command-specific settings
-        assert_eq!(merged.share, vec!["user"]);
-        assert_eq!(merged.ro_bind, vec!["/usr"]);
-        assert_eq!(merged.bind, vec!["~/.npm:~/.npm"]);
+        // Default to the base entry
+        assert!(node_cmd.share.is_empty());
+        assert_eq!(node_cmd.env.get("NODE_ENV").unwrap(), "production");
+        assert_eq!(node_cmd.profiles.len(), 2);
+
+        let dev = node_cmd.clone().with_profile("dev").unwrap();
+        assert_eq!(dev.share, vec!["network"]);
+        assert_eq!(dev.ro_bind, vec![RoBind::from("/usr")]);
+        assert_eq!(dev.env.get("NODE_ENV").unwrap(), "development");
+        assert_eq!(dev.timeout, None);
+        assert!(dev.profiles.is_empty());
+
+        let ci = node_cmd.clone().with_profile("ci").unwrap();
+        assert!(ci.share.is_empty());
+        assert_eq!(ci.env.get("NODE_ENV").unwrap(), "production");
+        assert_eq!(ci.timeout, Some(600));
+
+        let err = node_cmd.with_profile("prod").unwrap_err();
+        assert!(err.to_string().contains("No profile 'prod' found"));
     }
 
     #[test]
-    fn test_merge_without_extends() {
+    fn test_description() {
         let config = Config::from_yaml(indoc! {"
-            base:
-              type: model
+            curl:
+              description: Needs the network to fetch release archives
               share:
-                - user
-
-            node:
-              bind:
-                - ~/.npm:~/.npm
+                - network
+            git: {}
         "})
         .unwrap();
-        let node_cmd = config.get_command("node").unwrap();
-        let merged = config.merge_with_base(node_cmd.clone());
 
-        // Should not merge base since extends is not set
-        assert_eq!(merged.share, node_cmd.share);
-        assert_eq!(merged.bind, node_cmd.bind);
+        let curl_cmd = config.get_command("curl").unwrap();
+        assert_eq!(
+            curl_cmd.description.as_deref(),
+            Some("Needs the network to fetch release archives")
+        );
+        assert_eq!(config.get_command("git").unwrap().description, None);
+
+        // Round-trips through serialization
+        let yaml = serde_yaml::to_string(&curl_cmd).unwrap();
+        let reparsed: Entry = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(reparsed.description, curl_cmd.description);
     }
 
     #[test]
-    fn test_from_file() {
-        let yaml = indoc! {"
-            test:
-              enabled: true
-        "};
-        let mut temp_file = NamedTempFile::new().unwrap();
-        temp_file.write_all(yaml.as_bytes()).unwrap();
+    fn test_lock_file() {
+        let config = Config::from_yaml(indoc! {"
+            cache:
+              type: model
+              lock_file:
+                - ~/.cache/build.lock
+            make:
+              extends: cache
+              lock_file:
+                - /tmp/make.lock
+        "})
+        .unwrap();
 
-        let config = Config::from_file(temp_file.path()).unwrap();
-        let commands = config.get_commands();
-        assert_eq!(commands.len(), 1);
-        assert!(commands.contains_key("test"));
+        let make_cmd = config.get_command("make").unwrap();
+        assert_eq!(make_cmd.lock_file, vec!["/tmp/make.lock"]);
+
+        let merged = config.merge_with_template(make_cmd);
+        assert_eq!(
+            merged.lock_file,
+            vec!["/tmp/make.lock", "~/.cache/build.lock"]
+        );
     }
 
     #[test]
-    fn test_default_enabled() {
+    fn test_uid_gid() {
         let config = Config::from_yaml(indoc! {"
-            node:
+            pkg:
+              uid: 0
+              gid: 0
+            shared:
               share:
                 - user
+              uid: 1000
+            plain: {}
         "})
         .unwrap();
-        let node_cmd = config.get_command("node").unwrap();
-        // enabled should default to true
-        assert!(node_cmd.enabled);
+
+        let pkg_cmd = config.get_command("pkg").unwrap();
+        assert_eq!(pkg_cmd.uid, Some(0));
+        assert_eq!(pkg_cmd.gid, Some(0));
+        assert!(pkg_cmd.check_id_mapping().is_ok());
+
+        let shared_cmd = config.get_command("shared").unwrap();
+        assert!(shared_cmd.check_id_mapping().is_err());
+
+        let plain_cmd = config.get_command("plain").unwrap();
+        assert_eq!(plain_cmd.uid, None);
+        assert!(plain_cmd.check_id_mapping().is_ok());
     }
 
     #[test]
-    fn test_disabled_command() {
+    fn test_run_as_root() {
         let config = Config::from_yaml(indoc! {"
-            node:
-              enabled: false
+            apt:
+              run_as_root: true
+            shared:
               share:
                 - user
+              run_as_root: true
         "})
         .unwrap();
-        let node_cmd = config.get_command("node").unwrap();
-        assert!(!node_cmd.enabled);
+
+        assert!(
+            config
+                .get_command("apt")
+                .unwrap()
+                .check_id_mapping()
+                .is_ok()
+        );
+        let err = config
+            .get_command("shared")
+            .unwrap()
+            .check_id_mapping()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'run_as_root' requires the user namespace to be unshared"
+        );
     }
 
     #[test]
-    fn test_env_variables() {
+    fn test_network_restricted() {
         let config = Config::from_yaml(indoc! {"
-            node:
-              env:
-                NODE_ENV: production
-                PATH: /custom/path
-              unset_env:
-                - DEBUG
+            npm:
+              network: restricted
+              allow_hosts:
+                - registry.npmjs.org
+            shared:
+              share:
+                - network
+              network: restricted
+              allow_hosts:
+                - example.org
+            empty:
+              network: restricted
+            stray:
+              allow_hosts:
+                - example.org
         "})
         .unwrap();
-        let node_cmd = config.get_command("node").unwrap();
 
-        assert_eq!(node_cmd.env.len(), 2);
-        assert_eq!(
-            node_cmd.env.get("NODE_ENV"),
-            Some(&"production".to_string())
-        );
-        assert_eq!(node_cmd.unset_env, vec!["DEBUG"]);
+        let npm_cmd = config.get_command("npm").unwrap();
+        assert_eq!(npm_cmd.network, Some(Network::Restricted));
+        assert!(npm_cmd.check_network().is_ok());
+
+        for name in ["shared", "empty", "stray"] {
+            assert!(config.get_command(name).unwrap().check_network().is_err());
+        }
+        assert!(Entry::default().check_network().is_ok());
     }
 
     #[test]
-    fn test_tmpfs() {
+    fn test_remount_ro() {
         let config = Config::from_yaml(indoc! {"
-            node:
-              tmpfs:
-                - /tmp
-                - /var/tmp
+            app:
+              bind:
+                - $PWD:/app
+              remount_ro:
+                - /app/vendor
         "})
         .unwrap();
-        let node_cmd = config.get_command("node").unwrap();
-        assert_eq!(node_cmd.tmpfs, vec!["/tmp", "/var/tmp"]);
+        let app_cmd = config.get_command("app").unwrap();
+        assert_eq!(app_cmd.remount_ro, vec!["/app/vendor"]);
     }
 
     #[test]
@@ -410,7 +2253,7 @@ mod tests {
         assert_eq!(python_cmd.extends, Some("strict".to_string()));
         let merged_python = config.merge_with_template(python_cmd);
         assert_eq!(merged_python.share, vec!["user"]);
-        assert_eq!(merged_python.ro_bind, vec!["/usr"]);
+        assert_eq!(merged_python.ro_bind, vec![RoBind::from("/usr")]);
         assert_eq!(merged_python.bind, vec!["~/.local:~/.local"]);
     }
 
@@ -436,6 +2279,132 @@ mod tests {
         assert_eq!(merged.bind, node_cmd.bind);
     }
 
+    #[test]
+    fn test_extends_chain() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+            node:
+              extends: base
+            python:
+              extends: nonexistent
+        "})
+        .unwrap();
+
+        assert_eq!(config.get_extends_chain("node").unwrap(), vec!["base"]);
+        assert!(config.get_extends_chain("python").unwrap().is_empty());
+        assert!(config.get_extends_chain("base").unwrap().is_empty());
+        assert!(config.check_extends().is_ok());
+    }
+
+    #[test]
+    fn test_template_inheritance() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              share:
+                - user
+              ro_bind:
+                - /usr
+              env:
+                LEVEL: base
+                BASE_ONLY: yes
+
+            base-gui:
+              type: model
+              extends: base
+              bind:
+                - /tmp/.X11-unix:/tmp/.X11-unix
+              env:
+                LEVEL: base-gui
+
+            firefox:
+              extends: base-gui
+              share:
+                - network
+        "})
+        .unwrap();
+
+        assert_eq!(
+            config.get_extends_chain("firefox").unwrap(),
+            vec!["base-gui", "base"]
+        );
+
+        let firefox_cmd = config.get_command("firefox").unwrap();
+        let merged = config.merge_with_template(firefox_cmd);
+
+        // All three layers are merged
+        assert_eq!(merged.share, vec!["network", "user"]);
+        assert_eq!(merged.bind, vec!["/tmp/.X11-unix:/tmp/.X11-unix"]);
+        assert_eq!(merged.ro_bind, vec![RoBind::from("/usr")]);
+        // Nearest template wins on env collisions
+        assert_eq!(merged.env.get("LEVEL"), Some(&"base-gui".to_string()));
+        assert_eq!(merged.env.get("BASE_ONLY"), Some(&"yes".to_string()));
+    }
+
+    #[test]
+    fn test_merge_stops_on_cycle() {
+        let config = Config::from_yaml(indoc! {"
+            a:
+              type: model
+              extends: b
+              ro_bind:
+                - /a
+            b:
+              type: model
+              extends: a
+              ro_bind:
+                - /b
+            node:
+              extends: a
+        "})
+        .unwrap();
+
+        let node_cmd = config.get_command("node").unwrap();
+        let merged = config.merge_with_template(node_cmd);
+        assert_eq!(merged.ro_bind, vec![RoBind::from("/a"), RoBind::from("/b")]);
+    }
+
+    #[test]
+    fn test_extends_two_node_cycle() {
+        let config = Config::from_yaml(indoc! {"
+            a:
+              type: model
+              extends: b
+            b:
+              type: model
+              extends: a
+            node:
+              extends: a
+        "})
+        .unwrap();
+
+        let err = config.get_extends_chain("node").unwrap_err();
+        assert_eq!(err.to_string(), "Circular extends chain: a -> b -> a");
+        assert!(config.check_extends().is_err());
+    }
+
+    #[test]
+    fn test_extends_three_node_cycle() {
+        let config = Config::from_yaml(indoc! {"
+            a:
+              type: model
+              extends: b
+            b:
+              type: model
+              extends: c
+            c:
+              type: model
+              extends: a
+        "})
+        .unwrap();
+
+        let err = config.get_extends_chain("a").unwrap_err();
+        assert_eq!(err.to_string(), "Circular extends chain: a -> b -> c -> a");
+        let err = config.check_extends().unwrap_err();
+        assert!(err.to_string().contains("Circular extends chain"));
+    }
+
     #[test]
     fn test_get_entries_with() {
         let config = Config::from_yaml(indoc! {"
@@ -465,7 +2434,8 @@ mod tests {
         .unwrap();
 
         // Filter enabled commands only
-        let enabled = config.get_entries_with(|e| e.enabled && e.entry_type == EntryType::Command);
+        let enabled =
+            config.get_entries_with(|e| e.is_enabled() && e.entry_type == EntryType::Command);
         assert_eq!(enabled.len(), 2);
         assert!(enabled.contains_key("node"));
         assert!(enabled.contains_key("rust"));
@@ -474,7 +2444,7 @@ mod tests {
 
         // Filter disabled commands
         let disabled =
-            config.get_entries_with(|e| !e.enabled && e.entry_type == EntryType::Command);
+            config.get_entries_with(|e| !e.is_enabled() && e.entry_type == EntryType::Command);
         assert_eq!(disabled.len(), 1);
         assert!(disabled.contains_key("python"));
 
@@ -494,7 +2464,7 @@ mod tests {
 
         // Complex filter: enabled commands with bind
         let enabled_with_bind = config.get_entries_with(|e| {
-            e.enabled && e.entry_type == EntryType::Command && !e.bind.is_empty()
+            e.is_enabled() && e.entry_type == EntryType::Command && !e.bind.is_empty()
         });
         assert_eq!(enabled_with_bind.len(), 1);
         assert!(enabled_with_bind.contains_key("node"));
@@ -524,11 +2494,11 @@ mod tests {
         .unwrap();
 
         // Get entry only if enabled
-        let node_enabled = config.get_entry_with("node", |e| e.enabled);
+        let node_enabled = config.get_entry_with("node", |e| e.is_enabled());
         assert!(node_enabled.is_some());
-        assert!(node_enabled.unwrap().enabled);
+        assert!(node_enabled.unwrap().is_enabled());
 
-        let python_enabled = config.get_entry_with("python", |e| e.enabled);
+        let python_enabled = config.get_entry_with("python", |e| e.is_enabled());
         assert!(python_enabled.is_none());
 
         // Get entry only if it's a command
@@ -553,12 +2523,12 @@ mod tests {
 
         // Complex filter: enabled command with bind
         let node_complex = config.get_entry_with("node", |e| {
-            e.enabled && e.entry_type == EntryType::Command && !e.bind.is_empty()
+            e.is_enabled() && e.entry_type == EntryType::Command && !e.bind.is_empty()
         });
         assert!(node_complex.is_some());
 
         let python_complex = config.get_entry_with("python", |e| {
-            e.enabled && e.entry_type == EntryType::Command && !e.bind.is_empty()
+            e.is_enabled() && e.entry_type == EntryType::Command && !e.bind.is_empty()
         });
         assert!(python_complex.is_none());
 
@@ -599,7 +2569,216 @@ mod tests {
         let all = config.get_entries_with(|_| true);
         assert_eq!(all.len(), 3);
 
-        let all_enabled = config.get_entries_with(|e| e.enabled);
+        let all_enabled = config.get_entries_with(|e| e.is_enabled());
         assert_eq!(all_enabled.len(), 3);
     }
+
+    #[test]
+    fn test_ro_bind_exclude() {
+        let config = Config::from_yaml(indoc! {"
+            vars:
+              PREFIX: /usr
+            node:
+              ro_bind:
+                - /lib
+                - path: ${PREFIX}
+                  exclude:
+                    - ${PREFIX}/local
+        "})
+        .unwrap();
+        let node_cmd = config.get_command("node").unwrap();
+
+        assert_eq!(
+            node_cmd.ro_bind,
+            vec![
+                RoBind::from("/lib"),
+                RoBind::Tree {
+                    path: "/usr".to_string(),
+                    exclude: vec!["/usr/local".to_string()],
+                },
+            ]
+        );
+        assert_eq!(
+            node_cmd.ro_bind[1].to_string(),
+            "/usr (excluding /usr/local)"
+        );
+    }
+
+    #[test]
+    fn test_version_warnings() {
+        let config = Config::from_yaml("version: 1\nnode: {}\n").unwrap();
+        assert_eq!(config.version, Some(CONFIG_VERSION));
+        assert!(config.warnings.is_empty());
+        assert!(config.get_command("node").is_some());
+
+        let config = Config::from_yaml("node: {}\n").unwrap();
+        assert_eq!(config.warnings.len(), 1);
+        assert!(config.warnings[0].contains("has no 'version' field"));
+
+        let config = Config::from_yaml("version: 0\nnode: {}\n").unwrap();
+        assert!(config.warnings[0].contains("uses the old version 0"));
+    }
+
+    #[test]
+    fn test_legacy_document() {
+        let config = Config::from_yaml(indoc! {"
+            templates:
+              base:
+                share:
+                  - user
+            commands:
+              node:
+                extends: base
+        "})
+        .unwrap();
+
+        assert!(config.warnings[0].contains("old 'commands'/'templates' layout"));
+        assert_eq!(config.get_model("base").unwrap().share, vec!["user"]);
+        let node_cmd = config.get_command("node").unwrap();
+        assert_eq!(node_cmd.extends, Some("base".to_string()));
+        assert!(config.get_command("commands").is_none());
+
+        // A command named `commands` is not the old layout
+        let document: Mapping = serde_yaml::from_str("commands:\n  share:\n    - user\n").unwrap();
+        assert!(!is_legacy_document(&document));
+    }
+
+    #[test]
+    fn test_migrate_document() {
+        let document: Mapping = serde_yaml::from_str(indoc! {"
+            vars:
+              ROOT: /srv
+            templates:
+              base: {}
+            commands:
+              node:
+                extends: base
+        "})
+        .unwrap();
+
+        let migrated = serde_yaml::to_string(&migrate_document(document)).unwrap();
+
+        assert_eq!(
+            migrated,
+            indoc! {"
+                version: 1
+                vars:
+                  ROOT: /srv
+                base:
+                  type: model
+                node:
+                  extends: base
+            "}
+        );
+    }
+
+    #[test]
+    fn test_get_enabled_commands() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              share:
+                - user
+            python:
+              enabled: false
+            node:
+              extends: base
+            git: {}
+            legacy:
+              enabled:
+                os: plan9
+        "})
+        .unwrap();
+
+        let enabled = config.get_enabled_commands();
+        assert_eq!(enabled.keys().collect::<Vec<_>>(), vec!["git", "node"]);
+        // Merged with their models
+        assert_eq!(enabled["node"].share, vec!["user"]);
+    }
+
+    #[test]
+    fn test_dbus() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              dbus: session
+            browser:
+              extends: base
+            viewer:
+              extends: base
+              dbus: none
+            tool: {}
+        "})
+        .unwrap();
+
+        let enabled = config.get_enabled_commands();
+        assert_eq!(enabled["browser"].dbus, Some(Dbus::Session));
+        assert_eq!(enabled["viewer"].dbus, Some(Dbus::None));
+        assert_eq!(enabled["tool"].dbus, None);
+        assert!(Config::from_yaml("tool:\n  dbus: both\n").is_err());
+    }
+
+    #[test]
+    fn test_yaml_error_location() {
+        let err = Config::from_yaml(indoc! {"
+            version: 1
+            node:
+              share:
+                - user
+               bind: [
+        "})
+        .unwrap_err();
+        let message = format!("{:#}", err);
+
+        assert!(message.contains("line 5"), "{}", message);
+        assert!(message.contains(" 5 |    bind: ["), "{}", message);
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = Config::from_yaml(indoc! {"
+            base:
+              type: model
+              share:
+                - user
+            node:
+              extends: base
+              share:
+                - netwrok
+            python:
+              extends: pyhton-base
+            ruby:
+              extends: base
+              bind:
+                - /srv/gems
+              uid: 0
+            git:
+              merge:
+                robind: replace
+        "})
+        .unwrap();
+
+        let errors = config.validate();
+        let reported: Vec<_> = errors
+            .iter()
+            .map(|error| (error.entry.as_str(), error.message.as_str()))
+            .collect();
+        assert_eq!(
+            reported,
+            vec![
+                ("git", "unknown field 'robind' in merge"),
+                ("node", "unknown namespace 'netwrok' in share"),
+                ("python", "extends unknown model 'pyhton-base'"),
+                ("ruby", "invalid bind format '/srv/gems'"),
+                (
+                    "ruby",
+                    "'uid' and 'gid' require the user namespace to be unshared"
+                ),
+            ]
+        );
+        assert_eq!(
+            errors[1].to_string(),
+            "Invalid configuration for 'node': unknown namespace 'netwrok' in share"
+        );
+    }
 }
@@ -0,0 +1,104 @@
+// Copyright (C) 2025 Pierre Le Gall
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{Context, Result, bail};
+use serde_yaml::{Mapping, Value};
+use std::fs;
+use std::path::Path;
+
+use super::{CONFIG_VERSION, migrate_document};
+
+/// Edits config files in place
+///
+/// Files are rewritten from their parsed YAML: entry and key order is kept,
+/// but comments and custom formatting are lost.
+pub struct ConfigWriter;
+
+impl ConfigWriter {
+    /// Set the enabled flag of a command entry
+    pub fn set_enabled<P: AsRef<Path>>(path: P, command: &str, enabled: bool) -> Result<()> {
+        let path = path.as_ref();
+        let mut document = Self::read(path)?;
+
+        let entry = document
+            .get_mut(command)
+            .filter(|entry| entry.get("type").and_then(Value::as_str) != Some("model"))
+            .context(format!("No configuration found for command '{}'", command))?;
+
+        // An empty entry (`node:`) is parsed as null
+        if entry.is_null() {
+            *entry = Value::Mapping(Mapping::new());
+        }
+        let Some(entry) = entry.as_mapping_mut() else {
+            bail!("Invalid configuration for command '{}'", command);
+        };
+        entry.insert(Value::from("enabled"), Value::from(enabled));
+
+        Self::write(path, &document)
+    }
+
+    /// Append a new command entry
+    pub fn add_command<P: AsRef<Path>>(
+        path: P,
+        command: &str,
+        extends: Option<&str>,
+        share: &[String],
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let mut document = Self::read(path)?;
+
+        if document.contains_key(command) {
+            bail!("An entry named '{}' already exists", command);
+        }
+
+        let mut entry = Mapping::new();
+        if let Some(extends) = extends {
+            entry.insert(Value::from("extends"), Value::from(extends));
+        }
+        if !share.is_empty() {
+            entry.insert(Value::from("share"), Value::from(share.to_vec()));
+        }
+        document.insert(Value::from(command), Value::Mapping(entry));
+
+        Self::write(path, &document)
+    }
+
+    /// Upgrade a config file to the current format version
+    /// Returns false if it was already up to date
+    pub fn migrate<P: AsRef<Path>>(path: P) -> Result<bool> {
+        let path = path.as_ref();
+        let document = Self::read(path)?;
+
+        let version = document.get("version").and_then(Value::as_u64);
+        if version == Some(CONFIG_VERSION as u64) {
+            return Ok(false);
+        }
+        if version.is_some_and(|version| version > CONFIG_VERSION as u64) {
+            bail!("Config {:?} is newer than the supported version", path);
+        }
+
+        Self::write(path, &migrate_document(document))?;
+
+        Ok(true)
+    }
+
+    fn read(path: &Path) -> Result<Mapping> {
+        let yaml =
+            fs::read_to_string(path).context(format!("Failed to read config file: {:?}", path))?;
+
+        let document: Value = serde_yaml::from_str(&yaml)
+            .context(format!("Failed to parse YAML config {:?}", path))?;
+
+        match document {
+            Value::Mapping(mapping) => Ok(mapping),
+            Value::Null => Ok(Mapping::new()),
+            _ => bail!("Invalid config file {:?}: expected a mapping", path),
+        }
+    }
+
+    fn write(path: &Path, document: &Mapping) -> Result<()> {
+        let yaml = serde_yaml::to_string(document)?;
+
+        fs::write(path, yaml).context(format!("Failed to write config file: {:?}", path))
+    }
+}
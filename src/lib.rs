@@ -6,4 +6,4 @@ pub mod config;
 
 // Re-export commonly used types
 pub use bwrap::WrappedCommandBuilder;
-pub use config::{Config, Entry, loader};
+pub use config::{Config, Entry, EntryBuilder, loader};
@@ -3,44 +3,183 @@
 
 mod cli;
 mod shell_hooks;
+mod wizard;
 
 use anyhow::{Context, Result, bail};
 use clap::Parser;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::debug;
 
-use cli::{Cli, CommandAction, ConfigAction, ShellHookAction, Subject};
+use cli::{
+    CheckFormat, Cli, CommandAction, ConfigAction, GlobalArgs, ModelAction, ShellHookAction,
+    Subject, WhichFormat,
+};
 use shell_hooks::Shell;
-use shwrap::bwrap::WrappedCommandBuilder;
-use shwrap::config::{self, loader::ConfigLoader};
+use shwrap::bwrap::{
+    self, BindMode, MISSING_PROC, Mount, WrappedCommandBuilder, bwrap_program, find_executable,
+};
+use shwrap::config::{self, Entry, loader::ConfigLoader, writer::ConfigWriter};
+
+/// Exit code when bwrap is not installed (as for a shell command not found)
+const BWRAP_MISSING_EXIT_CODE: i32 = 127;
+
+/// Environment variable setting the file of `command exec --record`
+const AUDIT_LOG_VAR: &str = "SHWRAP_AUDIT_LOG";
 
 fn main() -> Result<()> {
     let input = Cli::parse();
+    let global = &input.global;
+    init_logging(global.verbose);
 
     match input.subject {
         Subject::Config { action } => match action {
-            ConfigAction::Init { template } => {
-                config_init_cmd(template)?;
+            ConfigAction::Init {
+                template,
+                list,
+                interactive,
+            } => {
+                if list {
+                    config_init_list_cmd();
+                } else {
+                    config_init_cmd(template, interactive)?;
+                }
+            }
+            ConfigAction::Check {
+                path,
+                silent,
+                bwrap,
+                format,
+            } => {
+                if bwrap {
+                    config_check_bwrap_cmd(silent);
+                } else if let Some(CheckFormat::Json) = format {
+                    config_check_json_cmd(path, global)?;
+                } else {
+                    config_check_cmd(path, silent, global)?;
+                }
             }
-            ConfigAction::Check { path, silent } => {
-                config_check_cmd(path, silent)?;
+            ConfigAction::Migrate { path } => {
+                config_migrate_cmd(path, global)?;
             }
-            ConfigAction::Which => {
-                config_which_cmd()?;
+            ConfigAction::Which { format } => {
+                config_which_cmd(format, global)?;
             }
         },
-        Subject::Command { action } => match action {
-            CommandAction::List { simple } => {
-                command_list_cmd(simple)?;
+        Subject::Command { action } => match *action {
+            CommandAction::List {
+                simple,
+                json,
+                disabled,
+            } => {
+                command_list_cmd(simple, json, disabled, global)?;
+            }
+            CommandAction::Exec {
+                no_isolation,
+                timeout,
+                profile,
+                harden,
+                as_root,
+                stats,
+                record,
+                stdin_file,
+                stdout_file,
+                keep_env,
+                unset_all_env,
+                env_prefix,
+                no_config,
+                entry,
+                command,
+                args,
+            } => {
+                let options = ExecOptions {
+                    no_isolation,
+                    timeout,
+                    profile: profile.as_deref(),
+                    harden,
+                    as_root,
+                    stats,
+                    record: record.or_else(|| {
+                        std::env::var_os(AUDIT_LOG_VAR)
+                            .filter(|path| !path.is_empty())
+                            .map(PathBuf::from)
+                    }),
+                    stdin_file: stdin_file.as_deref(),
+                    stdout_file: stdout_file.as_deref(),
+                    keep_env: &keep_env,
+                    unset_all_env,
+                    env_prefix,
+                    no_config,
+                    entry: entry.to_entry(),
+                };
+                command_exec_cmd(&command, &args, &options, global)?;
+            }
+            CommandAction::RunAll {
+                dirs,
+                command,
+                args,
+            } => {
+                command_run_all_cmd(&command, &dirs, &args, global)?;
+            }
+            CommandAction::Add {
+                command,
+                extends,
+                share,
+            } => {
+                command_add_cmd(&command, extends.as_deref(), &share, global)?;
             }
-            CommandAction::Exec { command, args } => {
-                command_exec_cmd(&command, &args)?;
+            CommandAction::Enable { command } => {
+                command_set_enabled_cmd(&command, true, global)?;
             }
-            CommandAction::Show { command, args } => {
-                command_show_cmd(&command, &args)?;
+            CommandAction::Disable { command } => {
+                command_set_enabled_cmd(&command, false, global)?;
+            }
+            CommandAction::Which { command } => {
+                command_which_cmd(&command)?;
+            }
+            CommandAction::Show {
+                print_config,
+                pretty,
+                null,
+                explain,
+                shell,
+                diff,
+                profile,
+                command,
+                args,
+            } => {
+                if let Some(other) = diff {
+                    command_show_diff_cmd(&command, &other, profile.as_deref(), global)?;
+                    return Ok(());
+                }
+                let format = match (print_config, pretty, null, explain, shell) {
+                    (true, ..) => ShowFormat::Config,
+                    (_, true, ..) => ShowFormat::Pretty,
+                    (_, _, true, ..) => ShowFormat::Null,
+                    (_, _, _, true, _) => ShowFormat::Explain,
+                    (.., Some(shell)) => ShowFormat::Shell(shell),
+                    _ => ShowFormat::Line,
+                };
+                command_show_cmd(&command, &args, format, profile.as_deref(), global)?;
+            }
+        },
+        Subject::Model { action } => match action {
+            ModelAction::List { simple } => {
+                model_list_cmd(simple, global)?;
             }
         },
+        Subject::Doctor => {
+            doctor_cmd(global);
+        }
         Subject::ShellHook { action } => match action {
-            ShellHookAction::Get { shell } => {
-                shell_hook_get_cmd(&shell)?;
+            ShellHookAction::Get {
+                shell,
+                commands_from_config,
+            } => {
+                shell_hook_get_cmd(shell, commands_from_config, global)?;
             }
         },
     }
@@ -48,44 +187,368 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn command_exec_cmd(command: &str, args: &[String]) -> Result<()> {
-    let config = ConfigLoader::load()?.context("No configuration found")?;
+/// Install a stderr subscriber for the library logs, if verbose
+fn init_logging(verbosity: u8) {
+    let level = match verbosity {
+        0 => return,
+        1 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
 
-    let cmd_config = config
-        .get_command(command)
-        .context(format!("No configuration found for command '{}'", command))?;
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .without_time()
+        .with_target(false)
+        .init();
+}
 
-    if !cmd_config.enabled {
-        bail!("Command '{}' is disabled in configuration", command);
+/// Get the config file given with --config, or the first one found in the hierarchy
+fn get_config_file(global: &GlobalArgs) -> Result<Option<PathBuf>> {
+    match &global.config {
+        Some(path) => Ok(Some(path.clone())),
+        None => ConfigLoader::get_config_file(),
     }
+}
+
+fn load_config(global: &GlobalArgs) -> Result<config::Config> {
+    find_config(global)?.context("No configuration found")
+}
+
+/// Load the configuration, None if there is none
+fn find_config(global: &GlobalArgs) -> Result<Option<config::Config>> {
+    let config = match (&global.config, global.merge) {
+        (Some(path), _) => Some(config::Config::from_file(path)?),
+        (None, true) => ConfigLoader::load_merged()?,
+        (None, false) => ConfigLoader::load()?,
+    };
 
-    let merged_config = config.merge_with_base(cmd_config);
-    let builder = WrappedCommandBuilder::new(merged_config);
+    if !global.quiet
+        && let Some(config) = &config
+    {
+        for warning in &config.warnings {
+            eprintln!("Warning: config {}", warning);
+        }
+    }
 
-    let exit_code = builder.exec(command, args)?;
+    Ok(config)
+}
+
+/// Options of `command exec`
+struct ExecOptions<'a> {
+    no_isolation: bool,
+    timeout: Option<u64>,
+    profile: Option<&'a str>,
+    harden: bool,
+    as_root: bool,
+    stats: bool,
+    /// Audit log to append the invocation to
+    record: Option<PathBuf>,
+    stdin_file: Option<&'a Path>,
+    stdout_file: Option<&'a Path>,
+    keep_env: &'a [String],
+    unset_all_env: bool,
+    env_prefix: bool,
+    no_config: bool,
+    /// Entry built from the command line flags
+    entry: Option<Entry>,
+}
+
+fn command_exec_cmd(
+    command: &str,
+    args: &[String],
+    options: &ExecOptions,
+    global: &GlobalArgs,
+) -> Result<()> {
+    let start = Instant::now();
+
+    let mut builder = match (options.no_config, &options.entry) {
+        (true, entry) => WrappedCommandBuilder::new(entry.clone().unwrap_or_default()),
+        (false, Some(entry)) => {
+            let config = find_config(global)?;
+            if config.is_some_and(|config| config.get_command(command).is_some()) {
+                bail!(
+                    "'{}' is configured, pass --no-config to use the entry from the command line",
+                    command
+                );
+            }
+            WrappedCommandBuilder::new(entry.clone())
+        }
+        (false, None) => {
+            let config = load_config(global)?;
+            WrappedCommandBuilder::from_config_with_profile(&config, command, options.profile)?
+        }
+    };
+    if options.as_root {
+        builder = builder.as_root();
+    }
+    builder = builder.keep_env(options.keep_env, |key| std::env::var(key).ok());
+    if options.unset_all_env {
+        builder = builder.unset_all_env();
+    }
+    let args = match options.env_prefix {
+        true => {
+            let (vars, args) = bwrap::split_env_prefix(args);
+            builder = builder.with_env(vars);
+            args
+        }
+        false => args,
+    };
+    if let Some(path) = options.stdin_file {
+        builder = builder.with_stdin_file(path);
+    }
+    if let Some(path) = options.stdout_file {
+        builder = builder.with_stdout_file(path);
+    }
+    builder.config().check_args(args)?;
+    builder.config().check_id_mapping()?;
+    builder.config().check_network()?;
+    let config_time = start.elapsed();
+
+    if let Some(timeout) = options.timeout {
+        builder = builder.with_timeout(timeout);
+    }
+
+    if options.harden {
+        builder = builder.hardened();
+    }
+
+    if options.no_isolation {
+        eprintln!("Warning: running '{}' without namespace isolation", command);
+        builder = builder.without_isolation();
+    }
+
+    if global.quiet {
+        builder = builder.quiet();
+    }
+
+    let resolved = serde_yaml::to_string(builder.config())?;
+    debug!(
+        "resolved configuration of '{}':\n{}",
+        command,
+        resolved.trim_end()
+    );
+
+    let args_start = Instant::now();
+    // Built once, the command runs with the timed args
+    let (bwrap_args, warnings) = builder.build_args_with_warnings()?;
+    let args_time = args_start.elapsed();
+    if !global.quiet {
+        for warning in warnings {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+    debug!(
+        command = %builder.show_args(&bwrap_args, command, args),
+        "resolved command"
+    );
+
+    bwrap::check_platform()?;
+    check_bwrap(&builder.bwrap_program());
+    if !global.quiet && builder.missing_proc() {
+        eprintln!("Warning: {}", MISSING_PROC);
+    }
+    // Opened before running the command, so an unwritable log fails early
+    let mut audit_log = match &options.record {
+        Some(path) => Some((
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .context(format!("Failed to open audit log {:?}", path))?,
+            ExecRecord::new(command, args, &builder)?,
+        )),
+        None => None,
+    };
+    builder.run_pre_exec()?;
+
+    // Stats and records are written after the command exits, so shwrap must
+    // stay around
+    if !options.stats && audit_log.is_none() && !builder.needs_supervision() {
+        let never = builder.exec_replace_args(bwrap_args, command, args)?;
+        match never {}
+    }
+
+    let exit_code = builder.exec_args(bwrap_args, command, args)?;
+
+    if options.stats {
+        print_stats(config_time, args_time, start.elapsed(), exit_code);
+    }
+    if let Some((file, record)) = &mut audit_log {
+        record.exit_code = exit_code;
+        record.append(file)?;
+    }
 
     std::process::exit(exit_code)
 }
 
-fn command_list_cmd(simple: bool) -> Result<()> {
-    let config = ConfigLoader::load()?.context("No configuration found")?;
+/// Line of the `command exec --record` audit log
+#[derive(Serialize)]
+struct ExecRecord {
+    /// Seconds since the Unix epoch, when the command started
+    timestamp: u64,
+    command: String,
+    args: Vec<String>,
+    unshared: Vec<String>,
+    shared: Vec<String>,
+    binds: Vec<RecordedBind>,
+    exit_code: i32,
+}
 
-    // Sort commands alphabetically
-    let commands_map = config.get_commands();
-    let mut commands: Vec<_> = commands_map.iter().collect();
-    commands.sort_by_key(|(name, _)| *name);
+#[derive(Serialize)]
+struct RecordedBind {
+    /// read-write, read-only or device
+    mode: &'static str,
+    src: String,
+    dst: String,
+}
 
-    if simple {
-        for (name, cmd_config) in commands {
-            if cmd_config.enabled {
+impl ExecRecord {
+    fn new(command: &str, args: &[String], builder: &WrappedCommandBuilder) -> Result<Self> {
+        let plan = builder.plan_with_warnings()?.0;
+        let binds = plan
+            .mounts
+            .into_iter()
+            .filter_map(|mount| match mount {
+                Mount::Bind { mode, src, dst, .. } => Some(RecordedBind {
+                    mode: match mode {
+                        BindMode::ReadWrite => "read-write",
+                        BindMode::ReadOnly => "read-only",
+                        BindMode::Device => "device",
+                    },
+                    src,
+                    dst,
+                }),
+                _ => None,
+            })
+            .collect();
+
+        Ok(ExecRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            command: command.to_string(),
+            args: args.to_vec(),
+            unshared: plan.unshared,
+            shared: plan.shared,
+            binds,
+            exit_code: 0,
+        })
+    }
+
+    /// Append the record as one JSON line, in a single write so that
+    /// concurrent writers (opening the log in append mode) don't interleave
+    fn append(&self, file: &mut File) -> Result<()> {
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())
+            .context("Failed to write to the audit log")
+    }
+}
+
+/// Print the `command exec --stats` block to stderr
+fn print_stats(config_time: Duration, args_time: Duration, total_time: Duration, exit_code: i32) {
+    let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+
+    eprintln!("Stats:");
+    eprintln!("  config: {:.3}ms", millis(config_time));
+    eprintln!("  args: {:.3}ms", millis(args_time));
+    eprintln!("  total: {:.3}ms", millis(total_time));
+    eprintln!("  exit code: {}", exit_code);
+}
+
+fn command_run_all_cmd(
+    command: &str,
+    dirs: &[PathBuf],
+    args: &[String],
+    global: &GlobalArgs,
+) -> Result<()> {
+    let original_dir = std::env::current_dir().context("Failed to get current directory")?;
+
+    let mut results = Vec::new();
+    for dir in dirs {
+        debug!(dir = %dir.display(), "running '{}'", command);
+
+        let exit_code = match run_in_dir(&original_dir.join(dir), command, args, global) {
+            Ok(exit_code) => exit_code,
+            Err(err) => {
+                eprintln!("Error in {}: {:#}", dir.display(), err);
+                1
+            }
+        };
+        results.push((dir, exit_code));
+    }
+
+    eprintln!("\nSummary:");
+    for (dir, exit_code) in &results {
+        match exit_code {
+            0 => eprintln!("  {}: ok", dir.display()),
+            code => eprintln!("  {}: failed (exit code {})", dir.display(), code),
+        }
+    }
+
+    if results.iter().any(|(_, exit_code)| *exit_code != 0) {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Run a wrapped command from the given directory, with the configuration found there
+fn run_in_dir(dir: &Path, command: &str, args: &[String], global: &GlobalArgs) -> Result<i32> {
+    std::env::set_current_dir(dir)
+        .context(format!("Failed to change directory to {}", dir.display()))?;
+
+    let config = load_config(global)?;
+    let mut builder = WrappedCommandBuilder::from_config_with_profile(&config, command, None)?;
+    builder.config().check_args(args)?;
+    builder.config().check_id_mapping()?;
+    builder.config().check_network()?;
+    if global.quiet {
+        builder = builder.quiet();
+    }
+    check_bwrap(&builder.bwrap_program());
+
+    builder.run_pre_exec()?;
+    builder.exec(command, args)
+}
+
+fn command_list_cmd(simple: bool, json: bool, disabled: bool, global: &GlobalArgs) -> Result<()> {
+    let config = load_config(global)?;
+
+    if disabled {
+        return command_list_disabled(&config, simple, json);
+    }
+
+    let active = config.get_enabled_commands();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&active)?);
+    } else if simple {
+        // The wildcard entry is not a command name (shell hooks can't wrap it)
+        for name in active.keys() {
+            if name != config::WILDCARD_COMMAND {
                 println!("{}", name);
             }
         }
     } else {
+        // Sort commands alphabetically, commands disabled by a condition included
+        let commands_map = config.get_commands();
+        let mut commands: Vec<_> = commands_map.iter().collect();
+        commands.sort_by_key(|(name, _)| *name);
+
         println!("Active command configurations:");
         for (name, cmd_config) in commands {
-            if cmd_config.enabled {
-                println!("\n{}:", name);
+            if let Some(reason) = cmd_config.disabled_reason() {
+                println!("\n{} (disabled: {})", name, reason);
+            } else if let Some(cmd_config) = active.get(name) {
+                match name.as_str() {
+                    config::WILDCARD_COMMAND => println!("\n{} (any other command):", name),
+                    _ => println!("\n{}:", name),
+                }
+                if let Some(description) = &cmd_config.description {
+                    println!("  description: {}", description);
+                }
                 if !cmd_config.share.is_empty() {
                     println!("  share: {}", cmd_config.share.join(", "));
                 }
@@ -99,30 +562,282 @@ fn command_list_cmd(simple: bool) -> Result<()> {
     Ok(())
 }
 
-fn command_show_cmd(command: &str, args: &[String]) -> Result<()> {
-    let config = ConfigLoader::load()?.context("No configuration found")?;
+/// List the disabled commands only, with the reason when disabled by a condition
+fn command_list_disabled(config: &config::Config, simple: bool, json: bool) -> Result<()> {
+    let disabled = config.get_disabled_commands();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&disabled)?);
+    } else if simple {
+        for name in disabled.keys() {
+            println!("{}", name);
+        }
+    } else {
+        println!("Disabled command configurations:");
+        for (name, cmd_config) in &disabled {
+            match cmd_config.disabled_reason() {
+                Some(reason) => println!("  {} ({})", name, reason),
+                None => println!("  {}", name),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn command_add_cmd(
+    command: &str,
+    extends: Option<&str>,
+    share: &[String],
+    global: &GlobalArgs,
+) -> Result<()> {
+    let config_path = get_config_file(global)?
+        .context("No configuration found (create one with 'shwrap config init')")?;
 
-    let cmd_config = config
-        .get_command(command)
-        .context(format!("No configuration found for command '{}'", command))?;
+    ConfigWriter::add_command(&config_path, command, extends, share)?;
+
+    println!("Added '{}' to {}", command, config_path.display());
+
+    Ok(())
+}
 
-    let merged_config = config.merge_with_base(cmd_config);
-    let builder = WrappedCommandBuilder::new(merged_config);
+fn command_set_enabled_cmd(command: &str, enabled: bool, global: &GlobalArgs) -> Result<()> {
+    let config_path = get_config_file(global)?.context("No configuration found")?;
 
-    let cmd_line = builder.show(command, args);
-    println!("{}", cmd_line);
+    ConfigWriter::set_enabled(&config_path, command, enabled)?;
+
+    match enabled {
+        true => println!("Enabled '{}' in {}", command, config_path.display()),
+        false => println!("Disabled '{}' in {}", command, config_path.display()),
+    }
 
     Ok(())
 }
 
-fn config_check_cmd(path: Option<String>, silent: bool) -> Result<()> {
-    let config_path = if let Some(p) = path {
-        std::path::PathBuf::from(p)
-    } else {
-        ConfigLoader::get_config_file()?.context("No configuration found")?
+fn command_which_cmd(command: &str) -> Result<()> {
+    let path = find_executable(command)
+        .with_context(|| format!("Command '{}' not found in PATH", command))?;
+
+    println!("{}", path.display());
+
+    Ok(())
+}
+
+/// Exit with a helpful message if the bwrap executable is not found
+fn check_bwrap(program: &str) -> PathBuf {
+    match find_executable(program) {
+        Some(path) => path,
+        None if program == "bwrap" => {
+            eprintln!("Error: bubblewrap is not installed; install the 'bubblewrap' package");
+            std::process::exit(BWRAP_MISSING_EXIT_CODE)
+        }
+        None => {
+            eprintln!("Error: bubblewrap executable '{}' not found", program);
+            std::process::exit(BWRAP_MISSING_EXIT_CODE)
+        }
+    }
+}
+
+/// Print a PASS/FAIL line per installation check, exits with 1 if any fails
+fn doctor_cmd(global: &GlobalArgs) {
+    let mut failed = false;
+    let mut report = |check: &str, result: Result<String, String>| match result {
+        Ok(detail) => println!("PASS {}: {}", check, detail),
+        Err(detail) => {
+            println!("FAIL {}: {}", check, detail);
+            failed = true;
+        }
     };
 
+    // The bwrap executables run by the enabled commands (bwrap_path), the
+    // default one without any
+    let config = find_config(global);
+    let mut programs = Vec::new();
+    if let Ok(Some(config)) = &config {
+        for entry in config.get_enabled_commands().into_values() {
+            let entry = config.merge_with_template(entry);
+            programs.push(bwrap_program(entry.bwrap_path.as_deref()));
+        }
+    }
+    if programs.is_empty() {
+        programs.push(bwrap_program(None));
+    }
+    programs.sort();
+    programs.dedup();
+
+    for program in programs {
+        let path = find_executable(&program);
+        report(
+            "bwrap",
+            match &path {
+                Some(path) => Ok(path.display().to_string()),
+                None => Err(format!("'{}' not found", program)),
+            },
+        );
+        report(
+            "bwrap version",
+            path.and_then(|_| bwrap::bwrap_version(&program))
+                .map(|version| version.to_string())
+                .ok_or_else(|| "unknown".to_string()),
+        );
+    }
+
+    report(
+        "user namespaces",
+        match bwrap::userns_disabled_by(|path| std::fs::read_to_string(path).ok()) {
+            None => Ok("enabled".to_string()),
+            Some(sysctl) => Err(format!("disabled by {}", sysctl)),
+        },
+    );
+
+    let config = config.and_then(|config| match config {
+        Some(_) => get_config_file(global),
+        None => Ok(None),
+    });
+    report(
+        "config",
+        match config {
+            Ok(Some(path)) => Ok(path.display().to_string()),
+            Ok(None) => Err("no configuration found".to_string()),
+            Err(err) => Err(format!("{:#}", err)),
+        },
+    );
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn model_list_cmd(simple: bool, global: &GlobalArgs) -> Result<()> {
+    let config = load_config(global)?;
+
+    // Sort models alphabetically
+    let models_map = config.get_models();
+    let mut models: Vec<_> = models_map.iter().collect();
+    models.sort_by_key(|(name, _)| *name);
+
+    if simple {
+        for (name, _) in models {
+            println!("{}", name);
+        }
+    } else {
+        println!("Available models:");
+        for (name, model) in models {
+            println!("\n{}:", name);
+            if !model.share.is_empty() {
+                println!("  share: {}", model.share.join(", "));
+            }
+            if !model.bind.is_empty() {
+                println!("  bind: {}", model.bind.join(", "));
+            }
+            if !model.ro_bind.is_empty() {
+                let ro_binds: Vec<_> = model.ro_bind.iter().map(|b| b.to_string()).collect();
+                println!("  ro_bind: {}", ro_binds.join(", "));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Output of `command show`
+enum ShowFormat {
+    /// Space-joined command line
+    Line,
+    /// One flag per line, with line continuations
+    Pretty,
+    /// NUL-terminated argv
+    Null,
+    /// One flag per line, with descriptions
+    Explain,
+    /// Command line quoted for a shell
+    Shell(Shell),
+    /// Resolved configuration as YAML
+    Config,
+}
+
+fn command_show_cmd(
+    command: &str,
+    args: &[String],
+    format: ShowFormat,
+    profile: Option<&str>,
+    global: &GlobalArgs,
+) -> Result<()> {
+    let config = load_config(global)?;
+    let mut builder = WrappedCommandBuilder::from_config_with_profile(&config, command, profile)?;
+
+    if let ShowFormat::Config = format {
+        print!("{}", serde_yaml::to_string(builder.config())?);
+        return Ok(());
+    }
+
+    if global.quiet {
+        builder = builder.quiet();
+    }
+
+    match format {
+        ShowFormat::Null => {
+            let mut stdout = std::io::stdout().lock();
+            for token in builder.show_argv(command, args)? {
+                stdout.write_all(token.as_bytes())?;
+                stdout.write_all(b"\0")?;
+            }
+        }
+        ShowFormat::Pretty => println!("{}", builder.show_pretty(command, args)?),
+        ShowFormat::Explain => println!("{}", builder.show_explained(command, args)?),
+        ShowFormat::Shell(shell) => {
+            println!("{}", shell.quote_argv(&builder.show_argv(command, args)?))
+        }
+        ShowFormat::Line | ShowFormat::Config => println!("{}", builder.show(command, args)?),
+    }
+
+    Ok(())
+}
+
+fn command_show_diff_cmd(
+    command: &str,
+    other: &str,
+    profile: Option<&str>,
+    global: &GlobalArgs,
+) -> Result<()> {
+    let config = load_config(global)?;
+    let builder = WrappedCommandBuilder::from_config_with_profile(&config, command, profile)?;
+    let other_builder = WrappedCommandBuilder::from_config_with_profile(&config, other, profile)?;
+
+    let lines = builder.config().diff(other_builder.config())?;
+    if lines.is_empty() {
+        println!("No differences between '{}' and '{}'", command, other);
+        return Ok(());
+    }
+
+    println!("--- {}", command);
+    println!("+++ {}", other);
+    for line in lines {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+fn config_check_bwrap_cmd(silent: bool) {
+    let path = check_bwrap(&bwrap_program(None));
+
+    if !silent {
+        println!("bubblewrap found: {}", path.display());
+    }
+}
+
+fn config_check_cmd(path: Option<String>, silent: bool, global: &GlobalArgs) -> Result<()> {
+    let config_path = config_check_path(path, global)?;
+
     let config = config::Config::from_file(&config_path)?;
+    let errors = config.validate();
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+        bail!("Found {} problem(s) in {:?}", errors.len(), config_path);
+    }
 
     if silent {
         return Ok(());
@@ -137,33 +852,183 @@ fn config_check_cmd(path: Option<String>, silent: bool) -> Result<()> {
     commands.sort_by_key(|(name, _)| *name);
 
     for (name, cmd_config) in commands {
-        match cmd_config.enabled {
-            true => println!("  - {}", name),
-            false => println!("  - {} (disabled)", name),
+        if let Some(reason) = cmd_config.disabled_reason() {
+            println!("  - {} (disabled: {})", name, reason);
+        } else if cmd_config.is_enabled() {
+            println!("  - {}", name);
+        } else {
+            println!("  - {} (disabled)", name);
+        }
+
+        for warning in command_warnings(&config, name, cmd_config)? {
+            println!("    warning: {}", warning);
         }
     }
 
     Ok(())
 }
 
-fn config_init_cmd(template: Option<String>) -> Result<()> {
-    use std::fs;
+/// Report of `config check --format json`
+#[derive(Serialize)]
+struct CheckReport {
+    path: PathBuf,
+    valid: bool,
+    commands: Vec<CheckedCommand>,
+    errors: Vec<CheckError>,
+}
 
-    let template_content = match template.as_deref() {
-        Some("nodejs") => include_str!("../templates/nodejs.yaml"),
-        Some("python") => include_str!("../templates/python.yaml"),
-        Some("ruby") => include_str!("../templates/ruby.yaml"),
-        Some("go") => include_str!("../templates/go.yaml"),
-        Some("rust") => include_str!("../templates/rust.yaml"),
-        None => include_str!("../templates/default.yaml"),
-        Some(other) => bail!("Unknown template: {}", other),
+#[derive(Serialize)]
+struct CheckedCommand {
+    name: String,
+    enabled: bool,
+    warnings: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct CheckError {
+    /// Offending entry, None for errors of the whole file (such as a syntax error)
+    command: Option<String>,
+    message: String,
+}
+
+fn config_check_json_cmd(path: Option<String>, global: &GlobalArgs) -> Result<()> {
+    let config_path = config_check_path(path, global)?;
+    let mut report = CheckReport {
+        path: config_path.clone(),
+        valid: false,
+        commands: vec![],
+        errors: vec![],
     };
 
-    let config_path = ConfigLoader::local_config_name();
-    if std::path::Path::new(config_path).exists() {
-        bail!("{} file already exists in current directory", config_path);
+    match config::Config::from_file(&config_path) {
+        Ok(config) => {
+            report.errors = config
+                .validate()
+                .into_iter()
+                .map(|error| CheckError {
+                    command: Some(error.entry),
+                    message: error.message,
+                })
+                .collect();
+
+            let commands_map = config.get_commands();
+            let mut commands: Vec<_> = commands_map.into_iter().collect();
+            commands.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (name, cmd_config) in commands {
+                // Entries with errors can't be built, their errors are reported
+                let warnings = match report.errors.is_empty() {
+                    true => command_warnings(&config, &name, &cmd_config)?,
+                    false => vec![],
+                };
+                report.commands.push(CheckedCommand {
+                    name,
+                    enabled: cmd_config.is_enabled(),
+                    warnings,
+                });
+            }
+        }
+        Err(err) => report.errors.push(CheckError {
+            command: None,
+            message: format!("{:#}", err),
+        }),
+    }
+    report.valid = report.errors.is_empty();
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !report.valid {
+        std::process::exit(1);
     }
 
+    Ok(())
+}
+
+/// Get the config file to check, the given one or the active one
+fn config_check_path(path: Option<String>, global: &GlobalArgs) -> Result<PathBuf> {
+    match path {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => get_config_file(global)?.context("No configuration found"),
+    }
+}
+
+/// Get the warnings of a command: skipped values, nested mounts and missing procfs
+fn command_warnings(
+    config: &config::Config,
+    name: &str,
+    cmd_config: &Entry,
+) -> Result<Vec<String>> {
+    let builder = WrappedCommandBuilder::from_entry(config, name, cmd_config.clone());
+
+    let (_, build_warnings) = builder.build_args_with_warnings()?;
+    let mut warnings: Vec<String> = build_warnings.iter().map(ToString::to_string).collect();
+    warnings.extend(builder.nested_mounts().iter().map(ToString::to_string));
+    if builder.missing_proc() {
+        warnings.push(MISSING_PROC.to_string());
+    }
+
+    Ok(warnings)
+}
+
+/// Templates bundled with shwrap, by name (the first one is used by default)
+const TEMPLATES: &[(&str, &str)] = &[
+    ("default", include_str!("../templates/default.yaml")),
+    ("nodejs", include_str!("../templates/nodejs.yaml")),
+    ("python", include_str!("../templates/python.yaml")),
+    ("ruby", include_str!("../templates/ruby.yaml")),
+    ("go", include_str!("../templates/go.yaml")),
+    ("rust", include_str!("../templates/rust.yaml")),
+];
+
+fn config_init_list_cmd() {
+    for (name, _) in TEMPLATES {
+        println!("{}", name);
+    }
+}
+
+fn config_init_cmd(template: Option<String>, interactive: bool) -> Result<()> {
+    use std::fs;
+    use std::io::IsTerminal;
+
+    let config_path = ConfigLoader::local_config_name();
+    let check_absent = || match Path::new(config_path).exists() {
+        true => bail!("{} file already exists in current directory", config_path),
+        false => Ok(()),
+    };
+
+    let interactive = interactive && {
+        let is_terminal = std::io::stdin().is_terminal();
+        if !is_terminal {
+            eprintln!("Warning: stdin is not a terminal, using the default template");
+        }
+        is_terminal
+    };
+
+    let bundled = |name: &str| TEMPLATES.iter().find(|(bundled, _)| *bundled == name);
+    let template_content = match template.as_deref() {
+        None if interactive => {
+            // Checked before asking anything
+            check_absent()?;
+            let answers = wizard::Answers::prompt(
+                &mut std::io::stdin().lock(),
+                &mut std::io::stdout().lock(),
+            )?;
+            answers.to_yaml()?
+        }
+        None => TEMPLATES[0].1.to_string(),
+        Some(name) if let Some((_, content)) = bundled(name) => content.to_string(),
+        // Anything else is a starter file of the user, checked before copying it
+        Some(path) if Path::new(path).is_file() => {
+            let content =
+                fs::read_to_string(path).context(format!("Failed to read template {:?}", path))?;
+            config::Config::from_yaml_with_source(&content, &format!("{:?}", path))?;
+            content
+        }
+        Some(other) => bail!(
+            "Unknown template: {} (not a bundled template nor a file, see --list)",
+            other
+        ),
+    };
+    check_absent()?;
+
     fs::write(config_path, template_content)
         .context(format!("Failed to write {} file", config_path))?;
 
@@ -172,23 +1037,63 @@ fn config_init_cmd(template: Option<String>) -> Result<()> {
     Ok(())
 }
 
-fn config_which_cmd() -> Result<()> {
-    if let Some(config_path) = ConfigLoader::get_config_file()? {
-        println!("{}", config_path.display());
-    } else {
-        println!("No configuration found");
+fn config_migrate_cmd(path: Option<PathBuf>, global: &GlobalArgs) -> Result<()> {
+    let config_path = match path {
+        Some(path) => path,
+        None => get_config_file(global)?.context("No configuration found")?,
+    };
+
+    match ConfigWriter::migrate(&config_path)? {
+        true => println!(
+            "Migrated {} to version {}",
+            config_path.display(),
+            config::CONFIG_VERSION
+        ),
+        false => println!("{} is already up to date", config_path.display()),
     }
 
     Ok(())
 }
 
-fn shell_hook_get_cmd(shell_name: &str) -> Result<()> {
-    let shell =
-        Shell::from_str(shell_name).context(format!("Unsupported shell: {}", shell_name))?;
+fn config_which_cmd(format: Option<WhichFormat>, global: &GlobalArgs) -> Result<()> {
+    let config_path = get_config_file(global)?;
+
+    match (format, config_path) {
+        (None | Some(WhichFormat::Path), Some(config_path)) => {
+            println!("{}", config_path.display())
+        }
+        (None, None) => println!("No configuration found"),
+        (Some(WhichFormat::Path), None) => {}
+        (Some(WhichFormat::Exists), Some(_)) => {}
+        (Some(WhichFormat::Exists), None) => std::process::exit(1),
+    }
 
-    let hook = shell
-        .get_hook()
-        .with_context(|| format!("No hook found for shell {}", shell.to_str()))?;
+    Ok(())
+}
+
+fn shell_hook_get_cmd(
+    shell: Option<Shell>,
+    commands_from_config: bool,
+    global: &GlobalArgs,
+) -> Result<()> {
+    let shell = match shell {
+        Some(shell) => shell,
+        None => Shell::detect()
+            .context("Could not detect the current shell, pass its name (bash, zsh, fish)")?,
+    };
+
+    let hook = if commands_from_config {
+        // The wildcard entry is not a command name
+        let commands: Vec<_> = load_config(global)?
+            .get_enabled_commands()
+            .into_keys()
+            .filter(|name| name != config::WILDCARD_COMMAND)
+            .collect();
+        shell.get_hook_with_commands(&commands)
+    } else {
+        shell.get_hook().map(String::from)
+    };
+    let hook = hook.with_context(|| format!("No hook found for shell {}", shell))?;
 
     print!("{}", hook);
 
@@ -37,8 +37,24 @@ __shwrap_set_commands() {
 }
 
 # Refresh SHWRAP_COMMANDS variable
+# (fixed when the hook was generated with --commands-from-config)
 __shwrap_refresh_commands() {
-  SHWRAP_COMMANDS=$(shwrap command list --simple 2>/dev/null)
+  if [[ -n "${SHWRAP_FIXED_COMMANDS+set}" ]]; then
+    SHWRAP_COMMANDS="$SHWRAP_FIXED_COMMANDS"
+  else
+    SHWRAP_COMMANDS=$(shwrap command list --simple 2>/dev/null)
+  fi
+}
+
+# Cache the config path of the current directory (see SHWRAP_CONFIG)
+__shwrap_refresh_config_cache() {
+  unset SHWRAP_CONFIG SHWRAP_CONFIG_CWD
+  local config
+  config=$(shwrap config which --format path 2>/dev/null)
+  if [[ -n "$config" ]]; then
+    export SHWRAP_CONFIG="$config"
+    export SHWRAP_CONFIG_CWD="$(pwd -P)"
+  fi
 }
 
 # Unset all commands
@@ -55,6 +71,7 @@ __shwrap_unset_commands() {
 __shwrap_directory_change_hook() {
   __shwrap_log "Directory change hook called"
   __shwrap_unset_commands
+  __shwrap_refresh_config_cache
   __shwrap_refresh_commands
   __shwrap_set_commands
 }
@@ -79,5 +96,6 @@ else
 fi
 
 # Initial setup
+__shwrap_refresh_config_cache
 __shwrap_refresh_commands
 __shwrap_set_commands
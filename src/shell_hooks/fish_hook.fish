@@ -38,8 +38,23 @@ function __shwrap_set_commands
 end
 
 # Refresh SHWRAP_COMMANDS variable
+# (fixed when the hook was generated with --commands-from-config)
 function __shwrap_refresh_commands
-  set -g SHWRAP_COMMANDS (shwrap command list --simple 2>/dev/null)
+  if set -q SHWRAP_FIXED_COMMANDS
+    set -g SHWRAP_COMMANDS $SHWRAP_FIXED_COMMANDS
+  else
+    set -g SHWRAP_COMMANDS (shwrap command list --simple 2>/dev/null)
+  end
+end
+
+# Cache the config path of the current directory (see SHWRAP_CONFIG)
+function __shwrap_refresh_config_cache
+  set -e SHWRAP_CONFIG SHWRAP_CONFIG_CWD
+  set -l config (shwrap config which --format path 2>/dev/null)
+  if test -n "$config"
+    set -gx SHWRAP_CONFIG $config
+    set -gx SHWRAP_CONFIG_CWD (pwd -P)
+  end
 end
 
 # Unset all commands
@@ -56,10 +71,12 @@ end
 function __shwrap_directory_change_hook --on-variable PWD
   __shwrap_log "Directory changed to:" $PWD
   __shwrap_unset_commands
+  __shwrap_refresh_config_cache
   __shwrap_refresh_commands
   __shwrap_set_commands
 end
 
 # Initial setup
+__shwrap_refresh_config_cache
 __shwrap_refresh_commands
 __shwrap_set_commands
@@ -1,10 +1,16 @@
 // Copyright (C) 2025 Pierre Le Gall
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
 const BASH_HOOK: &str = include_str!("bash_hook.sh");
 const ZSH_HOOK: &str = include_str!("zsh_hook.sh");
 const FISH_HOOK: &str = include_str!("fish_hook.fish");
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum Shell {
     Bash,
     Zsh,
@@ -12,6 +18,8 @@ pub enum Shell {
 }
 
 impl Shell {
+    pub const ALL: [Shell; 3] = [Shell::Bash, Shell::Zsh, Shell::Fish];
+
     pub fn to_str(&self) -> &str {
         match self {
             Shell::Bash => "bash",
@@ -20,13 +28,28 @@ impl Shell {
         }
     }
 
-    pub fn from_str(s: &str) -> Option<Self> {
-        match s.to_lowercase().as_str() {
-            "bash" => Some(Shell::Bash),
-            "zsh" => Some(Shell::Zsh),
-            "fish" => Some(Shell::Fish),
-            _ => None,
+    /// Detect the current shell from $SHELL, falling back to the parent process name
+    pub fn detect() -> Option<Self> {
+        if let Some(shell) = env::var_os("SHELL")
+            && let Ok(shell) = shell.to_string_lossy().parse()
+        {
+            return Some(shell);
         }
+
+        Self::detect_parent()
+    }
+
+    #[cfg(unix)]
+    fn detect_parent() -> Option<Self> {
+        use std::os::unix::process::parent_id;
+
+        let parent_name = std::fs::read_to_string(format!("/proc/{}/comm", parent_id())).ok()?;
+        parent_name.trim().parse().ok()
+    }
+
+    #[cfg(not(unix))]
+    fn detect_parent() -> Option<Self> {
+        None
     }
 
     pub fn get_hook(&self) -> Option<&str> {
@@ -36,4 +59,150 @@ impl Shell {
             Shell::Fish => Some(FISH_HOOK),
         }
     }
+
+    /// Get the hook wrapping only the given commands, instead of the ones
+    /// of the config found on each directory change
+    pub fn get_hook_with_commands(&self, commands: &[String]) -> Option<String> {
+        let hook = self.get_hook()?;
+
+        let preamble = match self {
+            Shell::Bash | Shell::Zsh => {
+                let commands = self.quote(&commands.join("\n"));
+                format!("typeset -g SHWRAP_FIXED_COMMANDS={}\n", commands)
+            }
+            Shell::Fish => {
+                let commands: Vec<_> = commands.iter().map(|cmd| self.quote(cmd)).collect();
+                format!("set -g SHWRAP_FIXED_COMMANDS {}\n", commands.join(" "))
+            }
+        };
+
+        Some(format!("{}{}", preamble, hook))
+    }
+
+    /// Join an argv into a command line the shell evaluates back into it
+    /// Words made of safe characters only are left unquoted
+    pub fn quote_argv(&self, argv: &[String]) -> String {
+        let is_safe = |word: &str| {
+            !word.is_empty()
+                && word
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_-+=/.,:@%".contains(c))
+        };
+
+        argv.iter()
+            .map(|word| match is_safe(word) {
+                true => word.clone(),
+                false => self.quote(word),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Quote a value as a single-quoted shell word
+    fn quote(&self, value: &str) -> String {
+        match self {
+            Shell::Bash | Shell::Zsh => format!("'{}'", value.replace('\'', "'\\''")),
+            Shell::Fish => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'")),
+        }
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+/// Error returned when parsing an unsupported shell name
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsupportedShell(pub String);
+
+impl fmt::Display for UnsupportedShell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let supported: Vec<_> = Shell::ALL.iter().map(Shell::to_str).collect();
+        write!(
+            f,
+            "Unsupported shell: {} (supported: {})",
+            self.0,
+            supported.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedShell {}
+
+impl FromStr for Shell {
+    type Err = UnsupportedShell;
+
+    /// Parse a shell name, also accepting paths (`/bin/zsh`) and login shell names (`-bash`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = Path::new(s)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(s)
+            .trim_start_matches('-')
+            .to_lowercase();
+
+        match name.as_str() {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(UnsupportedShell(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for shell in Shell::ALL {
+            assert_eq!(shell.to_string().parse::<Shell>(), Ok(shell));
+        }
+    }
+
+    #[test]
+    fn test_from_str_aliases() {
+        assert_eq!("ZSH".parse::<Shell>(), Ok(Shell::Zsh));
+        assert_eq!("/usr/bin/fish".parse::<Shell>(), Ok(Shell::Fish));
+        assert_eq!("-bash".parse::<Shell>(), Ok(Shell::Bash));
+    }
+
+    #[test]
+    fn test_from_str_unsupported() {
+        let err = "tcsh".parse::<Shell>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unsupported shell: tcsh (supported: bash, zsh, fish)"
+        );
+    }
+
+    #[test]
+    fn test_get_hook_with_commands() {
+        let commands = vec!["node".to_string(), "it's".to_string()];
+
+        let hook = Shell::Bash.get_hook_with_commands(&commands).unwrap();
+        assert!(hook.starts_with("typeset -g SHWRAP_FIXED_COMMANDS='node\nit'\\''s'\n"));
+        assert!(hook.ends_with(BASH_HOOK));
+
+        let hook = Shell::Fish.get_hook_with_commands(&commands).unwrap();
+        assert!(hook.starts_with("set -g SHWRAP_FIXED_COMMANDS 'node' 'it\\'s'\n"));
+        assert!(hook.ends_with(FISH_HOOK));
+    }
+
+    #[test]
+    fn test_quote_argv() {
+        let argv = ["bwrap", "--setenv", "HOME", "$HOME", "it's", "a\\b", ""].map(String::from);
+
+        assert_eq!(
+            Shell::Bash.quote_argv(&argv),
+            "bwrap --setenv HOME '$HOME' 'it'\\''s' 'a\\b' ''"
+        );
+        assert_eq!(
+            Shell::Fish.quote_argv(&argv),
+            "bwrap --setenv HOME '$HOME' 'it\\'s' 'a\\\\b' ''"
+        );
+    }
 }
@@ -0,0 +1,209 @@
+// Copyright (C) 2025 Pierre Le Gall
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{Context, Result, bail};
+use serde_yaml::{Mapping, Value};
+use std::io::{BufRead, Write};
+
+use shwrap::bwrap::NAMESPACES;
+use shwrap::config::{CONFIG_VERSION, Config};
+
+/// System directories bound read-only when asked
+const SYSTEM_DIRS: &[&str] = &["/usr", "/lib"];
+
+/// System directories bound read-only when asked, if they exist
+const OPTIONAL_SYSTEM_DIRS: &[&str] = &["/lib64"];
+
+/// Answers of `config init --interactive`
+#[derive(Debug, Default, PartialEq)]
+pub struct Answers {
+    pub command: String,
+    pub share: Vec<String>,
+    pub ro_bind: Vec<String>,
+    pub ro_bind_try: Vec<String>,
+    pub bind: Vec<String>,
+}
+
+impl Answers {
+    /// Ask the questions on `output`, reading the answers from `input`
+    /// Invalid answers are asked again, the end of the input is an error
+    pub fn prompt(input: &mut impl BufRead, output: &mut impl Write) -> Result<Self> {
+        let mut prompt = Prompt { input, output };
+        let mut answers = Answers::default();
+
+        while answers.command.is_empty() {
+            answers.command = prompt.ask("Command to wrap", "")?;
+        }
+
+        let question = format!(
+            "Namespaces to share with the host, among {}",
+            NAMESPACES.join(" ")
+        );
+        answers.share = loop {
+            let share: Vec<String> = prompt
+                .ask(&question, "none")?
+                .split_whitespace()
+                .filter(|namespace| *namespace != "none")
+                .map(String::from)
+                .collect();
+            match share
+                .iter()
+                .find(|namespace| !NAMESPACES.contains(&namespace.as_str()))
+            {
+                Some(unknown) => prompt.say(&format!("Unknown namespace: {}", unknown))?,
+                None => break share,
+            }
+        };
+
+        let question = format!(
+            "Bind {}, {} read-only?",
+            SYSTEM_DIRS.join(", "),
+            OPTIONAL_SYSTEM_DIRS.join(", ")
+        );
+        if prompt.confirm(&question, true)? {
+            answers.ro_bind = SYSTEM_DIRS.iter().map(|dir| dir.to_string()).collect();
+            answers.ro_bind_try = OPTIONAL_SYSTEM_DIRS
+                .iter()
+                .map(|dir| dir.to_string())
+                .collect();
+        }
+        // Relative sources are resolved against the config file directory
+        if prompt.confirm("Bind the current directory read-write at /workspace?", true)? {
+            answers.bind.push("./:/workspace".to_string());
+        }
+        let home_dir = format!("~/.{}", answers.command);
+        if prompt.confirm(&format!("Bind {} read-write?", home_dir), false)? {
+            answers.bind.push(format!("{}:{}", home_dir, home_dir));
+        }
+        let others = prompt.ask("Other binds (src:dst, space separated)", "none")?;
+        answers.bind.extend(
+            others
+                .split_whitespace()
+                .filter(|bind| *bind != "none")
+                .map(String::from),
+        );
+
+        Ok(answers)
+    }
+
+    /// Get the configuration file content, checked to be a valid config
+    pub fn to_yaml(&self) -> Result<String> {
+        let mut entry = Mapping::new();
+        for (key, values) in [
+            ("share", &self.share),
+            ("ro_bind", &self.ro_bind),
+            ("ro_bind_try", &self.ro_bind_try),
+            ("bind", &self.bind),
+        ] {
+            if !values.is_empty() {
+                entry.insert(Value::from(key), Value::from(values.clone()));
+            }
+        }
+
+        let mut document = Mapping::new();
+        document.insert(Value::from("version"), Value::from(CONFIG_VERSION));
+        document.insert(Value::from(self.command.as_str()), Value::Mapping(entry));
+
+        let content = format!(
+            "# Shwrap configuration created by 'shwrap config init --interactive'\n\n{}",
+            serde_yaml::to_string(&document)?
+        );
+        Config::from_yaml(&content).context("Invalid answers")?;
+
+        Ok(content)
+    }
+}
+
+/// Questions asked line by line
+struct Prompt<'a, I, O> {
+    input: &'a mut I,
+    output: &'a mut O,
+}
+
+impl<I: BufRead, O: Write> Prompt<'_, I, O> {
+    fn say(&mut self, message: &str) -> Result<()> {
+        writeln!(self.output, "{}", message)?;
+        Ok(())
+    }
+
+    /// Ask a question, `default` is the answer to an empty line
+    fn ask(&mut self, question: &str, default: &str) -> Result<String> {
+        match default.is_empty() {
+            true => write!(self.output, "{}: ", question)?,
+            false => write!(self.output, "{} [{}]: ", question, default)?,
+        }
+        self.output.flush()?;
+
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            bail!("No answer to '{}'", question);
+        }
+        match line.trim() {
+            "" => Ok(default.to_string()),
+            answer => Ok(answer.to_string()),
+        }
+    }
+
+    /// Ask a yes/no question
+    fn confirm(&mut self, question: &str, default: bool) -> Result<bool> {
+        let choices = if default { "Y/n" } else { "y/N" };
+        loop {
+            let answer = self.ask(&format!("{} [{}]", question, choices), "")?;
+            match answer.to_lowercase().as_str() {
+                "" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => self.say("Please answer y or n")?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_prompt() {
+        let mut input =
+            Cursor::new("\nnpm\nnetwork users\nnetwork\n\nmaybe\nn\ny\n/srv/cache:/cache\n");
+        let mut output = Vec::new();
+
+        let answers = Answers::prompt(&mut input, &mut output).unwrap();
+        assert_eq!(
+            answers,
+            Answers {
+                command: "npm".to_string(),
+                share: vec!["network".to_string()],
+                ro_bind: vec!["/usr".to_string(), "/lib".to_string()],
+                ro_bind_try: vec!["/lib64".to_string()],
+                bind: vec!["~/.npm:~/.npm".to_string(), "/srv/cache:/cache".to_string()],
+            }
+        );
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Unknown namespace: users"));
+        assert!(output.contains("Please answer y or n"));
+
+        let config = Config::from_yaml(&answers.to_yaml().unwrap()).unwrap();
+        let entry = config.get_command("npm").unwrap();
+        assert_eq!(entry.share, vec!["network"]);
+        assert_eq!(entry.ro_bind_try, vec!["/lib64"]);
+        assert_eq!(entry.bind, answers.bind);
+    }
+
+    #[test]
+    fn test_prompt_defaults() {
+        let mut input = Cursor::new("make\n\n\n\n\n\n");
+        let answers = Answers::prompt(&mut input, &mut Vec::new()).unwrap();
+        assert!(answers.share.is_empty());
+        assert_eq!(answers.ro_bind.len(), 2);
+        assert_eq!(answers.ro_bind_try, vec!["/lib64"]);
+        assert_eq!(answers.bind, vec!["./:/workspace"]);
+
+        // Running out of answers is an error
+        let mut input = Cursor::new("make\n");
+        assert!(Answers::prompt(&mut input, &mut Vec::new()).is_err());
+    }
+}
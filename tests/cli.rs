@@ -0,0 +1,1655 @@
+// Copyright (C) 2025 Pierre Le Gall
+// SPDX-License-Identifier: GPL-3.0-or-later
+// The tests run shell script stand-ins for bwrap
+#![cfg(unix)]
+
+use indoc::indoc;
+use shwrap::bwrap::WrappedCommandBuilder;
+use shwrap::config::loader::ConfigLoader;
+use shwrap::config::{Config, Entry};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Output};
+use tempfile::TempDir;
+
+/// Run the shwrap binary in the given directory, isolated from user and system configs
+fn run_shwrap(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_shwrap"))
+        .args(args)
+        .current_dir(dir)
+        .env("HOME", dir)
+        .env("SHWRAP_SYSTEM_CONFIG_DIR", dir)
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("SHWRAP_CONFIG")
+        .output()
+        .unwrap()
+}
+
+fn write_local_config(dir: &Path, yaml: &str) {
+    fs::write(dir.join(ConfigLoader::local_config_name()), yaml).unwrap();
+}
+
+#[test]
+fn test_model_list() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            base:
+              type: model
+              share:
+                - user
+              ro_bind:
+                - /usr
+
+            gui:
+              type: model
+              bind:
+                - /tmp/.X11-unix:/tmp/.X11-unix
+
+            node:
+              extends: base
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["model", "list"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("base:"));
+    assert!(stdout.contains("  share: user"));
+    assert!(stdout.contains("  ro_bind: /usr"));
+    assert!(stdout.contains("gui:"));
+    assert!(stdout.contains("  bind: /tmp/.X11-unix:/tmp/.X11-unix"));
+    assert!(!stdout.contains("node"));
+
+    let output = run_shwrap(temp_dir.path(), &["model", "list", "--simple"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "base\ngui\n");
+}
+
+#[test]
+fn test_config_check_reports_extends_cycle() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            a:
+              type: model
+              extends: b
+            b:
+              type: model
+              extends: a
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["config", "check"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Circular extends chain: a -> b -> a"));
+}
+
+#[test]
+fn test_command_exec_verbose() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            true:
+              ro_bind:
+                - /usr
+        "},
+    );
+
+    // bwrap may be missing, the resolved command is logged before running it
+    let output = run_shwrap(temp_dir.path(), &["--verbose", "command", "exec", "true"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("resolved configuration of 'true':"));
+    assert!(stderr.contains("ro_bind:"));
+    assert!(stderr.contains("resolved command command=bwrap"));
+    assert!(stderr.contains("--unshare-net"));
+
+    // Quiet by default
+    let output = run_shwrap(temp_dir.path(), &["command", "exec", "true"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains("DEBUG"));
+}
+
+#[test]
+fn test_command_exec_warns_once() {
+    let temp_dir = TempDir::new().unwrap();
+    let bwrap = temp_dir.path().join("bwrap");
+    fs::write(
+        &bwrap,
+        "#!/bin/sh\n[ \"$1\" = --version ] && exit 1\nexit 0\n",
+    )
+    .unwrap();
+    fs::set_permissions(&bwrap, fs::Permissions::from_mode(0o755)).unwrap();
+    write_local_config(
+        temp_dir.path(),
+        &format!(
+            "true:\n  bwrap_path: {}\n  root_tmpfs: true\n",
+            bwrap.display()
+        ),
+    );
+
+    for args in [
+        &["command", "exec", "true"][..],
+        &["command", "exec", "--stats", "true"],
+    ] {
+        let output = run_shwrap(temp_dir.path(), args);
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(
+            stderr.matches("root_tmpfs is set without any bind").count(),
+            1
+        );
+    }
+}
+
+#[test]
+fn test_config_check_reports_invalid_bind() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            node:
+              bind:
+                - invalid
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["config", "check"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Invalid configuration for 'node': invalid bind format 'invalid'"));
+}
+
+#[test]
+fn test_config_check_reports_nested_mounts() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            node:
+              bind:
+                - /srv/app:/app
+              ro_bind:
+                - /app/vendor
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["config", "check"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(
+        "  - node\n    warning: '/app/vendor' (read-only) is mounted over '/app' (read-write)"
+    ));
+}
+
+#[test]
+fn test_config_check_warns_about_missing_proc() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            node:
+              ro_bind:
+                - /usr
+            python:
+              ro_bind:
+                - /usr
+              auto_proc: true
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["config", "check"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout
+            .contains("  - node\n    warning: the pid namespace is unshared without a proc mount")
+    );
+    assert!(stdout.ends_with("  - python\n"));
+}
+
+#[test]
+fn test_config_check_reports_all_problems() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            node:
+              share:
+                - netwrok
+            python:
+              extends: missing
+            ruby:
+              bind:
+                - invalid
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["config", "check"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("'node': unknown namespace 'netwrok' in share"));
+    assert!(stderr.contains("'python': extends unknown model 'missing'"));
+    assert!(stderr.contains("'ruby': invalid bind format 'invalid'"));
+    assert!(stderr.contains("Found 3 problem(s)"));
+}
+
+#[test]
+fn test_config_check_reports_uid_with_shared_user() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            apt:
+              share:
+                - user
+              uid: 0
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["config", "check"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Invalid configuration for 'apt'"));
+    assert!(stderr.contains("require the user namespace to be unshared"));
+}
+
+#[test]
+fn test_config_check_reports_syntax_error_location() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        "version: 1\nnode:\n  bind: [/usr\n  env: {}\n",
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["config", "check"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(ConfigLoader::local_config_name()));
+    assert!(stderr.contains(" 4 |   env: {}"), "{}", stderr);
+}
+
+#[test]
+fn test_command_show_print_config() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            base:
+              type: model
+              ro_bind:
+                - /usr
+                - /lib
+
+            node:
+              extends: base
+              bind:
+                - ~/.npm:~/.npm
+        "},
+    );
+
+    let output = run_shwrap(
+        temp_dir.path(),
+        &["command", "show", "--print-config", "node"],
+    );
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("bwrap"));
+
+    let resolved: serde_yaml::Value = serde_yaml::from_str(&stdout).unwrap();
+    assert_eq!(resolved["extends"], "base");
+    assert_eq!(resolved["ro_bind"][0], "/usr");
+    assert_eq!(resolved["ro_bind"][1], "/lib");
+    assert_eq!(resolved["bind"][0], "~/.npm:~/.npm");
+}
+
+#[test]
+fn test_command_list_wildcard() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            '*':
+              share:
+                - user
+            node:
+              share:
+                - network
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["command", "list"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\n* (any other command):\n  share: user"));
+    assert!(stdout.contains("\nnode:\n  share: network"));
+
+    let output = run_shwrap(temp_dir.path(), &["command", "list", "--simple"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "node\n");
+
+    let output = run_shwrap(temp_dir.path(), &["command", "show", "ruby"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("--unshare-net"));
+    assert!(!stdout.contains("--unshare-user"));
+}
+
+#[test]
+fn test_config_flag_overrides_local_config() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            local:
+              share:
+                - user
+        "},
+    );
+    let other_config = temp_dir.path().join("other.yaml");
+    fs::write(
+        &other_config,
+        indoc! {"
+            other:
+              ro_bind:
+                - /opt
+        "},
+    )
+    .unwrap();
+    let other_config = other_config.to_str().unwrap();
+
+    let output = run_shwrap(
+        temp_dir.path(),
+        &["--config", other_config, "command", "list", "--simple"],
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "other\n");
+
+    let output = run_shwrap(
+        temp_dir.path(),
+        &["command", "show", "other", "-c", other_config],
+    );
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("--ro-bind /opt /opt"));
+
+    let output = run_shwrap(
+        temp_dir.path(),
+        &["-c", other_config, "command", "show", "local"],
+    );
+    assert!(!output.status.success());
+
+    let output = run_shwrap(temp_dir.path(), &["-c", other_config, "config", "which"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim_end(),
+        other_config
+    );
+}
+
+#[test]
+fn test_quiet_hides_build_warnings() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            node:
+              bind:
+                - invalid
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["command", "show", "node"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Warning: invalid bind format 'invalid'"));
+
+    let output = run_shwrap(temp_dir.path(), &["--quiet", "command", "show", "node"]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+
+    // Fatal errors still surface
+    let output = run_shwrap(temp_dir.path(), &["--quiet", "command", "show", "missing"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("No configuration found for command 'missing'"));
+}
+
+#[test]
+fn test_command_show_diff() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            base:
+              type: model
+              ro_bind:
+                - /usr
+
+            curl:
+              extends: base
+              share:
+                - network
+              env:
+                LANG: C
+
+            node:
+              extends: base
+              bind:
+                - ~/.npm:~/.npm
+              env:
+                LANG: C.UTF-8
+        "},
+    );
+
+    let output = run_shwrap(
+        temp_dir.path(),
+        &["command", "show", "--diff", "node", "curl"],
+    );
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[..2], ["--- curl", "+++ node"]);
+    assert!(lines.contains(&"- share: network"));
+    assert!(lines.contains(&"+ bind: ~/.npm:~/.npm"));
+    assert!(lines.contains(&"~ env.LANG: C -> C.UTF-8"));
+    assert!(!stdout.contains("ro_bind"));
+}
+
+#[test]
+fn test_command_show_merge_relative_paths() {
+    let temp_dir = TempDir::new().unwrap();
+    let system_dir = temp_dir.path().join("system");
+    let project_dir = temp_dir.path().join("project");
+    fs::create_dir(&system_dir).unwrap();
+    fs::create_dir(&project_dir).unwrap();
+    fs::write(
+        system_dir.join("default.yaml"),
+        "version: 1\nnode:\n  ro_bind:\n    - ./tools\n",
+    )
+    .unwrap();
+    write_local_config(
+        &project_dir,
+        "version: 1\ncurl:\n  ro_bind:\n    - ./vendor\n",
+    );
+
+    let show = |command: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_shwrap"))
+            .args(["command", "show", "--merge", command])
+            .current_dir(&project_dir)
+            .env("HOME", temp_dir.path())
+            .env("SHWRAP_SYSTEM_CONFIG_DIR", &system_dir)
+            .env_remove("XDG_CONFIG_HOME")
+            .env_remove("SHWRAP_CONFIG")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // Relative to the configuration defining each entry
+    let tools = system_dir.join("tools").display().to_string();
+    assert!(show("node").contains(&format!("--ro-bind {} {}", tools, tools)));
+    let vendor = project_dir.join("vendor").display().to_string();
+    assert!(show("curl").contains(&format!("--ro-bind {} {}", vendor, vendor)));
+}
+
+#[test]
+fn test_command_show_merge_parent_directories() {
+    let temp_dir = TempDir::new().unwrap();
+    let sub_dir = temp_dir.path().join("sub");
+    fs::create_dir(&sub_dir).unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            version: 1
+            base:
+              type: model
+              ro_bind:
+                - /usr
+            node:
+              ro_bind:
+                - /outer
+        "},
+    );
+    write_local_config(
+        &sub_dir,
+        indoc! {"
+            version: 1
+            node:
+              extends: base
+        "},
+    );
+
+    // Every local configuration found by the search is merged, the nearest wins
+    let output = run_shwrap(&sub_dir, &["command", "show", "--merge", "node"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("--ro-bind /usr /usr"));
+    assert!(!stdout.contains("/outer"));
+
+    // Only the nearest one otherwise
+    let output = run_shwrap(&sub_dir, &["command", "show", "node"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("--ro-bind /usr /usr"));
+}
+
+#[test]
+fn test_command_show_disabled() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            curl:
+              enabled: false
+            node:
+              ro_bind:
+                - /usr
+        "},
+    );
+
+    // Refused like by exec
+    for args in [
+        &["command", "show", "curl"][..],
+        &["command", "show", "--print-config", "curl"],
+        &["command", "show", "--diff", "node", "curl"],
+    ] {
+        let output = run_shwrap(temp_dir.path(), args);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("Command 'curl' is disabled"));
+    }
+}
+
+#[test]
+fn test_command_show_profile() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            node:
+              profiles:
+                dev:
+                  share:
+                    - network
+                ci:
+                  ro_bind:
+                    - /ci
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["command", "show", "node"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("--unshare-net"));
+    assert!(!stdout.contains("--ro-bind /ci /ci"));
+
+    let output = run_shwrap(
+        temp_dir.path(),
+        &["command", "show", "--profile", "dev", "node"],
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("--unshare-net"));
+
+    let output = run_shwrap(
+        temp_dir.path(),
+        &["command", "show", "--profile", "ci", "node"],
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("--unshare-net"));
+    assert!(stdout.contains("--ro-bind /ci /ci"));
+
+    let output = run_shwrap(
+        temp_dir.path(),
+        &["command", "show", "--profile", "nope", "node"],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("No profile 'nope' found"));
+}
+
+#[test]
+fn test_shell_hook_get_detects_shell() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_shwrap"))
+        .args(["shell-hook", "get"])
+        .current_dir(temp_dir.path())
+        .env("SHELL", "/bin/zsh")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("#!/usr/bin/env zsh"));
+
+    // An explicit name wins
+    let output = Command::new(env!("CARGO_BIN_EXE_shwrap"))
+        .args(["shell-hook", "get", "fish"])
+        .env("SHELL", "/bin/zsh")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("#!/usr/bin/env fish"));
+}
+
+#[test]
+fn test_command_which() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let output = run_shwrap(temp_dir.path(), &["command", "which", "sh"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let path = Path::new(stdout.trim_end());
+    assert!(path.is_absolute());
+    assert_eq!(path.file_name().unwrap(), "sh");
+
+    let output = run_shwrap(
+        temp_dir.path(),
+        &["command", "which", "shwrap-surely-missing-command"],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Command 'shwrap-surely-missing-command' not found in PATH"));
+}
+
+#[test]
+fn test_config_which_formats() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Without configuration
+    let output = run_shwrap(temp_dir.path(), &["config", "which"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "No configuration found\n"
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["config", "which", "--format", "path"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = run_shwrap(temp_dir.path(), &["config", "which", "--format", "exists"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+
+    // With configuration
+    write_local_config(temp_dir.path(), "node: {}\n");
+    let config_path = temp_dir.path().join(ConfigLoader::local_config_name());
+
+    let output = run_shwrap(temp_dir.path(), &["config", "which", "--format", "path"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{}\n", config_path.display())
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["config", "which", "--format", "exists"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_command_list_description() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            curl:
+              description: Fetches release archives
+              share:
+                - network
+            git: {}
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["command", "list"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("curl:\n  description: Fetches release archives\n"));
+
+    let output = run_shwrap(temp_dir.path(), &["command", "list", "--json"]);
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["curl"]["description"], "Fetches release archives");
+    assert_eq!(json["curl"]["share"][0], "network");
+    assert!(json["git"].get("description").is_none());
+}
+
+#[test]
+fn test_command_list_conditionally_disabled() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            node:
+              enabled:
+                os: plan9
+            git: {}
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["command", "list"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\nnode (disabled: requires os plan9"));
+    assert!(stdout.contains("\ngit:\n"));
+
+    let output = run_shwrap(temp_dir.path(), &["command", "list", "--simple"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "git\n");
+}
+
+#[test]
+fn test_command_list_disabled() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            base:
+              type: model
+              enabled: false
+            node:
+              enabled:
+                os: plan9
+            python:
+              enabled: false
+            git: {}
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["command", "list", "--disabled"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Disabled command configurations:\n"));
+    assert!(stdout.contains("  node (requires os plan9"));
+    assert!(stdout.contains("  python\n"));
+    assert!(!stdout.contains("git") && !stdout.contains("base"));
+
+    let output = run_shwrap(
+        temp_dir.path(),
+        &["command", "list", "--disabled", "--simple"],
+    );
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "node\npython\n");
+}
+
+#[test]
+fn test_bwrap_missing() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(temp_dir.path(), "version: 1\ntrue: {}\n");
+    let empty_path = temp_dir.path().join("bin");
+    fs::create_dir(&empty_path).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_shwrap"))
+        .args(["command", "exec", "true"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .env("SHWRAP_SYSTEM_CONFIG_DIR", temp_dir.path())
+        .env("PATH", &empty_path)
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("SHWRAP_CONFIG")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(127));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        stderr,
+        "Error: bubblewrap is not installed; install the 'bubblewrap' package\n"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_shwrap"))
+        .args(["config", "check", "--bwrap"])
+        .current_dir(temp_dir.path())
+        .env("PATH", &empty_path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(127));
+
+    // Found once a bwrap executable is in PATH
+    let bwrap = empty_path.join("bwrap");
+    fs::write(&bwrap, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&bwrap, fs::Permissions::from_mode(0o755)).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_shwrap"))
+        .args(["config", "check", "--bwrap"])
+        .current_dir(temp_dir.path())
+        .env("PATH", &empty_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("bubblewrap found: {}\n", bwrap.display())
+    );
+}
+
+#[test]
+fn test_bwrap_path_override() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            version: 1
+            node: {}
+            git:
+              bwrap_path: /opt/config/bwrap
+        "},
+    );
+
+    let show = |command: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_shwrap"))
+            .args(["command", "show", command])
+            .current_dir(temp_dir.path())
+            .env("HOME", temp_dir.path())
+            .env("SHWRAP_SYSTEM_CONFIG_DIR", temp_dir.path())
+            .env("SHWRAP_BWRAP_PATH", "/opt/env/bwrap")
+            .env_remove("XDG_CONFIG_HOME")
+            .env_remove("SHWRAP_CONFIG")
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert!(show("node").starts_with("/opt/env/bwrap --unshare-user"));
+    assert!(show("git").starts_with("/opt/config/bwrap --unshare-user"));
+}
+
+#[test]
+fn test_command_exec_timeout_reads_terminal() {
+    let temp_dir = TempDir::new().unwrap();
+    let bwrap = temp_dir.path().join("bwrap");
+    fs::write(
+        &bwrap,
+        "#!/bin/sh\n[ \"$1\" = --version ] && exit 1\nread line\necho \"read $line\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&bwrap, fs::Permissions::from_mode(0o755)).unwrap();
+    write_local_config(
+        temp_dir.path(),
+        &format!("cat:\n  bwrap_path: {}\n  timeout: 5\n", bwrap.display()),
+    );
+
+    // Run on a terminal by script(1), a background group would be stopped
+    // reading it until the timeout, and so would the shell reading it after
+    // if the terminal was not given back
+    let shwrap = format!(
+        "{} command exec cat && read line && echo \"then $line\"",
+        env!("CARGO_BIN_EXE_shwrap")
+    );
+    let mut child = Command::new("script")
+        .args(["--quiet", "--return", "--command", &shwrap, "/dev/null"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .env("SHWRAP_SYSTEM_CONFIG_DIR", temp_dir.path())
+        .env("SHELL", "/bin/sh")
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("SHWRAP_CONFIG")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"hello\nworld\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("read hello"), "{}", stdout);
+    assert!(stdout.contains("then world"), "{}", stdout);
+}
+
+#[test]
+fn test_command_exec_timeout_kills_network_helper_group() {
+    let temp_dir = TempDir::new().unwrap();
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let pid_file = temp_dir.path().join("bwrap.pid");
+
+    // Stand-ins: pasta runs the command as its child (not replaced by it), and
+    // bwrap outlives it unless its whole group is killed
+    let stubs = [
+        (
+            "pasta",
+            "#!/bin/sh\nwhile [ \"$1\" != -- ]; do shift; done\nshift\n\"$@\"\n",
+        ),
+        ("nft", "#!/bin/sh\nexit 0\n"),
+        (
+            "bwrap",
+            "#!/bin/sh\n[ \"$1\" = --version ] && exit 1\necho $$ > \"$PID_FILE\"\nexec sleep 30 </dev/null >/dev/null 2>&1\n",
+        ),
+    ];
+    for (name, content) in stubs {
+        let path = bin_dir.join(name);
+        fs::write(&path, content).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            version: 1
+            curl:
+              network: restricted
+              allow_hosts:
+                - localhost
+              timeout: 1
+        "},
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_shwrap"))
+        .args(["command", "exec", "curl"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .env("SHWRAP_SYSTEM_CONFIG_DIR", temp_dir.path())
+        .env("PATH", format!("{}:/usr/bin:/bin", bin_dir.display()))
+        .env("PID_FILE", &pid_file)
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("SHWRAP_CONFIG")
+        .env_remove("SHWRAP_BWRAP_PATH")
+        .output()
+        .unwrap();
+    assert_eq!(
+        output.status.code(),
+        Some(124),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Gone, or a zombie until its new parent reaps it
+    let pid = fs::read_to_string(&pid_file).unwrap();
+    let stat = Path::new("/proc").join(pid.trim()).join("stat");
+    let killed = (0..40).any(|_| {
+        let state = fs::read_to_string(&stat).ok();
+        let done = state.is_none_or(|stat| stat.contains(") Z "));
+        if !done {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        done
+    });
+    assert!(killed, "bwrap is still running after the timeout");
+}
+
+#[test]
+fn test_command_run_all() {
+    let temp_dir = TempDir::new().unwrap();
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let bwrap = bin_dir.join("bwrap");
+    fs::write(
+        &bwrap,
+        indoc! {r#"
+            #!/bin/sh
+            [ "$1" = --version ] && { echo bubblewrap 0.9.0; exit 0; }
+            echo "ran in $(basename "$PWD")"
+            [ -e fail ] && exit 3
+            exit 0
+        "#},
+    )
+    .unwrap();
+    fs::set_permissions(&bwrap, fs::Permissions::from_mode(0o755)).unwrap();
+
+    for name in ["front", "back"] {
+        let project_dir = temp_dir.path().join(name);
+        fs::create_dir(&project_dir).unwrap();
+        write_local_config(&project_dir, "true: {}\n");
+    }
+    fs::write(temp_dir.path().join("back").join("fail"), "").unwrap();
+
+    let path = format!("{}:/usr/bin:/bin", bin_dir.display());
+    let output = Command::new(env!("CARGO_BIN_EXE_shwrap"))
+        .args([
+            "command", "run-all", "--dir", "front", "--dir", "back", "true",
+        ])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .env("SHWRAP_SYSTEM_CONFIG_DIR", temp_dir.path())
+        .env("PATH", path)
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("SHWRAP_CONFIG")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "ran in front\nran in back\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Summary:\n  front: ok\n  back: failed (exit code 3)\n"));
+
+    // The bwrap executable set by the entry is run, bwrap missing from PATH
+    let shim_dir = temp_dir.path().join("shim");
+    fs::create_dir(&shim_dir).unwrap();
+    fs::rename(&bwrap, shim_dir.join("bwrap-shim")).unwrap();
+    let config = format!(
+        "true:\n  bwrap_path: {}\n",
+        shim_dir.join("bwrap-shim").display()
+    );
+    write_local_config(&temp_dir.path().join("front"), &config);
+    let output = Command::new(env!("CARGO_BIN_EXE_shwrap"))
+        .args(["command", "run-all", "--dir", "front", "true"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .env("SHWRAP_SYSTEM_CONFIG_DIR", temp_dir.path())
+        .env("PATH", format!("{}:/usr/bin:/bin", bin_dir.display()))
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("SHWRAP_CONFIG")
+        .env_remove("SHWRAP_BWRAP_PATH")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "ran in front\n");
+}
+
+#[test]
+fn test_command_exec_stats() {
+    let temp_dir = TempDir::new().unwrap();
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let bwrap = bin_dir.join("bwrap");
+    fs::write(
+        &bwrap,
+        indoc! {"
+            #!/bin/sh
+            [ \"$1\" = --version ] && { echo bubblewrap 0.9.0; exit 0; }
+            exit 5
+        "},
+    )
+    .unwrap();
+    fs::set_permissions(&bwrap, fs::Permissions::from_mode(0o755)).unwrap();
+    write_local_config(temp_dir.path(), "version: 1\ntrue: {}\n");
+
+    let path = format!("{}:/usr/bin:/bin", bin_dir.display());
+    let output = Command::new(env!("CARGO_BIN_EXE_shwrap"))
+        .args(["command", "exec", "--stats", "true"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .env("SHWRAP_SYSTEM_CONFIG_DIR", temp_dir.path())
+        .env("PATH", path)
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("SHWRAP_CONFIG")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(5));
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let stats = stderr.split_once("Stats:\n").unwrap().1;
+    let lines: Vec<(&str, &str)> = stats
+        .lines()
+        .map(|line| line.trim().split_once(": ").unwrap())
+        .collect();
+    let keys: Vec<&str> = lines.iter().map(|(key, _)| *key).collect();
+    assert_eq!(keys, vec!["config", "args", "total", "exit code"]);
+    for (_, value) in &lines[..3] {
+        let millis: f64 = value.strip_suffix("ms").unwrap().parse().unwrap();
+        assert!(millis >= 0.0);
+    }
+    assert_eq!(lines[3].1, "5");
+}
+
+#[test]
+fn test_command_exec_stdio_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let bwrap = bin_dir.join("bwrap");
+    fs::write(
+        &bwrap,
+        indoc! {r#"
+            #!/bin/sh
+            [ "$1" = --version ] && { echo bubblewrap 0.9.0; exit 0; }
+            exec cat
+        "#},
+    )
+    .unwrap();
+    fs::set_permissions(&bwrap, fs::Permissions::from_mode(0o755)).unwrap();
+    write_local_config(
+        temp_dir.path(),
+        "version: 1
+cat: {}
+",
+    );
+    fs::write(temp_dir.path().join("input.txt"), "hello\nworld\n").unwrap();
+
+    let path = format!("{}:/usr/bin:/bin", bin_dir.display());
+    let output = Command::new(env!("CARGO_BIN_EXE_shwrap"))
+        .args([
+            "--quiet",
+            "command",
+            "exec",
+            "--stdin-file",
+            "input.txt",
+            "--stdout-file",
+            "output.txt",
+            "cat",
+        ])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .env("SHWRAP_SYSTEM_CONFIG_DIR", temp_dir.path())
+        .env("PATH", path)
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("SHWRAP_CONFIG")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("output.txt")).unwrap(),
+        "hello\nworld\n"
+    );
+}
+
+#[test]
+fn test_command_exec_record() {
+    let temp_dir = TempDir::new().unwrap();
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let bwrap = bin_dir.join("bwrap");
+    fs::write(
+        &bwrap,
+        indoc! {r#"
+            #!/bin/sh
+            [ "$1" = --version ] && { echo bubblewrap 0.9.0; exit 0; }
+            exit 3
+        "#},
+    )
+    .unwrap();
+    fs::set_permissions(&bwrap, fs::Permissions::from_mode(0o755)).unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            version: 1
+            make:
+              share:
+                - network
+              ro_bind:
+                - /usr
+        "},
+    );
+
+    let path = format!("{}:/usr/bin:/bin", bin_dir.display());
+    let log = temp_dir.path().join("audit.log");
+    let exec = |args: &[&str], audit_log: Option<&Path>| {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_shwrap"));
+        cmd.args(["--quiet", "command", "exec"])
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env("HOME", temp_dir.path())
+            .env("SHWRAP_SYSTEM_CONFIG_DIR", temp_dir.path())
+            .env("PATH", &path)
+            .env_remove("XDG_CONFIG_HOME")
+            .env_remove("SHWRAP_CONFIG")
+            .env_remove("SHWRAP_AUDIT_LOG");
+        if let Some(audit_log) = audit_log {
+            cmd.env("SHWRAP_AUDIT_LOG", audit_log);
+        }
+        cmd.output().unwrap()
+    };
+
+    let output = exec(&["--record", log.to_str().unwrap(), "make", "all"], None);
+    assert_eq!(output.status.code(), Some(3));
+    let output = exec(&["make", "clean"], Some(&log));
+    assert_eq!(output.status.code(), Some(3));
+
+    let content = fs::read_to_string(&log).unwrap();
+    let records: Vec<serde_json::Value> = content
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["command"], "make");
+    assert_eq!(records[0]["args"], serde_json::json!(["all"]));
+    assert_eq!(records[0]["shared"], serde_json::json!(["network"]));
+    assert_eq!(records[0]["binds"][0]["dst"], "/usr");
+    assert_eq!(records[0]["exit_code"], 3);
+    assert!(records[0]["timestamp"].as_u64().unwrap() > 0);
+    assert_eq!(records[1]["args"], serde_json::json!(["clean"]));
+}
+
+#[test]
+fn test_command_show_unset_env_pattern() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            aws:
+              unset_env:
+                - AWS_*
+                - DEBUG
+        "},
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_shwrap"))
+        .args(["command", "show", "aws"])
+        .current_dir(temp_dir.path())
+        .env_clear()
+        .env("HOME", temp_dir.path())
+        .env("SHWRAP_SYSTEM_CONFIG_DIR", temp_dir.path())
+        .env("AWS_ACCESS_KEY_ID", "id")
+        .env("AWS_SECRET_ACCESS_KEY", "secret")
+        .env("MY_AWS_TOKEN", "token")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(
+        "--unsetenv AWS_ACCESS_KEY_ID --unsetenv AWS_SECRET_ACCESS_KEY --unsetenv DEBUG"
+    ));
+    assert!(!stdout.contains("MY_AWS_TOKEN"));
+    assert!(!stdout.contains("AWS_*"));
+}
+
+#[test]
+fn test_doctor() {
+    let temp_dir = TempDir::new().unwrap();
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let bwrap = bin_dir.join("bwrap");
+    fs::write(
+        &bwrap,
+        indoc! {r#"
+            #!/bin/sh
+            [ "$1" = --version ] && { echo bubblewrap 0.9.0; exit 0; }
+            exit 1
+        "#},
+    )
+    .unwrap();
+    fs::set_permissions(&bwrap, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let doctor = || {
+        Command::new(env!("CARGO_BIN_EXE_shwrap"))
+            .arg("doctor")
+            .current_dir(temp_dir.path())
+            .env("HOME", temp_dir.path())
+            .env("SHWRAP_SYSTEM_CONFIG_DIR", temp_dir.path())
+            .env("PATH", format!("{}:/usr/bin:/bin", bin_dir.display()))
+            .env_remove("XDG_CONFIG_HOME")
+            .env_remove("SHWRAP_CONFIG")
+            .env_remove("SHWRAP_BWRAP_PATH")
+            .output()
+            .unwrap()
+    };
+
+    // No configuration yet
+    let output = doctor();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], format!("PASS bwrap: {}", bwrap.display()));
+    assert_eq!(lines[1], "PASS bwrap version: 0.9.0");
+    assert!(
+        lines[2].starts_with("PASS user namespaces: ")
+            || lines[2].starts_with("FAIL user namespaces: ")
+    );
+    assert_eq!(lines[3], "FAIL config: no configuration found");
+
+    write_local_config(temp_dir.path(), "version: 1\nmake: {}\n");
+    let stdout = String::from_utf8(doctor().stdout).unwrap();
+    let config_path = temp_dir.path().join(".shwrap.yaml");
+    assert!(stdout.ends_with(&format!("PASS config: {}\n", config_path.display())));
+
+    // The bwrap executables set by the entries are checked instead
+    let shim = bin_dir.join("bwrap-shim");
+    fs::write(&shim, "#!/bin/sh\necho bubblewrap 0.10.0\n").unwrap();
+    fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).unwrap();
+    let config = format!(
+        "version: 1\nmake:\n  bwrap_path: {}\ncurl:\n  bwrap_path: /missing/bwrap\n",
+        shim.display()
+    );
+    write_local_config(temp_dir.path(), &config);
+    let output = doctor();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines[..4],
+        [
+            "FAIL bwrap: '/missing/bwrap' not found",
+            "FAIL bwrap version: unknown",
+            &format!("PASS bwrap: {}", shim.display()),
+            "PASS bwrap version: 0.10.0",
+        ]
+    );
+}
+
+#[test]
+fn test_command_exec_no_config() {
+    let temp_dir = TempDir::new().unwrap();
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let bwrap = bin_dir.join("bwrap");
+    fs::write(
+        &bwrap,
+        indoc! {r#"
+            #!/bin/sh
+            [ "$1" = --version ] && { echo bubblewrap 0.9.0; exit 0; }
+            printf '%s\n' "$@"
+        "#},
+    )
+    .unwrap();
+    fs::set_permissions(&bwrap, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = format!("{}:/usr/bin:/bin", bin_dir.display());
+    let exec = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_shwrap"))
+            .args(["command", "exec"])
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env("HOME", temp_dir.path())
+            .env("SHWRAP_SYSTEM_CONFIG_DIR", temp_dir.path())
+            .env("PATH", &path)
+            .env_remove("XDG_CONFIG_HOME")
+            .env_remove("SHWRAP_CONFIG")
+            .output()
+            .unwrap()
+    };
+    let flags = [
+        "--share",
+        "network",
+        "--ro-bind",
+        "/usr",
+        "--bind",
+        "/srv/app:/app",
+        "--env",
+        "MODE=test",
+    ];
+
+    // No configuration at all
+    let mut args = flags.to_vec();
+    args.extend(["ls", "/app"]);
+    let output = exec(&args);
+    assert!(output.status.success());
+
+    let entry = Entry {
+        share: vec!["network".to_string()],
+        ro_bind: vec!["/usr".into()],
+        bind: vec!["/srv/app:/app".to_string()],
+        env: [("MODE".to_string(), "test".to_string())].into(),
+        ..Default::default()
+    };
+    let mut expected = WrappedCommandBuilder::new(entry).build_args().unwrap();
+    expected.extend(["ls".to_string(), "/app".to_string()]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), expected);
+
+    // Configured commands need --no-config
+    write_local_config(temp_dir.path(), "version: 1\nls: {}\n");
+    let output = exec(&args);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("pass --no-config"));
+
+    let mut args = vec!["--no-config"];
+    args.extend(flags);
+    args.extend(["ls", "/app"]);
+    let output = exec(&args);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), stdout);
+
+    // `net` is the network namespace, unknown names are refused
+    args[2] = "net";
+    let output = exec(&args);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), stdout);
+
+    args[2] = "nett";
+    let output = exec(&args);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unknown namespace 'nett'"), "{}", stderr);
+}
+
+#[test]
+fn test_command_show_null() {
+    let temp_dir = TempDir::new().unwrap();
+    let yaml = indoc! {"
+        node:
+          bind:
+            - /srv/my app:/app
+          env:
+            GREETING: hello world
+    "};
+    write_local_config(temp_dir.path(), yaml);
+
+    let output = run_shwrap(
+        temp_dir.path(),
+        &["command", "show", "--null", "node", "app.js", "--port 80"],
+    );
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let tokens: Vec<_> = stdout.strip_suffix('\0').unwrap().split('\0').collect();
+
+    let config = Config::from_yaml(yaml).unwrap();
+    let builder = WrappedCommandBuilder::from_config(&config, "node").unwrap();
+    let mut expected = vec!["bwrap".to_string()];
+    expected.extend(builder.build_args().unwrap());
+    expected.extend(["node", "app.js", "--port 80"].map(String::from));
+    assert_eq!(tokens, expected);
+}
+
+#[test]
+fn test_command_show_shell() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            node:
+              bind:
+                - /srv/my app:/app
+        "},
+    );
+
+    let show = |shell: &str| {
+        let output = run_shwrap(
+            temp_dir.path(),
+            &["command", "show", "--shell", shell, "node", "it's"],
+        );
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let bash = show("bash");
+    assert!(bash.contains("--bind '/srv/my app' /app"));
+    assert!(bash.ends_with(" node 'it'\\''s'\n"));
+    let fish = show("fish");
+    assert!(fish.contains("--bind '/srv/my app' /app"));
+    assert!(fish.ends_with(" node 'it\\'s'\n"));
+}
+
+#[test]
+fn test_config_migrate() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            templates:
+              base:
+                ro_bind:
+                  - /usr
+            commands:
+              node:
+                extends: base
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["command", "list", "--simple"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "node\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("uses the old 'commands'/'templates' layout"));
+
+    let output = run_shwrap(temp_dir.path(), &["config", "migrate"]);
+    assert!(output.status.success());
+    let migrated = fs::read_to_string(temp_dir.path().join(".shwrap.yaml")).unwrap();
+    assert_eq!(
+        migrated,
+        indoc! {"
+            version: 1
+            base:
+              ro_bind:
+              - /usr
+              type: model
+            node:
+              extends: base
+        "}
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["command", "list", "--simple"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "node\n");
+    assert!(output.stderr.is_empty());
+
+    let output = run_shwrap(temp_dir.path(), &["config", "migrate"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("is already up to date\n"));
+}
+
+#[test]
+fn test_shell_hook_commands_from_config() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            version: 1
+            node: {}
+            python:
+              enabled: false
+        "},
+    );
+
+    let output = run_shwrap(
+        temp_dir.path(),
+        &["shell-hook", "get", "bash", "--commands-from-config"],
+    );
+    assert!(output.status.success());
+    let hook = String::from_utf8(output.stdout).unwrap();
+    assert!(hook.starts_with("typeset -g SHWRAP_FIXED_COMMANDS='node'\n"));
+    assert!(!hook.contains("python"));
+
+    let output = run_shwrap(
+        temp_dir.path(),
+        &["shell-hook", "get", "fish", "--commands-from-config"],
+    );
+    let hook = String::from_utf8(output.stdout).unwrap();
+    assert!(hook.starts_with("set -g SHWRAP_FIXED_COMMANDS 'node'\n"));
+
+    // The hook reloads the config on directory change by default
+    let output = run_shwrap(temp_dir.path(), &["shell-hook", "get", "bash"]);
+    let hook = String::from_utf8(output.stdout).unwrap();
+    assert!(!hook.contains("SHWRAP_FIXED_COMMANDS="));
+}
+
+#[test]
+fn test_config_init_list() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let output = run_shwrap(temp_dir.path(), &["config", "init", "--list"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "default\nnodejs\npython\nruby\ngo\nrust\n"
+    );
+    assert!(!temp_dir.path().join(".shwrap.yaml").exists());
+}
+
+#[test]
+fn test_config_init_from_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let starter = indoc! {"
+        # Team starter
+        version: 1
+        make:
+          ro_bind:
+            - /usr
+    "};
+    fs::write(temp_dir.path().join("starter.yaml"), starter).unwrap();
+    fs::write(temp_dir.path().join("broken.yaml"), "make: [").unwrap();
+
+    let output = run_shwrap(temp_dir.path(), &["config", "init", "-t", "broken.yaml"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("in \"broken.yaml\""), "{}", stderr);
+
+    let output = run_shwrap(temp_dir.path(), &["config", "init", "-t", "starter.yaml"]);
+    assert!(output.status.success());
+    let created = fs::read_to_string(temp_dir.path().join(".shwrap.yaml")).unwrap();
+    assert_eq!(created, starter);
+
+    let output = run_shwrap(temp_dir.path(), &["config", "init", "-t", "nope"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Unknown template: nope"));
+}
+
+#[test]
+fn test_config_init_interactive_without_terminal() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let output = run_shwrap(temp_dir.path(), &["config", "init", "--interactive"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("stdin is not a terminal, using the default template"));
+    let created = fs::read_to_string(temp_dir.path().join(".shwrap.yaml")).unwrap();
+    assert!(created.starts_with("# Default Shwrap configuration"));
+}
+
+#[test]
+fn test_relative_binds_resolved_against_config_dir() {
+    let temp_dir = TempDir::new().unwrap();
+    let project_dir = temp_dir.path().canonicalize().unwrap();
+    let sub_dir = project_dir.join("src").join("lib");
+    fs::create_dir_all(&sub_dir).unwrap();
+    write_local_config(
+        &project_dir,
+        indoc! {"
+            make:
+              ro_bind:
+                - ./scripts
+        "},
+    );
+
+    let output = run_shwrap(&sub_dir, &["--quiet", "command", "show", "make"]);
+    assert!(output.status.success());
+    let scripts = project_dir.join("scripts").display().to_string();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains(&format!("--ro-bind {} {}", scripts, scripts)),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_config_check_json() {
+    let temp_dir = TempDir::new().unwrap();
+    write_local_config(
+        temp_dir.path(),
+        indoc! {"
+            node:
+              share:
+                - netwrok
+            python:
+              extends: missing
+        "},
+    );
+
+    let output = run_shwrap(temp_dir.path(), &["config", "check", "--format", "json"]);
+    assert!(!output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["valid"], false);
+    assert_eq!(report["commands"][0]["name"], "node");
+    let errors = report["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0]["command"], "node");
+    assert_eq!(errors[0]["message"], "unknown namespace 'netwrok' in share");
+
+    write_local_config(temp_dir.path(), "node:\n  share: [user\n");
+    let output = run_shwrap(temp_dir.path(), &["config", "check", "--format", "json"]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["errors"][0]["command"], serde_json::Value::Null);
+
+    write_local_config(temp_dir.path(), "node:\n  ro_bind: [/usr]\n");
+    let output = run_shwrap(temp_dir.path(), &["config", "check", "--format", "json"]);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["valid"], true);
+    assert_eq!(report["errors"], serde_json::json!([]));
+    assert_eq!(report["commands"][0]["enabled"], true);
+}
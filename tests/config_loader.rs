@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use indoc::indoc;
+use shwrap::config::RoBind;
 use shwrap::config::loader::ConfigLoader;
 use std::env;
 use std::fs;
@@ -79,6 +80,73 @@ fn test_get_user_config_file() {
     assert!(result.is_ok());
 }
 
+#[test]
+fn test_get_user_config_file_honors_xdg_config_home() {
+    let _lock = DIR_MUTEX.lock().unwrap();
+
+    let xdg_dir = TempDir::new().unwrap();
+    let config_dir = xdg_dir.path().join("shwrap");
+    fs::create_dir_all(&config_dir).unwrap();
+    let config_path = config_dir.join(ConfigLoader::user_config_name());
+    fs::write(&config_path, "node: {}").unwrap();
+
+    let original_xdg = env::var("XDG_CONFIG_HOME").ok();
+    unsafe {
+        env::set_var("XDG_CONFIG_HOME", xdg_dir.path());
+    }
+
+    assert_eq!(ConfigLoader::get_user_config_dir(), config_dir);
+    let found = ConfigLoader::get_user_config_file().unwrap();
+    assert_eq!(found, Some(config_path));
+
+    // Nothing found under XDG_CONFIG_HOME
+    let empty_xdg_dir = TempDir::new().unwrap();
+    unsafe {
+        env::set_var("XDG_CONFIG_HOME", empty_xdg_dir.path());
+    }
+    let found = ConfigLoader::get_user_config_file().unwrap();
+    assert!(found.is_none());
+
+    unsafe {
+        if let Some(xdg) = original_xdg {
+            env::set_var("XDG_CONFIG_HOME", xdg);
+        } else {
+            env::remove_var("XDG_CONFIG_HOME");
+        }
+    }
+}
+
+#[test]
+fn test_get_user_config_dir_ignores_empty_xdg_config_home() {
+    let _lock = DIR_MUTEX.lock().unwrap();
+
+    let fake_home = TempDir::new().unwrap();
+    let original_xdg = env::var("XDG_CONFIG_HOME").ok();
+    let original_home = env::var("HOME").ok();
+    unsafe {
+        env::set_var("XDG_CONFIG_HOME", "");
+        env::set_var("HOME", fake_home.path());
+    }
+
+    assert_eq!(
+        ConfigLoader::get_user_config_dir(),
+        fake_home.path().join(".config").join("shwrap")
+    );
+
+    unsafe {
+        if let Some(xdg) = original_xdg {
+            env::set_var("XDG_CONFIG_HOME", xdg);
+        } else {
+            env::remove_var("XDG_CONFIG_HOME");
+        }
+        if let Some(home) = original_home {
+            env::set_var("HOME", home);
+        } else {
+            env::remove_var("HOME");
+        }
+    }
+}
+
 #[test]
 fn test_load_with_valid_config() {
     let _lock = DIR_MUTEX.lock().unwrap();
@@ -114,10 +182,14 @@ fn test_load_without_config() {
 
     let original_dir = env::current_dir().unwrap();
     let original_home = env::var("HOME").ok();
+    let original_xdg = env::var("XDG_CONFIG_HOME").ok();
+    let original_system = env::var("SHWRAP_SYSTEM_CONFIG_DIR").ok();
 
     // Set HOME to temp dir to avoid finding user config
     unsafe {
         env::set_var("HOME", temp_dir.path());
+        env::remove_var("XDG_CONFIG_HOME");
+        env::set_var("SHWRAP_SYSTEM_CONFIG_DIR", temp_dir.path());
     }
     env::set_current_dir(&temp_dir).unwrap();
 
@@ -133,6 +205,14 @@ fn test_load_without_config() {
         } else {
             env::remove_var("HOME");
         }
+        if let Some(xdg) = original_xdg {
+            env::set_var("XDG_CONFIG_HOME", xdg);
+        }
+        if let Some(system) = original_system {
+            env::set_var("SHWRAP_SYSTEM_CONFIG_DIR", system);
+        } else {
+            env::remove_var("SHWRAP_SYSTEM_CONFIG_DIR");
+        }
     }
 }
 
@@ -155,6 +235,160 @@ fn test_get_config_file_hierarchy_local_first() {
     env::set_current_dir(original_dir).unwrap();
 }
 
+#[test]
+fn test_get_config_file_hierarchy_system_fallback() {
+    let _lock = DIR_MUTEX.lock().unwrap();
+
+    let work_dir = TempDir::new().unwrap();
+    let fake_home = TempDir::new().unwrap();
+    let system_dir = TempDir::new().unwrap();
+
+    let original_dir = env::current_dir().unwrap();
+    let original_home = env::var("HOME").ok();
+    let original_xdg = env::var("XDG_CONFIG_HOME").ok();
+    let original_system = env::var("SHWRAP_SYSTEM_CONFIG_DIR").ok();
+    unsafe {
+        env::set_var("HOME", fake_home.path());
+        env::remove_var("XDG_CONFIG_HOME");
+        env::set_var("SHWRAP_SYSTEM_CONFIG_DIR", system_dir.path());
+    }
+    env::set_current_dir(&work_dir).unwrap();
+
+    // Only system config: system is used
+    let system_config_path = system_dir.path().join("default.yaml");
+    fs::write(&system_config_path, "node: {}").unwrap();
+    let found = ConfigLoader::get_config_file().unwrap();
+    assert_eq!(found, Some(system_config_path.clone()));
+    assert_eq!(
+        ConfigLoader::get_system_config_file().unwrap(),
+        Some(system_config_path)
+    );
+
+    // User config takes precedence over system config
+    let user_config_dir = fake_home.path().join(".config").join("shwrap");
+    fs::create_dir_all(&user_config_dir).unwrap();
+    let user_config_path = user_config_dir.join(ConfigLoader::user_config_name());
+    fs::write(&user_config_path, "node: {}").unwrap();
+    let found = ConfigLoader::get_config_file().unwrap();
+    assert_eq!(found, Some(user_config_path));
+
+    // Local config takes precedence over both
+    let local_config_path = work_dir.path().join(ConfigLoader::local_config_name());
+    fs::write(&local_config_path, "node: {}").unwrap();
+    let found = ConfigLoader::get_config_file().unwrap();
+    assert_eq!(found, Some(local_config_path));
+
+    env::set_current_dir(original_dir).unwrap();
+    unsafe {
+        if let Some(home) = original_home {
+            env::set_var("HOME", home);
+        } else {
+            env::remove_var("HOME");
+        }
+        if let Some(xdg) = original_xdg {
+            env::set_var("XDG_CONFIG_HOME", xdg);
+        }
+        if let Some(system) = original_system {
+            env::set_var("SHWRAP_SYSTEM_CONFIG_DIR", system);
+        } else {
+            env::remove_var("SHWRAP_SYSTEM_CONFIG_DIR");
+        }
+    }
+}
+
+#[test]
+fn test_load_merged_layers_hierarchy() {
+    let _lock = DIR_MUTEX.lock().unwrap();
+
+    let work_dir = TempDir::new().unwrap();
+    let fake_home = TempDir::new().unwrap();
+    let system_dir = TempDir::new().unwrap();
+
+    let user_config_dir = fake_home.path().join(".config").join("shwrap");
+    fs::create_dir_all(&user_config_dir).unwrap();
+    fs::write(
+        user_config_dir.join(ConfigLoader::user_config_name()),
+        indoc! {"
+            base:
+              type: model
+              share:
+                - user
+              ro_bind:
+                - /usr
+
+            node:
+              extends: base
+              env:
+                SOURCE: user_config
+
+            git:
+              share:
+                - network
+        "},
+    )
+    .unwrap();
+    fs::write(
+        work_dir.path().join(ConfigLoader::local_config_name()),
+        indoc! {"
+            node:
+              extends: base
+              share:
+                - network
+              env:
+                SOURCE: local_config
+        "},
+    )
+    .unwrap();
+
+    let original_dir = env::current_dir().unwrap();
+    let original_home = env::var("HOME").ok();
+    let original_xdg = env::var("XDG_CONFIG_HOME").ok();
+    let original_system = env::var("SHWRAP_SYSTEM_CONFIG_DIR").ok();
+    unsafe {
+        env::set_var("HOME", fake_home.path());
+        env::remove_var("XDG_CONFIG_HOME");
+        env::set_var("SHWRAP_SYSTEM_CONFIG_DIR", system_dir.path());
+    }
+    env::set_current_dir(&work_dir).unwrap();
+
+    let config = ConfigLoader::load_merged().unwrap().unwrap();
+
+    // Local command overrides the user one, but inherits the user-only template
+    let node_cmd = config.get_command("node").unwrap();
+    assert_eq!(
+        node_cmd.env.get("SOURCE"),
+        Some(&"local_config".to_string())
+    );
+    let merged = config.merge_with_template(node_cmd);
+    assert!(merged.share.contains(&"user".to_string()));
+    assert!(merged.share.contains(&"network".to_string()));
+    assert!(merged.ro_bind.contains(&RoBind::from("/usr")));
+
+    // User-only commands are still available
+    assert!(config.get_command("git").is_some());
+
+    // Non-merged loading only sees the local config
+    let local_only = ConfigLoader::load().unwrap().unwrap();
+    assert!(local_only.get_command("git").is_none());
+
+    env::set_current_dir(original_dir).unwrap();
+    unsafe {
+        if let Some(home) = original_home {
+            env::set_var("HOME", home);
+        } else {
+            env::remove_var("HOME");
+        }
+        if let Some(xdg) = original_xdg {
+            env::set_var("XDG_CONFIG_HOME", xdg);
+        }
+        if let Some(system) = original_system {
+            env::set_var("SHWRAP_SYSTEM_CONFIG_DIR", system);
+        } else {
+            env::remove_var("SHWRAP_SYSTEM_CONFIG_DIR");
+        }
+    }
+}
+
 #[test]
 fn test_get_config_file_walks_up_directories() {
     let _lock = DIR_MUTEX.lock().unwrap();
@@ -178,3 +412,149 @@ fn test_get_config_file_walks_up_directories() {
 
     env::set_current_dir(original_dir).unwrap();
 }
+
+#[test]
+fn test_get_config_file_uses_cache() {
+    let _lock = DIR_MUTEX.lock().unwrap();
+
+    let work_dir = TempDir::new().unwrap();
+    let other_dir = TempDir::new().unwrap();
+    let project_dir = work_dir.path().join("project");
+    let src_dir = project_dir.join("src");
+    fs::create_dir_all(&src_dir).unwrap();
+    let parent_config_path = work_dir.path().join(ConfigLoader::local_config_name());
+    let cached_config_path = other_dir.path().join("cached.yaml");
+    fs::write(&cached_config_path, "node: {}").unwrap();
+
+    let original_dir = env::current_dir().unwrap();
+    env::set_current_dir(&src_dir).unwrap();
+    let current_dir = env::current_dir().unwrap();
+
+    // Cache hit: the search is skipped
+    unsafe {
+        env::set_var("SHWRAP_CONFIG", &cached_config_path);
+        env::set_var("SHWRAP_CONFIG_CWD", &current_dir);
+    }
+    let found = ConfigLoader::get_config_file().unwrap();
+    assert_eq!(found, Some(cached_config_path.clone()));
+
+    // Stale cache (local config created since in an ancestor): full search
+    fs::write(&parent_config_path, "node: {}").unwrap();
+    let found = ConfigLoader::get_config_file().unwrap();
+    assert_eq!(found, Some(parent_config_path.clone()));
+
+    // Cache hit on a local config of an ancestor
+    unsafe {
+        env::set_var("SHWRAP_CONFIG", &parent_config_path);
+    }
+    let found = ConfigLoader::get_config_file().unwrap();
+    assert_eq!(found, Some(parent_config_path.clone()));
+
+    // Stale cache (local config created since between the current directory
+    // and the cached one): full search
+    for dir in [&current_dir, &project_dir] {
+        let local_config_path = dir.join(ConfigLoader::local_config_name());
+        fs::write(&local_config_path, "node: {}").unwrap();
+        let found = ConfigLoader::get_config_file().unwrap();
+        assert_eq!(found, Some(local_config_path.clone()));
+        fs::remove_file(&local_config_path).unwrap();
+    }
+
+    // Stale cache (resolved from another directory): full search
+    unsafe {
+        env::set_var("SHWRAP_CONFIG", &cached_config_path);
+        env::set_var("SHWRAP_CONFIG_CWD", other_dir.path());
+    }
+    let found = ConfigLoader::get_config_file().unwrap();
+    assert_eq!(found, Some(parent_config_path.clone()));
+
+    // Stale cache (file removed): full search
+    unsafe {
+        env::set_var("SHWRAP_CONFIG_CWD", &current_dir);
+    }
+    fs::remove_file(&cached_config_path).unwrap();
+    let found = ConfigLoader::get_config_file().unwrap();
+    assert_eq!(found, Some(parent_config_path));
+
+    env::set_current_dir(original_dir).unwrap();
+    unsafe {
+        env::remove_var("SHWRAP_CONFIG");
+        env::remove_var("SHWRAP_CONFIG_CWD");
+    }
+}
+
+#[test]
+fn test_find_all() {
+    let _lock = DIR_MUTEX.lock().unwrap();
+
+    let work_dir = TempDir::new().unwrap();
+    let fake_home = TempDir::new().unwrap();
+    let system_dir = TempDir::new().unwrap();
+
+    let sub_dir = work_dir.path().join("project");
+    fs::create_dir(&sub_dir).unwrap();
+    let parent_config_path = work_dir.path().join(ConfigLoader::local_config_name());
+    fs::write(&parent_config_path, "node: {}").unwrap();
+    let local_config_path = sub_dir.join(ConfigLoader::local_config_name());
+    fs::write(&local_config_path, "node: {}").unwrap();
+    let user_config_dir = fake_home.path().join(".config").join("shwrap");
+    fs::create_dir_all(&user_config_dir).unwrap();
+    let user_config_path = user_config_dir.join(ConfigLoader::user_config_name());
+    fs::write(&user_config_path, "node: {}").unwrap();
+
+    let original_dir = env::current_dir().unwrap();
+    let original_home = env::var("HOME").ok();
+    let original_xdg = env::var("XDG_CONFIG_HOME").ok();
+    let original_system = env::var("SHWRAP_SYSTEM_CONFIG_DIR").ok();
+    unsafe {
+        env::set_var("HOME", fake_home.path());
+        env::remove_var("XDG_CONFIG_HOME");
+        env::set_var("SHWRAP_SYSTEM_CONFIG_DIR", system_dir.path());
+    }
+    env::set_current_dir(&sub_dir).unwrap();
+
+    let found = ConfigLoader::find_all().unwrap();
+    assert_eq!(
+        found,
+        vec![
+            local_config_path.clone(),
+            parent_config_path,
+            user_config_path
+        ]
+    );
+    assert_eq!(
+        ConfigLoader::get_config_file().unwrap(),
+        Some(local_config_path)
+    );
+
+    env::set_current_dir(original_dir).unwrap();
+    unsafe {
+        if let Some(home) = original_home {
+            env::set_var("HOME", home);
+        } else {
+            env::remove_var("HOME");
+        }
+        if let Some(xdg) = original_xdg {
+            env::set_var("XDG_CONFIG_HOME", xdg);
+        }
+        if let Some(system) = original_system {
+            env::set_var("SHWRAP_SYSTEM_CONFIG_DIR", system);
+        } else {
+            env::remove_var("SHWRAP_SYSTEM_CONFIG_DIR");
+        }
+    }
+}
+
+#[test]
+fn test_load_from_str_with_source() {
+    let yaml = indoc! {"
+        commands:
+          npm:
+            enabled: true
+    "};
+    let config = ConfigLoader::load_from_str_with_source(yaml, "embedded").unwrap();
+    assert!(config.get_command("npm").is_some());
+
+    let err = ConfigLoader::load_from_str_with_source("commands: [", "my-plugin.yaml").unwrap_err();
+    assert!(format!("{:#}", err).contains("in my-plugin.yaml:"));
+}
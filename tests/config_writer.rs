@@ -0,0 +1,123 @@
+// Copyright (C) 2025 Pierre Le Gall
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use indoc::indoc;
+use shwrap::config::writer::ConfigWriter;
+use shwrap::config::{Config, RoBind};
+use std::fs;
+use tempfile::TempDir;
+
+fn write_config(dir: &TempDir, yaml: &str) -> std::path::PathBuf {
+    let path = dir.path().join(".shwrap.yaml");
+    fs::write(&path, yaml).unwrap();
+    path
+}
+
+#[test]
+fn test_set_enabled_flips_flag() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = write_config(
+        &temp_dir,
+        indoc! {"
+            base:
+              type: model
+              share:
+                - user
+
+            node:
+              extends: base
+              bind:
+                - ~/.npm:~/.npm
+
+            python:
+        "},
+    );
+
+    ConfigWriter::set_enabled(&path, "node", false).unwrap();
+    ConfigWriter::set_enabled(&path, "python", false).unwrap();
+
+    let config = Config::from_file(&path).unwrap();
+    let node_cmd = config.get_command("node").unwrap();
+    assert!(!node_cmd.is_enabled());
+    // The rest of the entry survives the rewrite
+    assert_eq!(node_cmd.extends, Some("base".to_string()));
+    assert_eq!(node_cmd.bind, vec!["~/.npm:~/.npm"]);
+    assert!(!config.get_command("python").unwrap().is_enabled());
+    assert_eq!(config.get_models().len(), 1);
+
+    ConfigWriter::set_enabled(&path, "node", true).unwrap();
+    let config = Config::from_file(&path).unwrap();
+    assert!(config.get_command("node").unwrap().is_enabled());
+}
+
+#[test]
+fn test_set_enabled_unknown_command() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = write_config(
+        &temp_dir,
+        indoc! {"
+            base:
+              type: model
+            node:
+              enabled: true
+        "},
+    );
+
+    assert!(ConfigWriter::set_enabled(&path, "ruby", false).is_err());
+    // Models can't be toggled
+    assert!(ConfigWriter::set_enabled(&path, "base", false).is_err());
+}
+
+#[test]
+fn test_add_command() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = write_config(
+        &temp_dir,
+        indoc! {"
+            base:
+              type: model
+              ro_bind:
+                - /usr
+        "},
+    );
+
+    ConfigWriter::add_command(
+        &path,
+        "node",
+        Some("base"),
+        &["network".to_string(), "user".to_string()],
+    )
+    .unwrap();
+    ConfigWriter::add_command(&path, "python", None, &[]).unwrap();
+
+    let config = Config::from_file(&path).unwrap();
+    let node_cmd = config.get_command("node").unwrap();
+    assert!(node_cmd.is_enabled());
+    assert_eq!(node_cmd.extends, Some("base".to_string()));
+    assert_eq!(node_cmd.share, vec!["network", "user"]);
+    assert_eq!(
+        config.merge_with_template(node_cmd).ro_bind,
+        vec![RoBind::from("/usr")]
+    );
+    assert!(config.get_command("python").is_some());
+}
+
+#[test]
+fn test_add_command_already_exists() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = write_config(
+        &temp_dir,
+        indoc! {"
+            node:
+              share:
+                - user
+        "},
+    );
+
+    let err = ConfigWriter::add_command(&path, "node", None, &[]).unwrap_err();
+    assert_eq!(err.to_string(), "An entry named 'node' already exists");
+
+    // The existing entry is left untouched
+    let config = Config::from_file(&path).unwrap();
+    assert_eq!(config.get_command("node").unwrap().share, vec!["user"]);
+}
@@ -3,7 +3,7 @@
 
 use indoc::indoc;
 use shwrap::config::loader::ConfigLoader;
-use shwrap::config::EntryType;
+use shwrap::config::{Enabled, EntryType, RoBind};
 use std::env;
 use std::fs;
 use tempfile::TempDir;
@@ -42,18 +42,18 @@ fn test_full_config_loading_and_execution() {
 
     // Verify node command
     let node_cmd = config.get_command("node").unwrap();
-    assert!(node_cmd.enabled);
+    assert!(node_cmd.is_enabled());
     assert_eq!(node_cmd.extends, Some("base".to_string()));
 
     // Verify merging with base
     let merged = config.merge_with_base(node_cmd);
     assert!(merged.share.contains(&"user".to_string()));
-    assert!(merged.ro_bind.contains(&"/usr".to_string()));
+    assert!(merged.ro_bind.contains(&RoBind::from("/usr")));
     assert_eq!(merged.env.get("NODE_ENV"), Some(&"production".to_string()));
 
     // Verify python command is disabled
     let python_cmd = config.get_command("python").unwrap();
-    assert!(!python_cmd.enabled);
+    assert!(!python_cmd.is_enabled());
 }
 
 #[test]
@@ -64,20 +64,21 @@ fn test_bwrap_builder_integration() {
 
     let mut config = Entry {
         entry_type: EntryType::Command,
-        enabled: true,
+        enabled: Enabled::Always(true),
         extends: None,
         share: vec![],
         bind: vec!["/tmp:/tmp".to_string()],
-        ro_bind: vec!["/usr".to_string()],
+        ro_bind: vec!["/usr".into()],
         dev_bind: vec![],
         tmpfs: vec!["/var/tmp".to_string()],
         env: HashMap::new(),
         unset_env: vec![],
+        ..Default::default()
     };
     config.env.insert("TEST".to_string(), "value".to_string());
 
     let builder = WrappedCommandBuilder::new(config);
-    let args = builder.build_args();
+    let args = builder.build_args().unwrap();
 
     // All namespaces unshared by default
     assert!(args.contains(&"--unshare-net".to_string()));
@@ -90,7 +91,7 @@ fn test_bwrap_builder_integration() {
     assert!(args.contains(&"value".to_string()));
 
     // Test show command
-    let cmd_line = builder.show("echo", &["hello".to_string()]);
+    let cmd_line = builder.show("echo", &["hello".to_string()]).unwrap();
     assert!(cmd_line.starts_with("bwrap"));
     assert!(cmd_line.contains("echo"));
     assert!(cmd_line.contains("hello"));
@@ -130,7 +131,7 @@ fn test_config_with_all_features() {
     let merged = config.merge_with_base(test_cmd);
 
     // Verify all fields are populated correctly
-    assert!(merged.enabled);
+    assert!(merged.is_enabled());
     assert_eq!(merged.share.len(), 1);
     assert_eq!(merged.ro_bind.len(), 2);
     assert_eq!(merged.bind.len(), 1);
@@ -142,7 +143,7 @@ fn test_config_with_all_features() {
     // Build and verify bwrap args
     use shwrap::bwrap::WrappedCommandBuilder;
     let builder = WrappedCommandBuilder::new(merged);
-    let args = builder.build_args();
+    let args = builder.build_args().unwrap();
 
     // User is shared, so no --unshare-user
     assert!(!args.contains(&"--unshare-user".to_string()));
@@ -178,15 +179,15 @@ fn test_multiple_commands_in_config() {
 
     // Test each command
     let node = config.get_command("node").unwrap();
-    assert!(node.enabled);
+    assert!(node.is_enabled());
     assert_eq!(node.share, vec!["user", "network"]);
 
     let python = config.get_command("python").unwrap();
-    assert!(python.enabled);
+    assert!(python.is_enabled());
     assert_eq!(python.share, vec!["user"]);
 
     let ruby = config.get_command("ruby").unwrap();
-    assert!(!ruby.enabled);
+    assert!(!ruby.is_enabled());
 }
 
 #[test]
@@ -214,19 +215,22 @@ fn test_command_show_formatting() {
 
     let config = Entry {
         entry_type: EntryType::Command,
-        enabled: true,
+        enabled: Enabled::Always(true),
         extends: None,
         share: vec![],
         bind: vec![],
-        ro_bind: vec!["/usr".to_string()],
+        ro_bind: vec!["/usr".into()],
         dev_bind: vec![],
         tmpfs: vec![],
         env: HashMap::new(),
         unset_env: vec![],
+        ..Default::default()
     };
 
     let builder = WrappedCommandBuilder::new(config);
-    let cmd = builder.show("ls", &["-la".to_string(), "/tmp".to_string()]);
+    let cmd = builder
+        .show("ls", &["-la".to_string(), "/tmp".to_string()])
+        .unwrap();
 
     // Verify command format
     let parts: Vec<&str> = cmd.split_whitespace().collect();
@@ -301,7 +305,7 @@ fn test_custom_template_name() {
     let python = config.get_command("python").unwrap();
     let merged_python = config.merge_with_template(python);
     assert_eq!(merged_python.share, vec!["user"]);
-    assert_eq!(merged_python.ro_bind, vec!["/usr"]);
+    assert_eq!(merged_python.ro_bind, vec![RoBind::from("/usr")]);
 }
 
 #[test]
@@ -320,7 +324,7 @@ fn test_unshare_all_by_default_integration() {
 
     let isolated_cmd = config.get_command("isolated").unwrap();
     let builder = WrappedCommandBuilder::new(isolated_cmd);
-    let cmd_line = builder.show("echo", &["test".to_string()]);
+    let cmd_line = builder.show("echo", &["test".to_string()]).unwrap();
 
     // All namespaces should be unshared
     assert!(cmd_line.contains("--unshare-user"));
@@ -350,7 +354,7 @@ fn test_share_specific_namespaces_integration() {
 
     let network_cmd = config.get_command("network_enabled").unwrap();
     let builder = WrappedCommandBuilder::new(network_cmd);
-    let cmd_line = builder.show("echo", &["test".to_string()]);
+    let cmd_line = builder.show("echo", &["test".to_string()]).unwrap();
 
     // User and network should NOT be unshared
     assert!(!cmd_line.contains("--unshare-user"));
@@ -383,7 +387,7 @@ fn test_share_multiple_namespaces_integration() {
 
     let relaxed_cmd = config.get_command("relaxed").unwrap();
     let builder = WrappedCommandBuilder::new(relaxed_cmd);
-    let cmd_line = builder.show("echo", &["test".to_string()]);
+    let cmd_line = builder.show("echo", &["test".to_string()]).unwrap();
 
     // User, network, and ipc should NOT be unshared
     assert!(!cmd_line.contains("--unshare-user"));
@@ -419,7 +423,7 @@ fn test_share_all_namespaces_integration() {
 
     let no_isolation_cmd = config.get_command("no_isolation").unwrap();
     let builder = WrappedCommandBuilder::new(no_isolation_cmd);
-    let cmd_line = builder.show("echo", &["test".to_string()]);
+    let cmd_line = builder.show("echo", &["test".to_string()]).unwrap();
 
     // No namespaces should be unshared
     assert!(!cmd_line.contains("--unshare-user"));
@@ -455,7 +459,7 @@ fn test_template_with_share_inheritance() {
     let app_cmd = config.get_command("app").unwrap();
     let merged = config.merge_with_template(app_cmd);
     let builder = WrappedCommandBuilder::new(merged);
-    let cmd_line = builder.show("echo", &["test".to_string()]);
+    let cmd_line = builder.show("echo", &["test".to_string()]).unwrap();
 
     // User and network should NOT be unshared (inherited + added)
     assert!(!cmd_line.contains("--unshare-user"));
@@ -504,11 +508,13 @@ fn test_user_config_loaded_when_no_local_config() {
 
     // Save original HOME and current directory
     let original_home = env::var("HOME").ok();
+    let original_xdg = env::var("XDG_CONFIG_HOME").ok();
     let original_dir = env::current_dir().unwrap();
 
     // Set fake HOME and change to work directory
     unsafe {
         env::set_var("HOME", fake_home.path());
+        env::remove_var("XDG_CONFIG_HOME");
     }
     env::set_current_dir(work_dir.path()).unwrap();
 
@@ -526,15 +532,15 @@ fn test_user_config_loaded_when_no_local_config() {
     assert!(git_cmd.is_some());
 
     let git_cmd = git_cmd.unwrap();
-    assert!(git_cmd.enabled);
+    assert!(git_cmd.is_enabled());
     assert_eq!(git_cmd.extends, Some("base".to_string()));
 
     // Verify merging with base works
     let merged = config.merge_with_base(git_cmd);
     assert!(merged.share.contains(&"user".to_string()));
     assert!(merged.share.contains(&"network".to_string()));
-    assert!(merged.ro_bind.contains(&"/usr".to_string()));
-    assert!(merged.ro_bind.contains(&"/lib".to_string()));
+    assert!(merged.ro_bind.contains(&RoBind::from("/usr")));
+    assert!(merged.ro_bind.contains(&RoBind::from("/lib")));
     assert_eq!(
         merged.env.get("GIT_AUTHOR_NAME"),
         Some(&"TestUser".to_string())
@@ -547,6 +553,9 @@ fn test_user_config_loaded_when_no_local_config() {
         } else {
             env::remove_var("HOME");
         }
+        if let Some(xdg) = original_xdg {
+            env::set_var("XDG_CONFIG_HOME", xdg);
+        }
     }
     env::set_current_dir(original_dir).unwrap();
 }
@@ -623,3 +632,44 @@ fn test_local_config_takes_precedence_over_user_config() {
     }
     env::set_current_dir(original_dir).unwrap();
 }
+
+#[test]
+fn test_nested_and_flat_layouts_share_config_type() {
+    // The crate root re-exports the same config types
+    let nested: shwrap::Config = shwrap::Config::from_yaml(indoc! {"
+        templates:
+          base:
+            share:
+              - user
+        commands:
+          node:
+            extends: base
+            bind:
+              - /tmp:/tmp
+    "})
+    .unwrap();
+    let flat: shwrap::config::Config = shwrap::config::Config::from_yaml(indoc! {"
+        version: 1
+        base:
+          type: model
+          share:
+            - user
+        node:
+          extends: base
+          bind:
+            - /tmp:/tmp
+    "})
+    .unwrap();
+
+    for config in [&nested, &flat] {
+        let node_cmd: shwrap::Entry = config.get_command("node").unwrap();
+        let merged = config.merge_with_template(node_cmd);
+        assert_eq!(merged.share, vec!["user"]);
+        assert_eq!(merged.bind, vec!["/tmp:/tmp"]);
+        assert_eq!(config.get_models()["base"].entry_type, EntryType::Model);
+    }
+    assert_eq!(
+        serde_yaml::to_value(&nested.entries).unwrap(),
+        serde_yaml::to_value(&flat.entries).unwrap()
+    );
+}